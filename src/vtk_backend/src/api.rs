@@ -1,17 +1,198 @@
+mod account_deletion;
+mod activity;
+mod admin;
+mod burn_after_reading;
 mod delete_file;
 mod download_file;
+mod encrypted_metadata;
+mod file_requests;
+mod file_tags;
+mod file_versions;
+mod folders;
+mod groups;
+mod import_account;
+mod linked_principals;
+mod list_files;
+mod quotas;
 mod upload_file_atomic;
 mod upload_file_continue;
 mod register_file;
+mod share_links;
+mod siwe;
+mod sui_link;
+mod takeout;
+mod trash;
+mod user_directory;
 mod user_management;
 
 // use crate::{FileContent, State, UploadFileContinueRequest};
+pub use account_deletion::{
+    cancel_account_deletion,
+    get_account_deletion_status,
+    process_account_deletions,
+    request_account_deletion,
+    schedule_account_deletions,
+    AccountDeletionError,
+    AccountDeletionStatus,
+};
+pub use activity::{get_login_history, record_login, ActivityError, LoginHistory};
+pub use admin::{
+    add_admin,
+    admin_remove_file,
+    get_canister_stats,
+    list_admins,
+    list_suspensions,
+    reactivate_user,
+    remove_admin,
+    suspend_user,
+    AdminError,
+    CanisterStats,
+};
 pub use delete_file::delete_file;
 pub use download_file::download_file;
+pub use encrypted_metadata::{set_encrypted_metadata, EncryptedMetadataError};
+pub use file_requests::{
+    get_alias_info,
+    request_file,
+    upload_file_to_alias,
+    AliasInfo,
+    RequestFileRequest,
+    RequestFileResponse,
+    UploadFileToAliasRequest,
+};
+pub use file_tags::{update_file_metadata, TagError, UpdateFileMetadataRequest};
+pub use file_versions::{
+    download_version,
+    list_versions,
+    prune_versions,
+    restore_version,
+    upload_new_version,
+    FileVersionInfo,
+    PruneVersionsRequest,
+    UploadNewVersionRequest,
+    VersionError,
+};
+pub use folders::{
+    copy_file,
+    create_folder,
+    delete_folder,
+    list_folder,
+    move_file,
+    rename_file,
+    rename_folder,
+    resolve_path,
+    CopyFileRequest,
+    CreateFolderRequest,
+    FolderError,
+    FolderInfo,
+    FolderListing,
+    ListFolderRequest,
+    PathEntry,
+};
+pub use groups::{
+    accept_group_invite,
+    assign_file_to_group,
+    create_group,
+    decline_group_invite,
+    group_upload_derivation_id,
+    invite_group_member,
+    list_group_invites,
+    list_groups,
+    remove_group_member,
+    set_group_member_role,
+    vetkey_derivation_id,
+    GroupError,
+    GroupInfo,
+    GroupInviteInfo,
+    GroupMemberInfo,
+};
+pub use import_account::{
+    import_account,
+    ImportAccountRequest,
+    ImportConflict,
+    ImportError,
+    ImportReport,
+    ImportedFile,
+};
+pub use linked_principals::{
+    confirm_principal_link,
+    list_linked_principals,
+    request_principal_link,
+    unlink_principal,
+    LinkError,
+    LinkedPrincipals,
+};
+pub use list_files::{
+    list_files,
+    FileCursor,
+    FileSortField,
+    FileSortValue,
+    FileStatusFilter,
+    ListFilesError,
+    ListFilesRequest,
+    ListFilesResponse,
+};
+pub use quotas::{
+    check_storage_usage,
+    get_quota,
+    set_default_quota,
+    set_plan_quota,
+    set_user_plan,
+    QuotaError,
+    QuotaInfo,
+    StorageUsageReport,
+    UsageMismatch,
+};
 pub use upload_file_atomic::{upload_file_atomic, UploadFileAtomicRequest};
 pub use upload_file_continue::upload_file_continue;
 pub use crate::api::delete_file::DeleteFileResult;
 pub use register_file::{register_file, RegisterFileRequest, RegisterFileResponse};
+pub use share_links::{
+    active_share_links,
+    create_share_link,
+    download_by_link,
+    revoke_link,
+    CreateShareLinkRequest,
+    ShareLinkError,
+};
+pub use siwe::{
+    link_eth_address,
+    prepare_eth_link,
+    resolve_eth_address,
+    unlink_eth_address,
+    SiweChallengeRequest,
+    WalletLinkError,
+};
+pub use sui_link::{link_sui_address, prepare_sui_link, resolve_sui_address, unlink_sui_address};
+pub use takeout::{
+    decode_manifest,
+    download_takeout,
+    export_account,
+    get_takeout_info,
+    TakeoutError,
+    TakeoutFile,
+    TakeoutGroup,
+    TakeoutInfo,
+    TakeoutManifest,
+    TakeoutShareLink,
+};
+pub use trash::{
+    empty_trash,
+    list_trash,
+    purge_expired_trash,
+    restore_file,
+    schedule_trash_purge,
+    set_trash_retention,
+    TrashEntry,
+    TrashError,
+};
+pub use user_directory::{
+    resolve_username,
+    search_users,
+    SearchUsersRequest,
+    SearchUsersResponse,
+    UserDirectoryError,
+};
 pub use user_management::{
     create_user_profile,
     get_user_profile,
@@ -0,0 +1,256 @@
+use crate::api::delete_file::purge_file;
+use crate::api::groups::leave_all_groups;
+use crate::{with_state_mut, DeletionReceipt, PendingDeletion, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long an account marked for deletion can still be recovered: 7 days.
+pub const ACCOUNT_DELETION_GRACE_PERIOD: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+/// How often accounts marked for deletion are checked for expiry.
+pub const ACCOUNT_DELETION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccountDeletionError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The caller has no profile, or no deletion is scheduled.
+    #[serde(rename = "not_found")]
+    NotFound,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccountDeletionStatus {
+    #[serde(rename = "active")]
+    Active,
+    #[serde(rename = "scheduled")]
+    Scheduled(PendingDeletion),
+    #[serde(rename = "deleted")]
+    Deleted(DeletionReceipt),
+}
+
+/// Marks the caller's account for deletion once the grace period is over. Requesting it
+/// again keeps the original date.
+pub fn request_account_deletion(caller: Principal, state: &mut State) -> Result<PendingDeletion, AccountDeletionError> {
+    if caller == Principal::anonymous() {
+        return Err(AccountDeletionError::NotAuthenticated);
+    }
+    if !state.user_profiles.contains_key(&caller) {
+        return Err(AccountDeletionError::NotFound);
+    }
+    let now = crate::get_time();
+    let pending = state.pending_deletions.entry(caller).or_insert(PendingDeletion {
+        requested_at: now,
+        delete_at: now.saturating_add(ACCOUNT_DELETION_GRACE_PERIOD),
+    });
+    Ok(pending.clone())
+}
+
+pub fn cancel_account_deletion(caller: Principal, state: &mut State) -> Result<(), AccountDeletionError> {
+    if caller == Principal::anonymous() {
+        return Err(AccountDeletionError::NotAuthenticated);
+    }
+    state
+        .pending_deletions
+        .remove(&caller)
+        .map(|_| ())
+        .ok_or(AccountDeletionError::NotFound)
+}
+
+pub fn get_account_deletion_status(caller: Principal, state: &State) -> Result<AccountDeletionStatus, AccountDeletionError> {
+    if caller == Principal::anonymous() {
+        return Err(AccountDeletionError::NotAuthenticated);
+    }
+    if let Some(pending) = state.pending_deletions.get(&caller) {
+        return Ok(AccountDeletionStatus::Scheduled(pending.clone()));
+    }
+    if let Some(receipt) = state.deletion_receipts.get(&caller) {
+        return Ok(AccountDeletionStatus::Deleted(receipt.clone()));
+    }
+    Ok(AccountDeletionStatus::Active)
+}
+
+/// Deletes every account whose grace period is over and returns their receipts.
+pub fn process_account_deletions(state: &mut State, now: u64) -> Vec<DeletionReceipt> {
+    let expired: Vec<(Principal, PendingDeletion)> = state
+        .pending_deletions
+        .iter()
+        .filter(|(_, pending)| pending.delete_at <= now)
+        .map(|(&principal, pending)| (principal, pending.clone()))
+        .collect();
+    expired
+        .into_iter()
+        .map(|(principal, pending)| {
+            state.pending_deletions.remove(&principal);
+            delete_account(state, principal, pending.requested_at, now)
+        })
+        .collect()
+}
+
+/// Periodically deletes accounts whose grace period is over. Must be called from `init` and
+/// `post_upgrade`.
+pub fn schedule_account_deletions() {
+    ic_cdk_timers::set_timer_interval(ACCOUNT_DELETION_INTERVAL, || {
+        let receipts = with_state_mut(|s| process_account_deletions(s, crate::get_time()));
+        if !receipts.is_empty() {
+            ic_cdk::println!("deleted {} accounts", receipts.len());
+        }
+    });
+}
+
+/// Deletes the files, share links, group memberships, folders and profile of a user.
+fn delete_account(state: &mut State, principal: Principal, requested_at: u64, now: u64) -> DeletionReceipt {
+    let bytes_used = state.storage_usage.get(&principal).map_or(0, |usage| usage.bytes);
+    let share_links_revoked = state.share_links.values().filter(|link| link.owner == principal).count() as u64;
+
+    // Purging a file also revokes its share links and removes it from groups and folders.
+    let file_ids: Vec<u64> = state
+        .file_owners
+        .remove(&principal)
+        .into_iter()
+        .chain(state.user_trash.remove(&principal))
+        .flatten()
+        .collect();
+    for &file_id in &file_ids {
+        purge_file(state, file_id);
+    }
+    let bytes_freed = bytes_used - state.storage_usage.get(&principal).map_or(0, |usage| usage.bytes);
+
+    let groups_left = leave_all_groups(state, principal);
+    for folder_id in state.user_folders.remove(&principal).unwrap_or_default() {
+        state.folders.remove(&folder_id);
+    }
+
+    state.storage_usage.remove(&principal);
+    state.user_plans.remove(&principal);
+    state.takeouts.remove(&principal);
+    state.suspensions.remove(&principal);
+    state.login_history.remove(&principal);
+    crate::api::linked_principals::remove_links(state, principal);
+    crate::api::siwe::remove_eth_address(state, principal);
+    crate::api::sui_link::remove_sui_address(state, principal);
+    let username = state.user_profiles.remove(&principal).map(|profile| {
+        state.username_to_principal.remove(&profile.username);
+        state.user_count -= 1;
+        profile.username
+    });
+
+    let receipt = DeletionReceipt {
+        principal,
+        username,
+        requested_at,
+        deleted_at: now,
+        files_deleted: file_ids.len() as u64,
+        bytes_freed,
+        share_links_revoked,
+        groups_left,
+    };
+    state.deletion_receipts.insert(principal, receipt.clone());
+    receipt
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        accept_group_invite, assign_file_to_group, create_group, create_share_link, create_user_profile,
+        delete_user_profile, invite_group_member, upload_file_atomic, CreateShareLinkRequest, UploadFileAtomicRequest,
+    };
+    use crate::{CreateUserRequest, GroupRole, UserResponse};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn create_user(state: &mut State, principal: Principal, username: &str) {
+        let request = CreateUserRequest {
+            username: username.to_string(),
+            display_name: None,
+            email: None,
+        };
+        assert!(matches!(create_user_profile(principal, request, state), UserResponse::Ok(_)));
+    }
+
+    fn upload(state: &mut State, principal: Principal) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: "a.txt".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "txt".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(principal, request, state).unwrap()
+    }
+
+    #[test]
+    fn deletion_waits_for_the_grace_period_and_can_be_cancelled() {
+        let mut state = State::seeded();
+        create_user(&mut state, owner(), "alice");
+
+        assert!(matches!(delete_user_profile(owner(), &mut state), UserResponse::Ok(_)));
+        let pending = request_account_deletion(owner(), &mut state).unwrap();
+        assert_eq!(pending.delete_at, crate::get_time() + ACCOUNT_DELETION_GRACE_PERIOD);
+
+        assert!(process_account_deletions(&mut state, pending.delete_at - 1).is_empty());
+        cancel_account_deletion(owner(), &mut state).unwrap();
+        assert!(process_account_deletions(&mut state, pending.delete_at).is_empty());
+        assert_eq!(get_account_deletion_status(owner(), &state), Ok(AccountDeletionStatus::Active));
+        assert_eq!(cancel_account_deletion(owner(), &mut state), Err(AccountDeletionError::NotFound));
+    }
+
+    #[test]
+    fn deletion_removes_files_shares_groups_and_username() {
+        let mut state = State::seeded();
+        create_user(&mut state, owner(), "alice");
+        create_user(&mut state, other(), "bob");
+        let file_id = upload(&mut state, owner());
+        upload(&mut state, other());
+
+        let request = CreateShareLinkRequest {
+            file_id,
+            expires_at: None,
+            max_downloads: None,
+            password: None,
+        };
+        create_share_link(owner(), request, &mut state).unwrap();
+        let group_id = create_group(owner(), "team".to_string(), &mut state).unwrap().group_id;
+        invite_group_member(owner(), group_id, "bob".to_string(), GroupRole::Viewer, &mut state).unwrap();
+        accept_group_invite(other(), group_id, &mut state).unwrap();
+        assign_file_to_group(owner(), file_id, Some(group_id), &mut state).unwrap();
+
+        let pending = request_account_deletion(owner(), &mut state).unwrap();
+        let receipts = process_account_deletions(&mut state, pending.delete_at);
+        assert_eq!(receipts.len(), 1);
+        let receipt = &receipts[0];
+        assert_eq!(receipt.username.as_deref(), Some("alice"));
+        assert_eq!(
+            (receipt.files_deleted, receipt.bytes_freed, receipt.share_links_revoked, receipt.groups_left),
+            (1, 3, 1, 1)
+        );
+
+        assert!(!state.file_data.contains_key(&file_id));
+        assert!(state.share_links.is_empty());
+        assert!(!state.user_profiles.contains_key(&owner()));
+        assert!(!state.username_to_principal.contains_key("alice"));
+        // The group lives on with its remaining member as owner.
+        assert_eq!(state.groups[&group_id].members.get(&other()), Some(&GroupRole::Owner));
+        assert!(state.group_files[&group_id].is_empty());
+        // Other users' files are untouched.
+        assert_eq!(state.file_owners[&other()].len(), 1);
+        assert_eq!(state.total_storage_used, 3);
+
+        assert_eq!(
+            get_account_deletion_status(owner(), &state),
+            Ok(AccountDeletionStatus::Deleted(receipt.clone()))
+        );
+        // The username is free again.
+        create_user(&mut state, Principal::from_slice(&[1, 2, 3]), "alice");
+    }
+}
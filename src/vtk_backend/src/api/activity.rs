@@ -0,0 +1,95 @@
+use crate::State;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// How many sign-ins are kept per principal. Older ones are dropped.
+pub const MAX_LOGIN_HISTORY: usize = 50;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ActivityError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LoginHistory {
+    pub principal: Principal,
+    /// The caller's most recent sign-in, if any.
+    pub last_login: Option<u64>,
+    /// Recent sign-ins, most recent first.
+    pub logins: Vec<u64>,
+}
+
+/// Records that the caller signed in, and updates the `last_login` of their account's profile.
+/// Frontends call this once per session, after authenticating. Returns the recorded time.
+pub fn record_login(caller: Principal, state: &mut State) -> Result<u64, ActivityError> {
+    if caller == Principal::anonymous() {
+        return Err(ActivityError::NotAuthenticated);
+    }
+    let now = crate::get_time();
+    let logins = state.login_history.entry(caller).or_default();
+    logins.push_front(now);
+    logins.truncate(MAX_LOGIN_HISTORY);
+    let account = state.account_of(caller);
+    if let Some(profile) = state.user_profiles.get_mut(&account) {
+        profile.last_login = now;
+    }
+    Ok(now)
+}
+
+/// Lists when the caller signed in with their principal.
+pub fn get_login_history(caller: Principal, state: &State) -> Result<LoginHistory, ActivityError> {
+    if caller == Principal::anonymous() {
+        return Err(ActivityError::NotAuthenticated);
+    }
+    let logins: Vec<u64> = state.login_history.get(&caller).into_iter().flatten().copied().collect();
+    Ok(LoginHistory {
+        principal: caller,
+        last_login: logins.first().copied(),
+        logins,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{create_user_profile, get_user_stats};
+    use crate::{CreateUserRequest, UserResponse};
+
+    fn caller() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    #[test]
+    fn logins_are_recorded_and_bounded() {
+        let mut state = State::default();
+        let request = CreateUserRequest {
+            username: "alice".to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(caller(), request, &mut state);
+        state.user_profiles.get_mut(&caller()).unwrap().last_login = 0;
+
+        assert_eq!(record_login(caller(), &mut state), Ok(crate::get_time()));
+        assert!(matches!(get_user_stats(caller(), &state), UserResponse::Ok(profile) if profile.last_login == crate::get_time()));
+
+        for _ in 0..MAX_LOGIN_HISTORY {
+            record_login(caller(), &mut state).unwrap();
+        }
+        let history = get_login_history(caller(), &state).unwrap();
+        assert_eq!(history.principal, caller());
+        assert_eq!(history.last_login, Some(crate::get_time()));
+        assert_eq!(history.logins.len(), MAX_LOGIN_HISTORY);
+    }
+
+    #[test]
+    fn history_is_per_principal() {
+        let mut state = State::default();
+        record_login(caller(), &mut state).unwrap();
+        let other = Principal::from_slice(&[1, 2, 3]);
+        let history = get_login_history(other, &state).unwrap();
+        assert_eq!((history.last_login, history.logins.len()), (None, 0));
+        assert_eq!(record_login(Principal::anonymous(), &mut state), Err(ActivityError::NotAuthenticated));
+    }
+}
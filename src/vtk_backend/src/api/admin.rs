@@ -0,0 +1,356 @@
+use crate::api::delete_file::purge_file;
+use crate::{State, StorageProvider, Suspension};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AdminError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The caller is not an admin.
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "not_found")]
+    NotFound,
+    /// The anonymous principal cannot be an admin, and suspensions need a reason.
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CanisterStats {
+    pub user_count: u64,
+    pub admin_count: u64,
+    pub file_count: u64,
+    /// Files whose content is stored on Walrus rather than in the canister.
+    pub walrus_file_count: u64,
+    pub trashed_file_count: u64,
+    /// Bytes stored across all users.
+    pub total_storage_used: u64,
+    pub group_count: u64,
+    pub share_link_count: u64,
+    pub pending_deletions: u64,
+}
+
+pub fn add_admin(caller: Principal, admin: Principal, state: &mut State) -> Result<(), AdminError> {
+    require_admin(state, caller)?;
+    if admin == Principal::anonymous() {
+        return Err(AdminError::InvalidInput);
+    }
+    state.admins.insert(admin);
+    Ok(())
+}
+
+/// Removes an admin. Controllers lose their admin rights only until they call an admin
+/// endpoint again.
+pub fn remove_admin(caller: Principal, admin: Principal, state: &mut State) -> Result<(), AdminError> {
+    require_admin(state, caller)?;
+    if !state.admins.remove(&admin) {
+        return Err(AdminError::NotFound);
+    }
+    Ok(())
+}
+
+pub fn list_admins(caller: Principal, is_controller: bool, state: &State) -> Result<Vec<Principal>, AdminError> {
+    require_admin_query(state, caller, is_controller)?;
+    Ok(state.admins.iter().copied().collect())
+}
+
+/// Suspends a user's account. Suspended users cannot upload, download or share files, and
+/// their share links and file request aliases stop working until they are reactivated.
+/// Suspending an account again replaces the reason.
+pub fn suspend_user(caller: Principal, user: Principal, reason: String, state: &mut State) -> Result<(), AdminError> {
+    require_admin(state, caller)?;
+    let user = state.account_of(user);
+    let reason = reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(AdminError::InvalidInput);
+    }
+    let profile = state.user_profiles.get_mut(&user).ok_or(AdminError::NotFound)?;
+    profile.is_active = false;
+    state.suspensions.insert(
+        user,
+        Suspension {
+            reason,
+            suspended_at: crate::get_time(),
+            suspended_by: caller,
+        },
+    );
+    Ok(())
+}
+
+/// Lifts a suspension.
+pub fn reactivate_user(caller: Principal, user: Principal, state: &mut State) -> Result<(), AdminError> {
+    require_admin(state, caller)?;
+    let user = state.account_of(user);
+    let profile = state.user_profiles.get_mut(&user).ok_or(AdminError::NotFound)?;
+    if profile.is_active {
+        return Err(AdminError::NotFound);
+    }
+    profile.is_active = true;
+    state.suspensions.remove(&user);
+    Ok(())
+}
+
+/// Lists suspended accounts with the reason they were suspended for.
+pub fn list_suspensions(caller: Principal, is_controller: bool, state: &State) -> Result<Vec<(Principal, Suspension)>, AdminError> {
+    require_admin_query(state, caller, is_controller)?;
+    Ok(state.suspensions.iter().map(|(&user, suspension)| (user, suspension.clone())).collect())
+}
+
+pub fn get_canister_stats(caller: Principal, is_controller: bool, state: &State) -> Result<CanisterStats, AdminError> {
+    require_admin_query(state, caller, is_controller)?;
+    Ok(CanisterStats {
+        user_count: state.user_count,
+        admin_count: state.admins.len() as u64,
+        file_count: state.file_data.len() as u64,
+        walrus_file_count: state
+            .file_data
+            .values()
+            .filter(|file| file.metadata.storage_provider == StorageProvider::Walrus)
+            .count() as u64,
+        trashed_file_count: state.trashed_files.len() as u64,
+        total_storage_used: state.total_storage_used,
+        group_count: state.groups.len() as u64,
+        share_link_count: state.share_links.len() as u64,
+        pending_deletions: state.pending_deletions.len() as u64,
+    })
+}
+
+/// Permanently deletes any user's file, bypassing the trash, e.g. to take down abusive
+/// content.
+pub fn admin_remove_file(caller: Principal, file_id: u64, state: &mut State) -> Result<(), AdminError> {
+    require_admin(state, caller)?;
+    if !state.file_data.contains_key(&file_id) {
+        return Err(AdminError::NotFound);
+    }
+    purge_file(state, file_id);
+    Ok(())
+}
+
+/// Admin check for queries. Queries cannot record controllers as admins, so controllers
+/// are let through even before an update call has added them.
+fn require_admin_query(state: &State, caller: Principal, is_controller: bool) -> Result<(), AdminError> {
+    if is_controller && caller != Principal::anonymous() {
+        return Ok(());
+    }
+    require_admin(state, caller)
+}
+
+fn require_admin(state: &State, caller: Principal) -> Result<(), AdminError> {
+    if caller == Principal::anonymous() {
+        return Err(AdminError::NotAuthenticated);
+    }
+    if !state.is_admin(caller) {
+        return Err(AdminError::PermissionError);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        assign_file_to_group, copy_file, create_group, create_share_link, create_user_profile,
+        delete_file, download_by_link, download_file, download_takeout, download_version, export_account,
+        get_alias_info, import_account, invite_group_member, list_files, list_users, register_file, request_file,
+        upload_file_atomic, upload_file_continue, upload_file_to_alias, upload_new_version, CopyFileRequest,
+        CreateShareLinkRequest, DeleteFileResult, FolderError, GroupError, ImportAccountRequest, ImportError,
+        RegisterFileRequest, RequestFileRequest, ShareLinkError, TakeoutError, UploadFileAtomicRequest,
+        UploadFileToAliasRequest, UploadNewVersionRequest, VersionError,
+    };
+    use crate::{
+        CreateUserRequest, FileDownloadResponse, GroupRole, UploadFileContinueRequest, UploadFileError, UserListResponse,
+    };
+
+    fn admin() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn user() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn setup() -> State {
+        let mut state = State::seeded();
+        state.admins.insert(admin());
+        let request = CreateUserRequest {
+            username: "bob".to_string(),
+            display_name: None,
+            email: Some("bob@example.com".to_string()),
+        };
+        create_user_profile(user(), request, &mut state);
+        state
+    }
+
+    fn upload(state: &mut State, principal: Principal, num_chunks: u64) -> Result<u64, UploadFileError> {
+        let request = UploadFileAtomicRequest {
+            name: "a.txt".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "txt".to_string(),
+            num_chunks,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(principal, request, state)
+    }
+
+    fn share(state: &mut State, principal: Principal, file_id: u64) -> Result<String, ShareLinkError> {
+        let request = CreateShareLinkRequest {
+            file_id,
+            expires_at: None,
+            max_downloads: None,
+            password: None,
+        };
+        create_share_link(principal, request, state)
+    }
+
+    fn request_upload(state: &mut State, principal: Principal) -> Result<String, UploadFileError> {
+        let request = RequestFileRequest {
+            file_name: "report.pdf".to_string(),
+            allow_anonymous: true,
+        };
+        request_file(principal, request, state).map(|response| response.alias)
+    }
+
+    fn upload_to_alias(state: &mut State, principal: Principal, alias: &str) -> Result<u64, UploadFileError> {
+        let request = UploadFileToAliasRequest {
+            alias: alias.to_string(),
+            content: vec![1],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+        };
+        upload_file_to_alias(principal, request, state)
+    }
+
+    #[test]
+    fn only_admins_manage_admins_and_list_users() {
+        let mut state = setup();
+        assert!(matches!(list_users(user(), false, &state), UserListResponse::NotAuthorized));
+        assert!(matches!(list_users(admin(), false, &state), UserListResponse::Ok(users) if users.len() == 1));
+
+        assert_eq!(add_admin(user(), user(), &mut state), Err(AdminError::PermissionError));
+        add_admin(admin(), user(), &mut state).unwrap();
+        assert_eq!(list_admins(user(), false, &state).unwrap().len(), 2);
+        remove_admin(user(), admin(), &mut state).unwrap();
+        assert_eq!(get_canister_stats(admin(), false, &state), Err(AdminError::PermissionError));
+    }
+
+    #[test]
+    fn controllers_pass_admin_queries_without_being_recorded() {
+        let state = setup();
+        assert!(get_canister_stats(user(), true, &state).is_ok());
+        assert!(matches!(list_users(user(), true, &state), UserListResponse::Ok(_)));
+        assert!(!state.is_admin(user()));
+        assert_eq!(list_admins(Principal::anonymous(), true, &state), Err(AdminError::NotAuthenticated));
+    }
+
+    #[test]
+    fn admins_suspend_users_and_remove_files() {
+        let mut state = setup();
+        let file_id = upload(&mut state, user(), 1).unwrap();
+
+        assert_eq!(suspend_user(user(), user(), "spam".to_string(), &mut state), Err(AdminError::PermissionError));
+        assert_eq!(suspend_user(admin(), user(), " ".to_string(), &mut state), Err(AdminError::InvalidInput));
+        suspend_user(admin(), user(), " spam ".to_string(), &mut state).unwrap();
+        assert!(!state.user_profiles[&user()].is_active);
+        let (suspended, suspension) = &list_suspensions(admin(), false, &state).unwrap()[0];
+        assert_eq!(*suspended, user());
+        assert_eq!((suspension.reason.as_str(), suspension.suspended_at), ("spam", crate::get_time()));
+        assert_eq!(suspension.suspended_by, admin());
+        assert_eq!(suspend_user(admin(), admin(), "spam".to_string(), &mut state), Err(AdminError::NotFound));
+
+        let stats = get_canister_stats(admin(), false, &state).unwrap();
+        assert_eq!((stats.user_count, stats.file_count, stats.total_storage_used), (1, 1, 3));
+
+        admin_remove_file(admin(), file_id, &mut state).unwrap();
+        assert!(state.file_data.is_empty());
+        assert_eq!(state.total_storage_used, 0);
+        assert_eq!(admin_remove_file(admin(), file_id, &mut state), Err(AdminError::NotFound));
+    }
+
+    #[test]
+    fn suspended_users_cannot_upload_download_or_share() {
+        let mut state = setup();
+        let other = Principal::from_slice(&[1, 2, 3]);
+        let request = CreateUserRequest {
+            username: "carol".to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(other, request, &mut state);
+
+        let file_id = upload(&mut state, user(), 1).unwrap();
+        let partial_id = upload(&mut state, user(), 2).unwrap();
+        let token = share(&mut state, user(), file_id).unwrap();
+        let alias = request_upload(&mut state, user()).unwrap();
+        let other_alias = request_upload(&mut state, other).unwrap();
+        let group_id = create_group(user(), "team".to_string(), &mut state).unwrap().group_id;
+        export_account(user(), &mut state).unwrap();
+        let manifest = download_takeout(user(), 0, &state).unwrap();
+
+        suspend_user(admin(), user(), "abuse".to_string(), &mut state).unwrap();
+
+        // Uploads
+        assert_eq!(upload(&mut state, user(), 1), Err(UploadFileError::AccountSuspended));
+        let chunk = UploadFileContinueRequest { file_id: partial_id, chunk_id: 1, contents: vec![4] };
+        assert_eq!(upload_file_continue(user(), chunk, &mut state), Err(UploadFileError::AccountSuspended));
+        let register = RegisterFileRequest {
+            file_name: "blob".to_string(),
+            storage_provider: StorageProvider::Icp,
+            blob_id: None,
+            requested_at: 0,
+            uploaded_at: None,
+            size: None,
+        };
+        assert_eq!(register_file(user(), register, &mut state).err(), Some(UploadFileError::AccountSuspended));
+        assert_eq!(request_upload(&mut state, user()), Err(UploadFileError::AccountSuspended));
+        assert_eq!(upload_to_alias(&mut state, user(), &other_alias), Err(UploadFileError::AccountSuspended));
+        let version = UploadNewVersionRequest {
+            file_id,
+            content: vec![4],
+            file_type: "txt".to_string(),
+            num_chunks: 1,
+        };
+        assert_eq!(upload_new_version(user(), version, &mut state), Err(VersionError::PermissionError));
+        assert_eq!(
+            import_account(user(), ImportAccountRequest { manifest }, &mut state),
+            Err(ImportError::AccountSuspended)
+        );
+
+        // Downloads
+        assert_eq!(download_file(&state, user(), file_id, 0), FileDownloadResponse::PermissionError);
+        assert_eq!(download_version(user(), file_id, 1, 0, &state), FileDownloadResponse::PermissionError);
+
+        // Sharing, and the suspended user's public links and aliases
+        assert_eq!(share(&mut state, user(), file_id), Err(ShareLinkError::AccountSuspended));
+        assert_eq!(download_by_link(other, token.clone(), 0, None, &mut state), Err(ShareLinkError::AccountSuspended));
+        assert_eq!(get_alias_info(alias.clone(), &state).err(), Some(UploadFileError::AccountSuspended));
+        assert_eq!(upload_to_alias(&mut state, other, &alias), Err(UploadFileError::AccountSuspended));
+        assert_eq!(
+            invite_group_member(user(), group_id, "carol".to_string(), GroupRole::Viewer, &mut state),
+            Err(GroupError::PermissionError)
+        );
+        assert_eq!(assign_file_to_group(user(), file_id, Some(group_id), &mut state), Err(GroupError::PermissionError));
+
+        // Copies and takeouts
+        let copy = CopyFileRequest { file_id, name: None };
+        assert_eq!(copy_file(user(), copy, &mut state), Err(FolderError::PermissionError));
+        assert_eq!(export_account(user(), &mut state), Err(TakeoutError::AccountSuspended));
+        assert_eq!(download_takeout(user(), 0, &state), Err(TakeoutError::AccountSuspended));
+
+        // Suspended users can still see and delete their data.
+        assert_eq!(list_files(user(), Default::default(), &state).unwrap().files.len(), 3);
+        assert!(matches!(delete_file(&mut state, user(), partial_id), DeleteFileResult::Ok));
+
+        reactivate_user(admin(), user(), &mut state).unwrap();
+        assert!(list_suspensions(admin(), false, &state).unwrap().is_empty());
+        assert_eq!(reactivate_user(admin(), user(), &mut state), Err(AdminError::NotFound));
+        assert!(matches!(download_file(&state, user(), file_id, 0), FileDownloadResponse::FoundFile(_)));
+        assert!(download_by_link(other, token, 0, None, &mut state).is_ok());
+        assert!(upload_to_alias(&mut state, other, &alias).is_ok());
+        assert!(share(&mut state, user(), file_id).is_ok());
+    }
+}
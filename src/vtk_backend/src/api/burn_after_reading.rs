@@ -0,0 +1,129 @@
+use crate::{FileContent, State};
+use candid::Principal;
+
+/// Records that `recipient` downloaded `chunk_id` of a file. If the file is marked
+/// burn-after-reading and the recipient now has every chunk, the file is burned.
+///
+/// Downloads by the owner are not tracked.
+pub(crate) fn record_download(state: &mut State, file_id: u64, recipient: Principal, chunk_id: u64) {
+    let num_chunks = match state.file_data.get(&file_id) {
+        Some(file) if file.metadata.requester_principal != recipient => match &file.content {
+            FileContent::Uploaded { num_chunks, .. } => *num_chunks,
+            _ => return,
+        },
+        _ => return,
+    };
+
+    let Some(recipients) = state.burn_after_reading.get_mut(&file_id) else {
+        return;
+    };
+    let downloaded = recipients.entry(recipient).or_default();
+    downloaded.insert(chunk_id);
+
+    if downloaded.len() as u64 == num_chunks {
+        burn(state, file_id, num_chunks, recipient);
+    }
+}
+
+/// Deletes the chunks of a file and replaces its content with a `Consumed` tombstone,
+/// so the owner can still see when it was read.
+fn burn(state: &mut State, file_id: u64, num_chunks: u64, consumed_by: Principal) {
+    let provider = state.file_data.get(&file_id).map(|file| file.metadata.storage_provider).unwrap_or_default();
+    provider.backend().delete(state, file_id, num_chunks);
+    if let Some(file) = state.file_data.get_mut(&file_id) {
+        file.content = FileContent::Consumed {
+            consumed_at: crate::get_time(),
+            consumed_by,
+        };
+    }
+    state.burn_after_reading.remove(&file_id);
+    crate::api::share_links::remove_file_links(state, file_id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        create_share_link, download_by_link, download_file, upload_file_atomic, upload_file_continue,
+        CreateShareLinkRequest, ShareLinkError, UploadFileAtomicRequest,
+    };
+    use crate::{FileDownloadResponse, UploadFileContinueRequest};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn recipient() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    /// Uploads a two-chunk burn-after-reading file and returns its id and a share link token.
+    fn setup(state: &mut State) -> (u64, String) {
+        let request = UploadFileAtomicRequest {
+            name: "credentials.txt".to_string(),
+            content: vec![1, 2],
+            file_type: "txt".to_string(),
+            num_chunks: 2,
+            burn_after_reading: Some(true),
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = upload_file_atomic(owner(), request, state).unwrap();
+        let request = UploadFileContinueRequest { file_id, chunk_id: 1, contents: vec![3, 4] };
+        upload_file_continue(owner(), request, state).unwrap();
+
+        let request = CreateShareLinkRequest { file_id, expires_at: None, max_downloads: None, password: None };
+        let token = create_share_link(owner(), request, state).unwrap();
+        (file_id, token)
+    }
+
+    #[test]
+    fn file_burns_after_recipient_reads_every_chunk() {
+        let mut state = State::seeded();
+        let (file_id, token) = setup(&mut state);
+
+        let first = download_by_link(recipient(), token.clone(), 0, None, &mut state).unwrap();
+        assert!(matches!(first, FileDownloadResponse::FoundFile(_)));
+        assert!(state.file_contents.contains_key(&(file_id, 0)));
+
+        let second = download_by_link(recipient(), token.clone(), 1, None, &mut state).unwrap();
+        assert!(matches!(second, FileDownloadResponse::FoundFile(_)));
+
+        // Chunks are gone, the record is a tombstone and the link no longer works.
+        assert!(!state.file_contents.contains_key(&(file_id, 0)));
+        assert!(!state.file_contents.contains_key(&(file_id, 1)));
+        assert_eq!(
+            state.file_data[&file_id].content,
+            FileContent::Consumed { consumed_at: crate::get_time(), consumed_by: recipient() }
+        );
+        assert!(!state.burn_after_reading.contains_key(&file_id));
+        assert_eq!(download_by_link(recipient(), token, 0, None, &mut state), Err(ShareLinkError::NotFound));
+        assert_eq!(download_file(&state, owner(), file_id, 0), FileDownloadResponse::NotFoundFile);
+    }
+
+    #[test]
+    fn downloads_are_tracked_per_recipient() {
+        let mut state = State::seeded();
+        let (file_id, token) = setup(&mut state);
+
+        download_by_link(recipient(), token, 0, None, &mut state).unwrap();
+        // Another recipient fetching only the second chunk does not complete the first one's set.
+        record_download(&mut state, file_id, Principal::anonymous(), 1);
+
+        assert!(state.file_contents.contains_key(&(file_id, 0)));
+        assert_eq!(state.burn_after_reading[&file_id].len(), 2);
+    }
+
+    #[test]
+    fn owner_downloads_do_not_burn() {
+        let mut state = State::seeded();
+        let (file_id, token) = setup(&mut state);
+
+        download_by_link(owner(), token.clone(), 0, None, &mut state).unwrap();
+        download_by_link(owner(), token, 1, None, &mut state).unwrap();
+
+        assert!(matches!(state.file_data[&file_id].content, FileContent::Uploaded { .. }));
+        assert!(state.burn_after_reading[&file_id].is_empty());
+    }
+}
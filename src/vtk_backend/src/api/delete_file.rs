@@ -1,4 +1,5 @@
-use crate::{FileContent, State, vetkeys::EncryptedFileData};
+use crate::api::quotas;
+use crate::{FileContent, State};
 use candid::{CandidType, Principal};
 use serde::{Serialize, Deserialize};
 
@@ -10,54 +11,77 @@ pub enum DeleteFileResult {
     PermissionError,
 }
 
+/// Moves a file into its owner's trash. It can be restored with `restore_file` until it is
+/// purged, either by `empty_trash` or once the trash retention period has passed.
 pub fn delete_file(state: &mut State, caller: Principal, file_id: u64) -> DeleteFileResult {
     // Check if caller is authenticated (not anonymous)
     if caller == Principal::anonymous() {
         return DeleteFileResult::NotAuthenticated;
     }
 
-    // Check if the user owns this file
-    match state.file_owners.get(&caller) {
-        Some(files) => {
-            if !files.contains(&file_id) {
-                return DeleteFileResult::PermissionError;
-            }
-
-            if let Some(file) = state.file_data.remove(&file_id) {
-                // Remove all chunks
-                let num_chunks = match file.content {
-                    FileContent::Uploaded { num_chunks, .. } |
-                    FileContent::PartiallyUploaded { num_chunks, .. } => num_chunks,
-                    FileContent::Pending { .. } => 0,
-                };
-                for chunk_id in 0..num_chunks {
-                    state.file_contents.remove(&(file_id, chunk_id));
-                }
-
-                // Remove the file from the user's owned files
-                if let Some(user_files) = state.file_owners.get_mut(&caller) {
-                    user_files.retain(|&id| id != file_id);
-                }
-
-                DeleteFileResult::Ok
-            } else {
-                DeleteFileResult::NotFound
-            }
+    // Missing files and other users' files get the same answer, so that callers cannot
+    // probe which file IDs exist. Files in the caller's own trash are already deleted.
+    if !state.is_file_owner(caller, file_id) {
+        if state.user_trash.get(&caller).is_some_and(|files| files.contains(&file_id)) {
+            return DeleteFileResult::NotFound;
         }
-        None => DeleteFileResult::PermissionError,
+        return DeleteFileResult::PermissionError;
     }
+
+    crate::api::trash::move_to_trash(state, caller, file_id);
+    DeleteFileResult::Ok
+}
+
+/// Permanently deletes a file and everything attached to it.
+pub(crate) fn purge_file(state: &mut State, file_id: u64) {
+    let Some(file) = state.file_data.remove(&file_id) else {
+        return;
+    };
+    let num_chunks = match file.content {
+        FileContent::Uploaded { num_chunks, .. } |
+        FileContent::PartiallyUploaded { num_chunks, .. } => num_chunks,
+        FileContent::Pending { .. } | FileContent::Consumed { .. } => 0,
+    };
+
+    // Remove all chunks, unless a copy of the file still uses them
+    file.metadata.storage_provider.backend().delete(state, file_id, num_chunks);
+    crate::api::file_versions::remove_versions(state, file_id);
+
+    let owner = file.metadata.requester_principal;
+    if let Some(files) = state.file_owners.get_mut(&owner) {
+        files.retain(|&id| id != file_id);
+    }
+    state.remove_usage(owner, 0, 1);
+    if let Some(profile) = state.user_profiles.get_mut(&owner).filter(|p| p.avatar_file_id == Some(file_id)) {
+        profile.avatar_file_id = None;
+    }
+    if state.trashed_files.remove(&file_id).is_some() {
+        if let Some(files) = state.user_trash.get_mut(&owner) {
+            files.retain(|&id| id != file_id);
+        }
+    }
+    crate::api::share_links::remove_file_links(state, file_id);
+    state.burn_after_reading.remove(&file_id);
+    state.walrus_registrants.remove(&file_id);
+    crate::api::file_requests::close_request(state, file_id);
+    crate::api::groups::unassign_file(state, file_id);
+    crate::api::folders::remove_file(state, file_id);
+    crate::api::file_tags::remove_metadata(state, owner, file_id);
+    state.encrypted_file_metadata.remove(&file_id);
+    state.file_group_keys.remove(&file_id);
+    quotas::release_reservation(state, file_id);
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{File, FileContent, FileMetadata, State};
+    use crate::{vetkeys::EncryptedFileData, File, FileContent, FileMetadata, State, StorageProvider};
     use std::collections::HashMap;
 
     #[test]
     fn delete_existing_file() {
         let mut state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
+        let test_principal = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
 
         // Insert a file manually
         state.file_data.insert(
@@ -68,7 +92,7 @@ mod test {
                     requester_principal: test_principal,
                     requested_at: 12345,
                     uploaded_at: Some(12345),
-                    storage_provider: "icp".to_string(),
+                    storage_provider: StorageProvider::Icp,
                     blob_id: None,
                     is_encrypted: true,
                 },
@@ -92,23 +116,29 @@ mod test {
         // Delete the file
         let result = delete_file(&mut state, test_principal, 0);
 
-        // Check result and that file is gone
+        // Check result and that file is in the trash
         assert_eq!(result, DeleteFileResult::Ok);
+        assert!(state.is_trashed(0));
+        assert!(!state.file_owners.get(&test_principal).unwrap().contains(&0));
+        assert_eq!(delete_file(&mut state, test_principal, 0), DeleteFileResult::NotFound);
+
+        // Purging removes the file for good
+        purge_file(&mut state, 0);
         assert!(!state.file_data.contains_key(&0));
         assert!(state.file_contents.get(&(0, 0)).is_none());
-        assert!(!state.file_owners.get(&test_principal).unwrap().contains(&0));
+        assert!(!state.is_trashed(0));
     }
 
     #[test]
     fn delete_nonexistent_file() {
         let mut state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
+        let test_principal = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
 
         // Try to delete a file that doesn't exist
         let result = delete_file(&mut state, test_principal, 42);
 
-        // Should return NotFound
-        assert_eq!(result, DeleteFileResult::NotFound);
+        // Should look the same as a file owned by someone else
+        assert_eq!(result, DeleteFileResult::PermissionError);
     }
 
     #[test]
@@ -125,8 +155,8 @@ mod test {
     #[test]
     fn wrong_user_cannot_delete() {
         let mut state = State::default();
-        let test_principal1 = Principal::from_text("2vxsx-fae").unwrap();
-        let test_principal2 = Principal::from_text("3vxsx-fae").unwrap();
+        let test_principal1 = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
+        let test_principal2 = Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap();
 
         // Insert a file owned by principal1
         state.file_data.insert(
@@ -137,7 +167,7 @@ mod test {
                     requester_principal: test_principal1,
                     requested_at: 12345,
                     uploaded_at: Some(12345),
-                    storage_provider: "icp".to_string(),
+                    storage_provider: StorageProvider::Icp,
                     blob_id: None,
                     is_encrypted: true,
                 },
@@ -1,49 +1,36 @@
 // pub use crate::ceil_division;
-use crate::{FileContent, FileData, FileDownloadResponse, State, vetkeys::VetKeyService, vetkeys::EncryptedFileData};
+use crate::{File, FileDownloadResponse, State};
 use candid::Principal;
-use std::collections::HashMap;
 
-pub async fn download_file(s: &State, caller: Principal, file_id: u64, chunk_id: u64) -> Result<FileDownloadResponse, String> {
-    // Check if caller is authenticated (not anonymous)
-    if caller == Principal::anonymous() {
-        return Ok(FileDownloadResponse::PermissionError);
+/// Returns one chunk of a file owned by the caller or shared with them through a group.
+///
+/// Contents are stored exactly as uploaded: files are encrypted client-side with a
+/// vetKey, so decryption also happens on the client.
+pub fn download_file(s: &State, caller: Principal, file_id: u64, chunk_id: u64) -> FileDownloadResponse {
+    // Check if caller is authenticated (not anonymous) and not suspended
+    if caller == Principal::anonymous() || s.is_suspended(caller) {
+        return FileDownloadResponse::PermissionError;
     }
 
-    // Check if the caller owns this file
-    match s.file_owners.get(&caller) {
-        Some(files) => {
-            if !files.contains(&file_id) {
-                return Ok(FileDownloadResponse::PermissionError);
-            }
-        }
-        None => return Ok(FileDownloadResponse::PermissionError),
+    let file = match s.file_data.get(&file_id) {
+        Some(file) => file,
+        None => return FileDownloadResponse::NotFoundFile,
+    };
+
+    // Check if the caller owns this file or can read it through a group
+    if !s.can_read_file(caller, file_id) {
+        return FileDownloadResponse::PermissionError;
     }
 
-    match s.file_data.get(&file_id) {
-        None => Ok(FileDownloadResponse::NotFoundFile),
-        Some(file) => match &file.content {
-            FileContent::Uploaded { file_type, num_chunks, vetkey_metadata } => {
-                match s.file_contents.get(&(file_id, chunk_id)) {
-                    Some(_encrypted_contents) => {
-                        // Decrypt the content using vetKeys
-                        let decrypted_contents = VetKeyService::decrypt_file_for_user(
-                            vetkey_metadata,
-                            caller,
-                            file_id,
-                        ).await.map_err(|e| format!("Decryption failed: {}", e))?;
-                        
-                        Ok(FileDownloadResponse::FoundFile(FileData {
-                            contents: decrypted_contents,
-                            file_type: file_type.clone(),
-                            num_chunks: *num_chunks,
-                        }))
-                    },
-                    None => Ok(FileDownloadResponse::NotFoundFile),
-                }
-            }
-            _ => Ok(FileDownloadResponse::NotUploadedFile),
-        },
+    read_chunk(s, file, file_id, chunk_id)
+}
+
+/// Reads one chunk of an uploaded file from its storage provider. Callers are responsible for permission checks.
+pub(crate) fn read_chunk(s: &State, file: &File, file_id: u64, chunk_id: u64) -> FileDownloadResponse {
+    if s.is_trashed(file_id) {
+        return FileDownloadResponse::NotFoundFile;
     }
+    file.metadata.storage_provider.backend().get(s, file, file_id, chunk_id)
 }
 
 
@@ -137,12 +124,13 @@ pub async fn download_file(s: &State, caller: Principal, file_id: u64, chunk_id:
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{File, FileContent, FileData, FileMetadata, State};
+    use crate::{vetkeys::EncryptedFileData, File, FileContent, FileData, FileMetadata, State, StorageProvider};
+    use std::collections::HashMap;
 
-    #[tokio::test]
-    async fn download_existing_uploaded_file() {
+    #[test]
+    fn download_existing_uploaded_file() {
         let mut state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
+        let test_principal = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
         
         state.file_data.insert(
             0,
@@ -152,7 +140,7 @@ mod test {
                     requester_principal: test_principal,
                     requested_at: 12345,
                     uploaded_at: Some(12345),
-                    storage_provider: "icp".to_string(),
+                    storage_provider: StorageProvider::Icp,
                     blob_id: None,
                     is_encrypted: true,
                 },
@@ -172,7 +160,7 @@ mod test {
         // Add file to user's owned files
         state.file_owners.insert(test_principal, vec![0]);
 
-        let result = download_file(&state, test_principal, 0, 0).await.unwrap();
+        let result = download_file(&state, test_principal, 0, 0);
         assert_eq!(
             result,
             FileDownloadResponse::FoundFile(FileData {
@@ -183,18 +171,18 @@ mod test {
         );
     }
 
-    #[tokio::test]
-    async fn download_nonexistent_file() {
+    #[test]
+    fn download_nonexistent_file() {
         let state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
-        let result = download_file(&state, test_principal, 42, 0).await.unwrap();
+        let test_principal = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
+        let result = download_file(&state, test_principal, 42, 0);
         assert_eq!(result, FileDownloadResponse::NotFoundFile);
     }
 
-    #[tokio::test]
-    async fn download_not_uploaded_file() {
+    #[test]
+    fn download_not_uploaded_file() {
         let mut state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
+        let test_principal = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
         
         state.file_data.insert(
             0,
@@ -204,7 +192,7 @@ mod test {
                     requester_principal: test_principal,
                     requested_at: 12345,
                     uploaded_at: None,
-                    storage_provider: "icp".to_string(),
+                    storage_provider: StorageProvider::Icp,
                     blob_id: None,
                     is_encrypted: false,
                 },
@@ -215,22 +203,22 @@ mod test {
         // Add file to user's owned files
         state.file_owners.insert(test_principal, vec![0]);
         
-        let result = download_file(&state, test_principal, 0, 0).await.unwrap();
+        let result = download_file(&state, test_principal, 0, 0);
         assert_eq!(result, FileDownloadResponse::NotUploadedFile);
     }
 
-    #[tokio::test]
-    async fn anonymous_user_cannot_download() {
+    #[test]
+    fn anonymous_user_cannot_download() {
         let state = State::default();
-        let result = download_file(&state, Principal::anonymous(), 0, 0).await.unwrap();
+        let result = download_file(&state, Principal::anonymous(), 0, 0);
         assert_eq!(result, FileDownloadResponse::PermissionError);
     }
 
-    #[tokio::test]
-    async fn wrong_user_cannot_download() {
+    #[test]
+    fn wrong_user_cannot_download() {
         let mut state = State::default();
-        let test_principal1 = Principal::from_text("2vxsx-fae").unwrap();
-        let test_principal2 = Principal::from_text("3vxsx-fae").unwrap();
+        let test_principal1 = Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap();
+        let test_principal2 = Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap();
         
         state.file_data.insert(
             0,
@@ -240,7 +228,7 @@ mod test {
                     requester_principal: test_principal1,
                     requested_at: 12345,
                     uploaded_at: Some(12345),
-                    storage_provider: "icp".to_string(),
+                    storage_provider: StorageProvider::Icp,
                     blob_id: None,
                     is_encrypted: true,
                 },
@@ -261,7 +249,7 @@ mod test {
         state.file_owners.insert(test_principal1, vec![0]);
         
         // Try to download as principal2
-        let result = download_file(&state, test_principal2, 0, 0).await.unwrap();
+        let result = download_file(&state, test_principal2, 0, 0);
         assert_eq!(result, FileDownloadResponse::PermissionError);
     }
 }
@@ -0,0 +1,184 @@
+use crate::{EncryptedFileMetadata, FileContent, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// Stored as the plaintext `file_type` of files with encrypted metadata.
+pub const ENCRYPTED_FILE_TYPE: &str = "encrypted";
+/// Upper bound on the size of each encrypted field, in bytes.
+pub const MAX_ENCRYPTED_FIELD_SIZE: usize = 1024;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum EncryptedMetadataError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    /// A field of the encrypted metadata is empty or too large.
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+}
+
+/// The plaintext name stored for a file with an encrypted name. It is unique per file, so
+/// encrypted files never collide with each other in a folder.
+pub fn placeholder_name(file_id: u64) -> String {
+    format!("encrypted-{}", file_id)
+}
+
+pub fn is_valid(metadata: &EncryptedFileMetadata) -> bool {
+    [&metadata.name, &metadata.file_type]
+        .iter()
+        .all(|field| !field.is_empty() && field.len() <= MAX_ENCRYPTED_FIELD_SIZE)
+}
+
+pub fn is_encrypted(state: &State, file_id: u64) -> bool {
+    state.encrypted_file_metadata.contains_key(&file_id)
+}
+
+/// Replaces the encrypted name and type of one of the caller's files. This is how files
+/// with encrypted metadata are renamed.
+///
+/// Calling this on a file with a plaintext name switches it to encrypted metadata: the
+/// plaintext name and type are replaced by placeholders.
+pub fn set_encrypted_metadata(
+    caller: Principal,
+    file_id: u64,
+    metadata: EncryptedFileMetadata,
+    state: &mut State,
+) -> Result<(), EncryptedMetadataError> {
+    if caller == Principal::anonymous() {
+        return Err(EncryptedMetadataError::NotAuthenticated);
+    }
+    if !state.file_data.contains_key(&file_id) || state.is_trashed(file_id) {
+        return Err(EncryptedMetadataError::NotFound);
+    }
+    if !state.is_file_owner(caller, file_id) {
+        return Err(EncryptedMetadataError::PermissionError);
+    }
+    if !is_valid(&metadata) {
+        return Err(EncryptedMetadataError::InvalidInput);
+    }
+
+    if let Some(file) = state.file_data.get_mut(&file_id) {
+        file.metadata.file_name = placeholder_name(file_id);
+        if let FileContent::Uploaded { file_type, .. } | FileContent::PartiallyUploaded { file_type, .. } =
+            &mut file.content
+        {
+            *file_type = ENCRYPTED_FILE_TYPE.to_string();
+        }
+    }
+    // Earlier versions would otherwise still reveal the plaintext type.
+    for version in state.file_versions.get_mut(&file_id).into_iter().flat_map(|history| &mut history.previous) {
+        version.file_type = ENCRYPTED_FILE_TYPE.to_string();
+    }
+    if let Some(pending) = state.pending_versions.get_mut(&file_id) {
+        pending.file_type = ENCRYPTED_FILE_TYPE.to_string();
+    }
+    state.encrypted_file_metadata.insert(file_id, metadata);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        copy_file, download_file, list_files, list_versions, rename_file, upload_file_atomic, upload_new_version,
+        CopyFileRequest, FolderError, UploadFileAtomicRequest, UploadNewVersionRequest,
+    };
+    use crate::{FileDownloadResponse, UploadFileError};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn encrypted(name: &[u8]) -> EncryptedFileMetadata {
+        EncryptedFileMetadata {
+            name: name.to_vec(),
+            file_type: vec![0xaa, 0xbb],
+        }
+    }
+
+    fn upload(state: &mut State, name: &str, file_type: &str, metadata: EncryptedFileMetadata) -> Result<u64, UploadFileError> {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content: vec![1, 2, 3],
+            file_type: file_type.to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: Some(metadata),
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state)
+    }
+
+    #[test]
+    fn plaintext_is_never_stored() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, "", "", encrypted(&[1, 2, 3])).unwrap();
+
+        let file = &state.file_data[&file_id];
+        assert_eq!(file.metadata.file_name, placeholder_name(file_id));
+        match download_file(&state, owner(), file_id, 0) {
+            FileDownloadResponse::FoundFile(data) => assert_eq!(data.file_type, ENCRYPTED_FILE_TYPE),
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        let files = list_files(owner(), Default::default(), &state).unwrap().files;
+        assert_eq!(files[0].encrypted_metadata, Some(encrypted(&[1, 2, 3])));
+
+        // A plaintext name next to the encrypted one would defeat the purpose.
+        assert!(upload(&mut state, "secret.pdf", "", encrypted(&[1])).is_err());
+        assert!(upload(&mut state, "", "", encrypted(&[])).is_err());
+    }
+
+    #[test]
+    fn encrypted_files_are_renamed_through_their_blobs() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, "", "", encrypted(&[1])).unwrap();
+
+        assert_eq!(
+            rename_file(owner(), file_id, "plain.pdf".to_string(), &mut state),
+            Err(FolderError::InvalidName)
+        );
+        set_encrypted_metadata(owner(), file_id, encrypted(&[2]), &mut state).unwrap();
+        assert_eq!(state.encrypted_file_metadata[&file_id], encrypted(&[2]));
+        assert_eq!(
+            set_encrypted_metadata(owner(), file_id, encrypted(&[]), &mut state),
+            Err(EncryptedMetadataError::InvalidInput)
+        );
+
+        let copy_id = copy_file(owner(), CopyFileRequest { file_id, name: None }, &mut state).unwrap();
+        assert_eq!(state.file_data[&copy_id].metadata.file_name, placeholder_name(copy_id));
+        assert_eq!(state.encrypted_file_metadata[&copy_id], encrypted(&[2]));
+    }
+
+    #[test]
+    fn switching_to_encrypted_metadata_scrubs_the_version_history() {
+        let mut state = State::default();
+        let request = UploadFileAtomicRequest {
+            name: "plain.pdf".to_string(),
+            content: vec![1],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = upload_file_atomic(owner(), request, &mut state).unwrap();
+        let request = UploadNewVersionRequest {
+            file_id,
+            content: vec![2],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+        };
+        upload_new_version(owner(), request, &mut state).unwrap();
+
+        set_encrypted_metadata(owner(), file_id, encrypted(&[1]), &mut state).unwrap();
+        let versions = list_versions(owner(), file_id, &state).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert!(versions.iter().all(|version| version.file_type == ENCRYPTED_FILE_TYPE));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::api::folders::unique_name;
+use crate::api::quotas;
+use crate::{File, FileContent, FileMetadata, FileRequest, State, StorageProvider, UploadFileError, vetkeys::EncryptedFileData};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RequestFileRequest {
+    /// What the owner is asking for, e.g. "ID scan". Used as the file name.
+    pub file_name: String,
+    /// Let anonymous callers upload against the alias (single-chunk uploads only).
+    pub allow_anonymous: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RequestFileResponse {
+    pub file_id: u64,
+    pub alias: String,
+}
+
+/// What an uploader needs to know about a request before uploading: in particular the
+/// requester's principal, which is the vetKey derivation ID the file must be encrypted to.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AliasInfo {
+    pub file_name: String,
+    pub requester: Principal,
+    pub requester_username: Option<String>,
+    pub requested_at: u64,
+    pub allow_anonymous: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadFileToAliasRequest {
+    pub alias: String,
+    pub content: Vec<u8>,
+    pub file_type: String,
+    pub num_chunks: u64,
+}
+
+/// Creates a pending file owned by the caller and returns the alias others can upload against.
+pub fn request_file(
+    caller: Principal,
+    request: RequestFileRequest,
+    state: &mut State,
+) -> Result<RequestFileResponse, UploadFileError> {
+    if caller == Principal::anonymous() {
+        return Err(UploadFileError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(UploadFileError::AccountSuspended);
+    }
+    if request.file_name.trim().is_empty() {
+        return Err(UploadFileError::InvalidInput);
+    }
+
+    let file_name = unique_name(state, caller, None, &request.file_name);
+    let file_id = state.generate_file_id();
+    let alias = state.generate_token();
+    state.file_data.insert(
+        file_id,
+        File {
+            metadata: FileMetadata {
+                file_name,
+                requester_principal: caller,
+                requested_at: crate::get_time(),
+                uploaded_at: None,
+                storage_provider: StorageProvider::Icp,
+                blob_id: None,
+                is_encrypted: false,
+            },
+            content: FileContent::Pending { alias: alias.clone() },
+        },
+    );
+    state.add_owned_file(caller, file_id);
+    index_alias(state, file_id, alias.clone(), request.allow_anonymous);
+
+    Ok(RequestFileResponse { file_id, alias })
+}
+
+/// Looks up an open file request by its alias. The aliases of suspended users are disabled.
+pub fn get_alias_info(alias: String, state: &State) -> Result<AliasInfo, UploadFileError> {
+    let (file_id, request) = open_request(state, &alias)?;
+    let file = state.file_data.get(&file_id).ok_or(UploadFileError::NotRequested)?;
+    let requester = file.metadata.requester_principal;
+    if state.is_suspended(requester) {
+        return Err(UploadFileError::AccountSuspended);
+    }
+
+    Ok(AliasInfo {
+        file_name: file.metadata.file_name.clone(),
+        requester,
+        requester_username: state.user_profiles.get(&requester).map(|p| p.username.clone()),
+        requested_at: file.metadata.requested_at,
+        allow_anonymous: request.allow_anonymous,
+    })
+}
+
+/// Uploads a file (or its first chunk) against a file request alias.
+///
+/// The content must be encrypted client-side to the requester's vetKey. The file stays in the
+/// requester's vault; the uploader only gets to send the remaining chunks.
+pub fn upload_file_to_alias(
+    caller: Principal,
+    request: UploadFileToAliasRequest,
+    state: &mut State,
+) -> Result<u64, UploadFileError> {
+    if request.num_chunks == 0 {
+        return Err(UploadFileError::InvalidInput);
+    }
+    let (file_id, file_request) = open_request(state, &request.alias)?;
+
+    if file_request.uploader.is_some() {
+        return Err(UploadFileError::AlreadyUploaded);
+    }
+    if caller == Principal::anonymous() && (!file_request.allow_anonymous || request.num_chunks != 1) {
+        return Err(UploadFileError::NotAuthenticated);
+    }
+
+    let file = state.file_data.get(&file_id).ok_or(UploadFileError::NotRequested)?;
+    if !matches!(file.content, FileContent::Pending { .. }) {
+        return Err(UploadFileError::AlreadyUploaded);
+    }
+    // The requester stores the file, so it counts against their quota.
+    let owner = file.metadata.requester_principal;
+    if state.is_suspended(owner) || state.is_suspended(caller) {
+        return Err(UploadFileError::AccountSuspended);
+    }
+    quotas::charge_chunk(state, owner, file_id, request.content.len() as u64)?;
+
+    let file = state.file_data.get_mut(&file_id).ok_or(UploadFileError::NotRequested)?;
+
+    let vetkey_metadata = EncryptedFileData {
+        encrypted_content: Vec::new(),
+        file_owners: vec![owner],
+        encryption_metadata: HashMap::new(),
+    };
+    file.content = if request.num_chunks == 1 {
+        FileContent::Uploaded {
+            num_chunks: 1,
+            file_type: request.file_type,
+            vetkey_metadata,
+        }
+    } else {
+        FileContent::PartiallyUploaded {
+            num_chunks: request.num_chunks,
+            file_type: request.file_type,
+            vetkey_metadata,
+        }
+    };
+    file.metadata.uploaded_at = Some(crate::get_time());
+    file.metadata.is_encrypted = true;
+    let complete = request.num_chunks == 1;
+
+    state.write_chunk(owner, file_id, 0, request.content);
+
+    if complete {
+        close_request(state, file_id);
+        quotas::release_reservation(state, file_id);
+    } else if let Some(file_request) = state.file_requests.get_mut(&file_id) {
+        file_request.uploader = Some(caller);
+    }
+
+    Ok(file_id)
+}
+
+/// Whether `caller` is the one uploading the file against a request alias.
+pub(crate) fn is_alias_uploader(state: &State, file_id: u64, caller: Principal) -> bool {
+    state
+        .file_requests
+        .get(&file_id)
+        .is_some_and(|request| request.uploader == Some(caller))
+}
+
+pub(crate) fn index_alias(state: &mut State, file_id: u64, alias: String, allow_anonymous: bool) {
+    state.file_alias_index.insert(alias.clone(), file_id);
+    state.file_requests.insert(
+        file_id,
+        FileRequest {
+            alias,
+            allow_anonymous,
+            uploader: None,
+        },
+    );
+}
+
+/// Forgets the alias of a request once its file is fully uploaded or deleted.
+pub(crate) fn close_request(state: &mut State, file_id: u64) {
+    if let Some(request) = state.file_requests.remove(&file_id) {
+        state.file_alias_index.remove(&request.alias);
+    }
+}
+
+fn open_request<'a>(state: &'a State, alias: &str) -> Result<(u64, &'a FileRequest), UploadFileError> {
+    let file_id = *state.file_alias_index.get(alias).ok_or(UploadFileError::NotRequested)?;
+    let request = state.file_requests.get(&file_id).ok_or(UploadFileError::NotRequested)?;
+    Ok((file_id, request))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{download_file, upload_file_continue};
+    use crate::{FileDownloadResponse, UploadFileContinueRequest};
+
+    fn requester() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn uploader() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn make_request(state: &mut State, allow_anonymous: bool) -> RequestFileResponse {
+        let request = RequestFileRequest {
+            file_name: "ID scan".to_string(),
+            allow_anonymous,
+        };
+        request_file(requester(), request, state).unwrap()
+    }
+
+    fn upload(alias: &str, content: Vec<u8>, num_chunks: u64) -> UploadFileToAliasRequest {
+        UploadFileToAliasRequest {
+            alias: alias.to_string(),
+            content,
+            file_type: "png".to_string(),
+            num_chunks,
+        }
+    }
+
+    #[test]
+    fn request_creates_pending_file_with_resolvable_alias() {
+        let mut state = State::seeded();
+        let response = make_request(&mut state, false);
+
+        assert_eq!(
+            state.file_data[&response.file_id].content,
+            FileContent::Pending { alias: response.alias.clone() }
+        );
+        assert_eq!(state.file_owners[&requester()], vec![response.file_id]);
+
+        let info = get_alias_info(response.alias, &state).unwrap();
+        assert_eq!(info.requester, requester());
+        assert_eq!(info.file_name, "ID scan");
+        assert_eq!(get_alias_info("nope".to_string(), &state), Err(UploadFileError::NotRequested));
+    }
+
+    #[test]
+    fn requests_need_an_authenticated_caller_and_a_name() {
+        let mut state = State::seeded();
+        let request = RequestFileRequest {
+            file_name: " ".to_string(),
+            allow_anonymous: false,
+        };
+        assert_eq!(request_file(requester(), request.clone(), &mut state), Err(UploadFileError::InvalidInput));
+        assert_eq!(request_file(Principal::anonymous(), request, &mut state), Err(UploadFileError::NotAuthenticated));
+        assert!(state.file_data.is_empty());
+    }
+
+    #[test]
+    fn upload_lands_in_requesters_vault() {
+        let mut state = State::seeded();
+        let response = make_request(&mut state, false);
+
+        let file_id = upload_file_to_alias(uploader(), upload(&response.alias, vec![1, 2], 1), &mut state).unwrap();
+        assert_eq!(file_id, response.file_id);
+
+        assert!(!state.file_owners.contains_key(&uploader()));
+        assert_eq!(
+            download_file(&state, requester(), file_id, 0),
+            FileDownloadResponse::FoundFile(crate::FileData {
+                contents: vec![1, 2],
+                file_type: "png".to_string(),
+                num_chunks: 1,
+            })
+        );
+        // The alias is used up.
+        assert!(state.file_alias_index.is_empty());
+        assert_eq!(
+            upload_file_to_alias(uploader(), upload(&response.alias, vec![3], 1), &mut state),
+            Err(UploadFileError::NotRequested)
+        );
+    }
+
+    #[test]
+    fn zero_chunks_are_rejected() {
+        let mut state = State::seeded();
+        let response = make_request(&mut state, false);
+        assert_eq!(
+            upload_file_to_alias(uploader(), upload(&response.alias, vec![1], 0), &mut state),
+            Err(UploadFileError::InvalidInput)
+        );
+        // The request is untouched and can still be fulfilled.
+        assert!(matches!(state.file_data[&response.file_id].content, FileContent::Pending { .. }));
+        assert!(upload_file_to_alias(uploader(), upload(&response.alias, vec![1], 1), &mut state).is_ok());
+    }
+
+    #[test]
+    fn multi_chunk_upload_is_continued_by_the_uploader_only() {
+        let mut state = State::seeded();
+        let response = make_request(&mut state, false);
+        let file_id = upload_file_to_alias(uploader(), upload(&response.alias, vec![0], 2), &mut state).unwrap();
+
+        // Nobody else can claim the alias in the meantime.
+        let intruder = Principal::from_slice(&[9, 9, 9]);
+        assert_eq!(
+            upload_file_to_alias(intruder, upload(&response.alias, vec![0], 1), &mut state),
+            Err(UploadFileError::AlreadyUploaded)
+        );
+        let chunk = UploadFileContinueRequest { file_id, chunk_id: 1, contents: vec![1] };
+        assert!(upload_file_continue(intruder, chunk.clone(), &mut state).is_err());
+
+        upload_file_continue(uploader(), chunk, &mut state).unwrap();
+        assert!(matches!(state.file_data[&file_id].content, FileContent::Uploaded { .. }));
+        assert!(state.file_requests.is_empty());
+    }
+
+    #[test]
+    fn anonymous_uploads_need_permission() {
+        let mut state = State::seeded();
+        let closed = make_request(&mut state, false);
+        assert_eq!(
+            upload_file_to_alias(Principal::anonymous(), upload(&closed.alias, vec![1], 1), &mut state),
+            Err(UploadFileError::NotAuthenticated)
+        );
+
+        let open = make_request(&mut state, true);
+        assert_eq!(
+            upload_file_to_alias(Principal::anonymous(), upload(&open.alias, vec![1], 2), &mut state),
+            Err(UploadFileError::NotAuthenticated)
+        );
+        assert!(upload_file_to_alias(Principal::anonymous(), upload(&open.alias, vec![1], 1), &mut state).is_ok());
+    }
+}
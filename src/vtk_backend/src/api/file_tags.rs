@@ -0,0 +1,252 @@
+use crate::State;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const MAX_TAGS_PER_FILE: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 32;
+pub const MAX_ATTRIBUTES_PER_FILE: usize = 16;
+pub const MAX_ATTRIBUTE_KEY_LENGTH: usize = 64;
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 256;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TagError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    /// A tag, key or value is empty or too long.
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// Too many tags or attributes for one file.
+    #[serde(rename = "limit_exceeded")]
+    LimitExceeded,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UpdateFileMetadataRequest {
+    pub file_id: u64,
+    /// Replaces all tags of the file. Left unchanged if `None`.
+    pub tags: Option<Vec<String>>,
+    /// Replaces all attributes of the file. Left unchanged if `None`.
+    pub attributes: Option<Vec<(String, String)>>,
+}
+
+/// Replaces the tags and/or attributes of one of the caller's files.
+///
+/// Tags are trimmed and lowercased, so `Invoices` and `invoices ` are the same tag.
+pub fn update_file_metadata(
+    caller: Principal,
+    request: UpdateFileMetadataRequest,
+    state: &mut State,
+) -> Result<(), TagError> {
+    if caller == Principal::anonymous() {
+        return Err(TagError::NotAuthenticated);
+    }
+    if !state.file_data.contains_key(&request.file_id) || state.is_trashed(request.file_id) {
+        return Err(TagError::NotFound);
+    }
+    if !state.is_file_owner(caller, request.file_id) {
+        return Err(TagError::PermissionError);
+    }
+
+    // Validate everything before changing anything.
+    let tags = request.tags.map(normalize_tags).transpose()?;
+    let attributes = request.attributes.map(validate_attributes).transpose()?;
+
+    if let Some(tags) = tags {
+        set_tags(state, caller, request.file_id, tags);
+    }
+    if let Some(attributes) = attributes {
+        if attributes.is_empty() {
+            state.file_attributes.remove(&request.file_id);
+        } else {
+            state.file_attributes.insert(request.file_id, attributes);
+        }
+    }
+    Ok(())
+}
+
+/// The caller's files carrying `tag`, looked up in the tag index.
+pub(crate) fn files_with_tag<'a>(state: &'a State, owner: Principal, tag: &str) -> impl Iterator<Item = &'a u64> {
+    state
+        .tag_index
+        .get(&(owner, normalize_tag(tag)))
+        .into_iter()
+        .flatten()
+}
+
+pub(crate) fn has_tag(state: &State, file_id: u64, tag: &str) -> bool {
+    state
+        .file_tags
+        .get(&file_id)
+        .is_some_and(|tags| tags.contains(&normalize_tag(tag)))
+}
+
+/// Gives a copy of a file the tags and attributes of the original.
+pub(crate) fn copy_metadata(state: &mut State, owner: Principal, from: u64, to: u64) {
+    if let Some(tags) = state.file_tags.get(&from).cloned() {
+        set_tags(state, owner, to, tags);
+    }
+    if let Some(attributes) = state.file_attributes.get(&from).cloned() {
+        state.file_attributes.insert(to, attributes);
+    }
+}
+
+/// Drops the tags and attributes of a purged file.
+pub(crate) fn remove_metadata(state: &mut State, owner: Principal, file_id: u64) {
+    set_tags(state, owner, file_id, BTreeSet::new());
+    state.file_attributes.remove(&file_id);
+}
+
+fn set_tags(state: &mut State, owner: Principal, file_id: u64, tags: BTreeSet<String>) {
+    for old_tag in state.file_tags.remove(&file_id).unwrap_or_default() {
+        let key = (owner, old_tag);
+        if let Some(files) = state.tag_index.get_mut(&key) {
+            files.remove(&file_id);
+            if files.is_empty() {
+                state.tag_index.remove(&key);
+            }
+        }
+    }
+    for tag in &tags {
+        state.tag_index.entry((owner, tag.clone())).or_default().insert(file_id);
+    }
+    if !tags.is_empty() {
+        state.file_tags.insert(file_id, tags);
+    }
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+fn normalize_tags(tags: Vec<String>) -> Result<BTreeSet<String>, TagError> {
+    let tags: BTreeSet<String> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    if tags.iter().any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LENGTH) {
+        return Err(TagError::InvalidInput);
+    }
+    if tags.len() > MAX_TAGS_PER_FILE {
+        return Err(TagError::LimitExceeded);
+    }
+    Ok(tags)
+}
+
+fn validate_attributes(attributes: Vec<(String, String)>) -> Result<BTreeMap<String, String>, TagError> {
+    if attributes.iter().any(|(key, value)| {
+        key.trim().is_empty() || key.len() > MAX_ATTRIBUTE_KEY_LENGTH || value.len() > MAX_ATTRIBUTE_VALUE_LENGTH
+    }) {
+        return Err(TagError::InvalidInput);
+    }
+    let attributes: BTreeMap<String, String> = attributes
+        .into_iter()
+        .map(|(key, value)| (key.trim().to_string(), value))
+        .collect();
+    if attributes.len() > MAX_ATTRIBUTES_PER_FILE {
+        return Err(TagError::LimitExceeded);
+    }
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        copy_file, delete_file, empty_trash, list_files, upload_file_atomic, CopyFileRequest, ListFilesRequest,
+        UploadFileAtomicRequest,
+    };
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn upload(state: &mut State, name: &str) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content: vec![1],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state).unwrap()
+    }
+
+    fn tag(state: &mut State, file_id: u64, tags: &[&str]) -> Result<(), TagError> {
+        let request = UpdateFileMetadataRequest {
+            file_id,
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            attributes: None,
+        };
+        update_file_metadata(owner(), request, state)
+    }
+
+    fn tagged(state: &State, tag: &str) -> Vec<String> {
+        let request = ListFilesRequest { tag: Some(tag.to_string()), ..Default::default() };
+        let files = list_files(owner(), request, state).unwrap().files;
+        files.into_iter().map(|f| f.file_name).collect()
+    }
+
+    #[test]
+    fn query_files_by_tag() {
+        let mut state = State::default();
+        let invoice = upload(&mut state, "invoice.pdf");
+        let contract = upload(&mut state, "contract.pdf");
+        tag(&mut state, invoice, &["Finance", "2026"]).unwrap();
+        tag(&mut state, contract, &["finance "]).unwrap();
+
+        assert_eq!(tagged(&state, "finance"), vec!["invoice.pdf", "contract.pdf"]);
+        assert_eq!(tagged(&state, "2026"), vec!["invoice.pdf"]);
+
+        // Replacing the tags updates the index.
+        tag(&mut state, invoice, &["archive"]).unwrap();
+        assert_eq!(tagged(&state, "finance"), vec!["contract.pdf"]);
+        assert!(tagged(&state, "2026").is_empty());
+        assert!(!state.tag_index.contains_key(&(owner(), "2026".to_string())));
+    }
+
+    #[test]
+    fn attributes_and_limits() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, "invoice.pdf");
+
+        let request = UpdateFileMetadataRequest {
+            file_id,
+            tags: None,
+            attributes: Some(vec![("customer".to_string(), "ACME".to_string())]),
+        };
+        assert_eq!(update_file_metadata(other(), request.clone(), &mut state), Err(TagError::PermissionError));
+        update_file_metadata(owner(), request, &mut state).unwrap();
+        let files = list_files(owner(), ListFilesRequest::default(), &state).unwrap().files;
+        assert_eq!(files[0].attributes, vec![("customer".to_string(), "ACME".to_string())]);
+
+        let too_many: Vec<String> = (0..=MAX_TAGS_PER_FILE).map(|i| i.to_string()).collect();
+        let too_many: Vec<&str> = too_many.iter().map(String::as_str).collect();
+        assert_eq!(tag(&mut state, file_id, &too_many), Err(TagError::LimitExceeded));
+        assert_eq!(tag(&mut state, file_id, &[" "]), Err(TagError::InvalidInput));
+    }
+
+    #[test]
+    fn copies_keep_tags_and_purged_files_leave_the_index() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, "invoice.pdf");
+        tag(&mut state, file_id, &["finance"]).unwrap();
+        let copy_id = copy_file(owner(), CopyFileRequest { file_id, name: None }, &mut state).unwrap();
+        assert_eq!(tagged(&state, "finance"), vec!["invoice.pdf", "Copy of invoice.pdf"]);
+
+        delete_file(&mut state, owner(), file_id);
+        delete_file(&mut state, owner(), copy_id);
+        assert!(tagged(&state, "finance").is_empty());
+        empty_trash(owner(), &mut state).unwrap();
+        assert!(state.tag_index.is_empty());
+        assert!(state.file_tags.is_empty());
+    }
+}
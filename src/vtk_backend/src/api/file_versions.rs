@@ -0,0 +1,508 @@
+use crate::api::encrypted_metadata::{self, ENCRYPTED_FILE_TYPE};
+use crate::api::quotas;
+use crate::vetkeys::EncryptedFileData;
+use crate::{FileContent, FileData, FileDownloadResponse, FileVersion, PendingVersion, State, UploadFileError};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum VersionError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// New versions can only be added to, and restored into, fully uploaded files.
+    #[serde(rename = "not_uploaded")]
+    NotUploaded,
+    /// The new version would take the owner over their storage quota.
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadNewVersionRequest {
+    pub file_id: u64,
+    pub content: Vec<u8>,
+    pub file_type: String,
+    pub num_chunks: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileVersionInfo {
+    pub version: u64,
+    pub created_at: u64,
+    pub size: u64,
+    pub num_chunks: u64,
+    pub file_type: String,
+    pub is_current: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PruneVersionsRequest {
+    pub file_id: u64,
+    /// Keep at most this many earlier versions (the most recent ones).
+    pub keep_last: Option<u64>,
+    /// Drop earlier versions older than this many nanoseconds.
+    pub max_age: Option<u64>,
+}
+
+/// Replaces the content of one of the caller's files, keeping its ID, and returns the new
+/// version number. The previous content stays available as an earlier version.
+///
+/// Like `upload_file_atomic`, this carries the first chunk; the remaining chunks are
+/// uploaded with `upload_file_continue`. The file keeps its current content until the last
+/// chunk arrives. Uploading another version before then abandons the unfinished one.
+/// The version number returned for an unfinished version is the one it will get unless
+/// another version is restored first.
+pub fn upload_new_version(
+    caller: Principal,
+    request: UploadNewVersionRequest,
+    state: &mut State,
+) -> Result<u64, VersionError> {
+    check_owner(state, caller, request.file_id)?;
+    if state.is_suspended(caller) {
+        return Err(VersionError::PermissionError);
+    }
+    if request.num_chunks == 0 || state.burn_after_reading.contains_key(&request.file_id) {
+        return Err(VersionError::InvalidInput);
+    }
+    // Files with encrypted metadata keep their type in the encrypted blob.
+    let file_type = if encrypted_metadata::is_encrypted(state, request.file_id) {
+        if !request.file_type.is_empty() {
+            return Err(VersionError::InvalidInput);
+        }
+        ENCRYPTED_FILE_TYPE.to_string()
+    } else {
+        request.file_type
+    };
+
+    let vetkey_metadata = EncryptedFileData {
+        encrypted_content: request.content.clone(),
+        file_owners: vec![caller],
+        encryption_metadata: HashMap::new(),
+    };
+
+    // Previous versions keep their chunks, so the new content takes extra space.
+    quotas::check_quota(state, caller, request.content.len() as u64).map_err(|_| VersionError::QuotaExceeded)?;
+
+    // The new content gets its own storage ID so the previous chunks stay where they are.
+    // Files stored outside the canister have no chunks to write to.
+    let backend = state.file_data[&request.file_id].metadata.storage_provider.backend();
+    let storage_id = state.generate_file_id();
+    backend
+        .put(state, caller, storage_id, 0, request.content)
+        .map_err(|_| VersionError::InvalidInput)?;
+    discard_pending_version(state, request.file_id);
+
+    if request.num_chunks > 1 {
+        let pending = PendingVersion {
+            storage_id,
+            num_chunks: request.num_chunks,
+            file_type,
+            vetkey_metadata,
+        };
+        state.pending_versions.insert(request.file_id, pending);
+        let history = state.file_versions.get(&request.file_id);
+        return Ok(history.map_or(1, |history| history.current_version.max(1)) + 1);
+    }
+    let content = FileContent::Uploaded {
+        num_chunks: request.num_chunks,
+        file_type,
+        vetkey_metadata,
+    };
+    replace_content(state, request.file_id, storage_id, content)
+}
+
+/// Stores a chunk of a version started with `upload_new_version`, and makes it the current
+/// content once all of its chunks are there.
+pub(crate) fn continue_version(state: &mut State, file_id: u64, chunk_id: u64, contents: Vec<u8>) -> Result<(), UploadFileError> {
+    let pending = state.pending_versions.get(&file_id).ok_or(UploadFileError::NotRequested)?;
+    let storage_id = pending.storage_id;
+    let num_chunks = pending.num_chunks;
+    if chunk_id >= num_chunks || state.file_contents.contains_key(&(storage_id, chunk_id)) {
+        return Err(UploadFileError::InvalidInput);
+    }
+    let owner = state.file_data[&file_id].metadata.requester_principal;
+    state.write_chunk(owner, storage_id, chunk_id, contents);
+
+    let uploaded = state.file_contents.range((storage_id, 0)..=(storage_id, num_chunks - 1)).count() as u64;
+    if uploaded == num_chunks {
+        let pending = state.pending_versions.remove(&file_id).ok_or(UploadFileError::NotRequested)?;
+        let content = FileContent::Uploaded {
+            num_chunks,
+            file_type: pending.file_type,
+            vetkey_metadata: pending.vetkey_metadata,
+        };
+        replace_content(state, file_id, storage_id, content).map_err(|_| UploadFileError::NotRequested)?;
+    }
+    Ok(())
+}
+
+/// Lists every version of a file, oldest first. The last one is the current version.
+pub fn list_versions(caller: Principal, file_id: u64, state: &State) -> Result<Vec<FileVersionInfo>, VersionError> {
+    check_reader(state, caller, file_id)?;
+    let file = &state.file_data[&file_id];
+    let history = state.file_versions.get(&file_id);
+
+    let mut versions = previous_versions(state, file_id);
+
+    if let FileContent::Uploaded { num_chunks, file_type, .. }
+    | FileContent::PartiallyUploaded { num_chunks, file_type, .. } = &file.content
+    {
+        versions.push(FileVersionInfo {
+            version: history.map_or(1, |history| history.current_version),
+            created_at: file.metadata.uploaded_at.unwrap_or(file.metadata.requested_at),
+            size: storage_size(state, state.storage_id(file_id), *num_chunks),
+            num_chunks: *num_chunks,
+            file_type: file_type.clone(),
+            is_current: true,
+        });
+    }
+    Ok(versions)
+}
+
+/// The earlier versions of a file, oldest first.
+pub(crate) fn previous_versions(state: &State, file_id: u64) -> Vec<FileVersionInfo> {
+    let history = state.file_versions.get(&file_id);
+    history
+        .into_iter()
+        .flat_map(|history| &history.previous)
+        .map(|version| FileVersionInfo {
+            version: version.version,
+            created_at: version.created_at,
+            size: version.size,
+            num_chunks: version.num_chunks,
+            file_type: version.file_type.clone(),
+            is_current: false,
+        })
+        .collect()
+}
+
+/// Downloads one chunk of an earlier version of a file. The current version is downloaded
+/// with `download_file`.
+pub fn download_version(
+    caller: Principal,
+    file_id: u64,
+    version: u64,
+    chunk_id: u64,
+    state: &State,
+) -> FileDownloadResponse {
+    match check_reader(state, caller, file_id) {
+        Ok(()) => {}
+        Err(VersionError::NotFound) => return FileDownloadResponse::NotFoundFile,
+        Err(_) => return FileDownloadResponse::PermissionError,
+    }
+    if state.is_suspended(caller) {
+        return FileDownloadResponse::PermissionError;
+    }
+    let Some(version) = find_version(state, file_id, version) else {
+        return FileDownloadResponse::NotFoundFile;
+    };
+
+    match state.file_contents.get(&(version.storage_id, chunk_id)) {
+        Some(contents) => FileDownloadResponse::FoundFile(FileData {
+            contents,
+            file_type: version.file_type.clone(),
+            num_chunks: version.num_chunks,
+        }),
+        None => FileDownloadResponse::NotFoundFile,
+    }
+}
+
+/// Makes an earlier version the current content again and returns the new version number.
+/// The content being replaced is kept as an earlier version, so restoring can be undone.
+pub fn restore_version(caller: Principal, file_id: u64, version: u64, state: &mut State) -> Result<u64, VersionError> {
+    check_owner(state, caller, file_id)?;
+    let old = find_version(state, file_id, version).cloned().ok_or(VersionError::NotFound)?;
+
+    // The restored chunks are now referenced by both the old and the current version.
+    state.retain_storage(old.storage_id);
+    let content = FileContent::Uploaded {
+        num_chunks: old.num_chunks,
+        file_type: old.file_type,
+        vetkey_metadata: old.vetkey_metadata,
+    };
+    replace_content(state, file_id, old.storage_id, content)
+}
+
+/// Deletes earlier versions of a file beyond `keep_last` or older than `max_age`, and returns
+/// how many were deleted. The current version is never pruned.
+pub fn prune_versions(caller: Principal, request: PruneVersionsRequest, state: &mut State) -> Result<u64, VersionError> {
+    if caller == Principal::anonymous() {
+        return Err(VersionError::NotAuthenticated);
+    }
+    if !state.file_data.contains_key(&request.file_id) || state.is_trashed(request.file_id) {
+        return Err(VersionError::NotFound);
+    }
+    if !state.is_file_owner(caller, request.file_id) {
+        return Err(VersionError::PermissionError);
+    }
+
+    let now = crate::get_time();
+    let Some(history) = state.file_versions.get_mut(&request.file_id) else {
+        return Ok(0);
+    };
+
+    let keep_from = request
+        .keep_last
+        .map_or(0, |keep| history.previous.len().saturating_sub(keep as usize));
+    let (pruned, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut history.previous)
+        .into_iter()
+        .enumerate()
+        .partition(|(index, version)| {
+            *index < keep_from
+                || request
+                    .max_age
+                    .is_some_and(|max_age| version.created_at.saturating_add(max_age) < now)
+        });
+    history.previous = kept.into_iter().map(|(_, version)| version).collect();
+
+    for (_, version) in &pruned {
+        state.release_storage(version.storage_id, version.num_chunks);
+    }
+    Ok(pruned.len() as u64)
+}
+
+/// Deletes all earlier versions of a file, e.g. when the file itself is purged.
+pub(crate) fn remove_versions(state: &mut State, file_id: u64) {
+    discard_pending_version(state, file_id);
+    for version in state.file_versions.remove(&file_id).map(|h| h.previous).unwrap_or_default() {
+        state.release_storage(version.storage_id, version.num_chunks);
+    }
+}
+
+/// Deletes the chunks of a version that was never finished.
+fn discard_pending_version(state: &mut State, file_id: u64) {
+    if let Some(pending) = state.pending_versions.remove(&file_id) {
+        state.release_storage(pending.storage_id, pending.num_chunks);
+    }
+}
+
+/// Archives the current content of a file as an earlier version and replaces it with
+/// `content`, stored under `storage_id`. Returns the new version number.
+fn replace_content(state: &mut State, file_id: u64, storage_id: u64, content: FileContent) -> Result<u64, VersionError> {
+    let previous_storage_id = state.storage_id(file_id);
+    let file = state.file_data.get_mut(&file_id).ok_or(VersionError::NotFound)?;
+    let created_at = file.metadata.uploaded_at.unwrap_or(file.metadata.requested_at);
+
+    // Only uploaded files get new versions.
+    let FileContent::Uploaded { num_chunks, file_type, vetkey_metadata } = &file.content else {
+        return Err(VersionError::NotUploaded);
+    };
+    let (num_chunks, file_type, vetkey_metadata) = (*num_chunks, file_type.clone(), vetkey_metadata.clone());
+    file.content = content;
+    file.metadata.uploaded_at = Some(crate::get_time());
+
+    let size = storage_size(state, previous_storage_id, num_chunks);
+    let history = state.file_versions.entry(file_id).or_default();
+    let version = history.current_version.max(1);
+    history.previous.push(FileVersion {
+        version,
+        storage_id: previous_storage_id,
+        num_chunks,
+        file_type,
+        size,
+        created_at,
+        vetkey_metadata,
+    });
+    history.current_version = version + 1;
+
+    // The file's reference to its previous chunks now belongs to the archived version.
+    state.file_storage.insert(file_id, storage_id);
+    Ok(version + 1)
+}
+
+fn find_version(state: &State, file_id: u64, version: u64) -> Option<&FileVersion> {
+    state
+        .file_versions
+        .get(&file_id)?
+        .previous
+        .iter()
+        .find(|v| v.version == version)
+}
+
+fn storage_size(state: &State, storage_id: u64, num_chunks: u64) -> u64 {
+    (0..num_chunks)
+        .filter_map(|chunk_id| state.file_contents.get(&(storage_id, chunk_id)))
+        .map(|chunk| chunk.len() as u64)
+        .sum()
+}
+
+fn check_owner(state: &State, caller: Principal, file_id: u64) -> Result<(), VersionError> {
+    if caller == Principal::anonymous() {
+        return Err(VersionError::NotAuthenticated);
+    }
+    let file = match state.file_data.get(&file_id) {
+        Some(file) if !state.is_trashed(file_id) => file,
+        _ => return Err(VersionError::NotFound),
+    };
+    if !state.is_file_owner(caller, file_id) {
+        return Err(VersionError::PermissionError);
+    }
+    if !matches!(file.content, FileContent::Uploaded { .. }) {
+        return Err(VersionError::NotUploaded);
+    }
+    Ok(())
+}
+
+fn check_reader(state: &State, caller: Principal, file_id: u64) -> Result<(), VersionError> {
+    if caller == Principal::anonymous() {
+        return Err(VersionError::NotAuthenticated);
+    }
+    if !state.file_data.contains_key(&file_id) || state.is_trashed(file_id) {
+        return Err(VersionError::NotFound);
+    }
+    if !state.can_read_file(caller, file_id) {
+        return Err(VersionError::PermissionError);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        copy_file, download_file, upload_file_atomic, upload_file_continue, CopyFileRequest, UploadFileAtomicRequest,
+    };
+    use crate::UploadFileContinueRequest;
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn upload(state: &mut State, content: Vec<u8>) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: "report.pdf".to_string(),
+            content,
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state).unwrap()
+    }
+
+    fn new_version(state: &mut State, file_id: u64, content: Vec<u8>) -> Result<u64, VersionError> {
+        let request = UploadNewVersionRequest {
+            file_id,
+            content,
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+        };
+        upload_new_version(owner(), request, state)
+    }
+
+    fn contents(response: FileDownloadResponse) -> Vec<u8> {
+        match response {
+            FileDownloadResponse::FoundFile(data) => data.contents,
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn new_versions_keep_the_file_id() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![1]);
+        assert_eq!(new_version(&mut state, file_id, vec![2, 2]), Ok(2));
+        assert_eq!(new_version(&mut state, file_id, vec![3, 3, 3]), Ok(3));
+
+        assert_eq!(contents(download_file(&state, owner(), file_id, 0)), vec![3, 3, 3]);
+        assert_eq!(contents(download_version(owner(), file_id, 1, 0, &state)), vec![1]);
+        assert_eq!(contents(download_version(owner(), file_id, 2, 0, &state)), vec![2, 2]);
+        assert_eq!(download_version(owner(), file_id, 3, 0, &state), FileDownloadResponse::NotFoundFile);
+        assert_eq!(download_version(other(), file_id, 1, 0, &state), FileDownloadResponse::PermissionError);
+
+        let versions = list_versions(owner(), file_id, &state).unwrap();
+        let summary: Vec<_> = versions.iter().map(|v| (v.version, v.size, v.is_current)).collect();
+        assert_eq!(summary, vec![(1, 1, false), (2, 2, false), (3, 3, true)]);
+    }
+
+    #[test]
+    fn restore_brings_back_an_old_version() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![1]);
+        new_version(&mut state, file_id, vec![2]).unwrap();
+
+        assert_eq!(restore_version(owner(), file_id, 1, &mut state), Ok(3));
+        assert_eq!(contents(download_file(&state, owner(), file_id, 0)), vec![1]);
+
+        // Pruning version 1 keeps the chunks, which the current version still uses.
+        let request = PruneVersionsRequest { file_id, keep_last: Some(1), max_age: None };
+        assert_eq!(prune_versions(owner(), request, &mut state), Ok(1));
+        assert_eq!(contents(download_file(&state, owner(), file_id, 0)), vec![1]);
+        assert_eq!(contents(download_version(owner(), file_id, 2, 0, &state)), vec![2]);
+    }
+
+    #[test]
+    fn prune_by_count_and_age() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![1]);
+        for content in 2..=4 {
+            new_version(&mut state, file_id, vec![content]).unwrap();
+        }
+        let old_storage = state.file_versions[&file_id].previous[0].storage_id;
+        state.file_versions.get_mut(&file_id).unwrap().previous[0].created_at = 0;
+
+        let request = PruneVersionsRequest { file_id, keep_last: None, max_age: Some(1_000) };
+        assert_eq!(prune_versions(owner(), request, &mut state), Ok(1));
+        assert!(!state.file_contents.contains_key(&(old_storage, 0)));
+
+        let request = PruneVersionsRequest { file_id, keep_last: Some(1), max_age: None };
+        assert_eq!(prune_versions(owner(), request, &mut state), Ok(1));
+        let versions: Vec<u64> = state.file_versions[&file_id].previous.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![3]);
+    }
+
+    #[test]
+    fn new_versions_do_not_affect_copies() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![1]);
+        let copy_id = copy_file(owner(), CopyFileRequest { file_id, name: None }, &mut state).unwrap();
+        new_version(&mut state, file_id, vec![2]).unwrap();
+
+        assert_eq!(contents(download_file(&state, owner(), copy_id, 0)), vec![1]);
+        remove_versions(&mut state, file_id);
+        assert_eq!(contents(download_file(&state, owner(), copy_id, 0)), vec![1]);
+    }
+
+    #[test]
+    fn multi_chunk_versions_replace_the_content_once_complete() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![1]);
+        let request = UploadNewVersionRequest {
+            file_id,
+            content: vec![2],
+            file_type: "pdf".to_string(),
+            num_chunks: 2,
+        };
+        assert_eq!(upload_new_version(owner(), request.clone(), &mut state), Ok(2));
+        let abandoned = state.pending_versions[&file_id].storage_id;
+
+        // The unfinished version does not block the file.
+        assert_eq!(contents(download_file(&state, owner(), file_id, 0)), vec![1]);
+        assert_eq!(new_version(&mut state, file_id, vec![3]), Ok(2));
+        assert!(!state.file_contents.contains_key(&(abandoned, 0)));
+        assert!(state.pending_versions.is_empty());
+
+        assert_eq!(upload_new_version(owner(), request, &mut state), Ok(3));
+        assert_eq!(restore_version(owner(), file_id, 1, &mut state), Ok(3));
+        let chunk = UploadFileContinueRequest { file_id, chunk_id: 1, contents: vec![4] };
+        upload_file_continue(owner(), chunk, &mut state).unwrap();
+
+        assert_eq!(contents(download_file(&state, owner(), file_id, 0)), vec![2]);
+        assert_eq!(contents(download_file(&state, owner(), file_id, 1)), vec![4]);
+        let versions: Vec<_> = list_versions(owner(), file_id, &state).unwrap().iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![1, 2, 3, 4]);
+    }
+}
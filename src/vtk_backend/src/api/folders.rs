@@ -0,0 +1,670 @@
+use crate::api::encrypted_metadata;
+use crate::api::list_files::public_file_metadata;
+use crate::{File, FileContent, Folder, PublicFileMetadata, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// Number of entries returned by `list_folder` when no limit is given.
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+/// Upper bound on the number of entries returned by a single `list_folder` call.
+pub const MAX_PAGE_SIZE: u64 = 500;
+
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FolderError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "invalid_name")]
+    InvalidName,
+    /// Another folder or file in the same folder already has this name.
+    #[serde(rename = "name_conflict")]
+    NameConflict,
+    #[serde(rename = "folder_not_empty")]
+    FolderNotEmpty,
+    /// Only fully uploaded files that are not burn-after-reading can be copied.
+    #[serde(rename = "not_copyable")]
+    NotCopyable,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    /// `None` to create the folder in the root folder.
+    pub parent_id: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CopyFileRequest {
+    pub file_id: u64,
+    /// Name of the copy. Defaults to "Copy of <name>".
+    pub name: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ListFolderRequest {
+    /// `None` to list the root folder.
+    pub folder_id: Option<u64>,
+    pub offset: u64,
+    pub limit: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FolderInfo {
+    pub folder_id: u64,
+    pub name: String,
+    pub parent_id: Option<u64>,
+    /// Absolute path of the folder, e.g. `/reports/2026`.
+    pub path: String,
+    pub created_at: u64,
+}
+
+/// One page of a folder's children: subfolders first, then files, each sorted by name.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FolderListing {
+    pub folders: Vec<FolderInfo>,
+    pub files: Vec<PublicFileMetadata>,
+    /// Total number of children, across all pages.
+    pub total: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PathEntry {
+    #[serde(rename = "root")]
+    Root,
+    #[serde(rename = "folder")]
+    Folder(FolderInfo),
+    #[serde(rename = "file")]
+    File(Box<PublicFileMetadata>),
+}
+
+pub fn create_folder(
+    caller: Principal,
+    request: CreateFolderRequest,
+    state: &mut State,
+) -> Result<FolderInfo, FolderError> {
+    if caller == Principal::anonymous() {
+        return Err(FolderError::NotAuthenticated);
+    }
+    check_parent(state, caller, request.parent_id)?;
+    let name = validate_name(&request.name)?;
+    if name_taken(state, caller, request.parent_id, &name) {
+        return Err(FolderError::NameConflict);
+    }
+
+    let folder_id = state.generate_folder_id();
+    state.folders.insert(
+        folder_id,
+        Folder {
+            folder_id,
+            owner: caller,
+            name,
+            parent_id: request.parent_id,
+            created_at: crate::get_time(),
+        },
+    );
+    state.user_folders.entry(caller).or_default().push(folder_id);
+
+    Ok(folder_info(state, folder_id))
+}
+
+pub fn rename_folder(
+    caller: Principal,
+    folder_id: u64,
+    name: String,
+    state: &mut State,
+) -> Result<FolderInfo, FolderError> {
+    let parent_id = owned_folder(state, caller, folder_id)?.parent_id;
+    let name = validate_name(&name)?;
+    if name_taken(state, caller, parent_id, &name) {
+        return Err(FolderError::NameConflict);
+    }
+
+    if let Some(folder) = state.folders.get_mut(&folder_id) {
+        folder.name = name;
+    }
+    Ok(folder_info(state, folder_id))
+}
+
+/// Deletes an empty folder.
+pub fn delete_folder(caller: Principal, folder_id: u64, state: &mut State) -> Result<(), FolderError> {
+    owned_folder(state, caller, folder_id)?;
+    if child_folders(state, caller, Some(folder_id)).next().is_some()
+        || child_files(state, caller, Some(folder_id)).next().is_some()
+    {
+        return Err(FolderError::FolderNotEmpty);
+    }
+
+    state.folders.remove(&folder_id);
+    if let Some(folders) = state.user_folders.get_mut(&caller) {
+        folders.retain(|&id| id != folder_id);
+    }
+    Ok(())
+}
+
+/// Moves one of the caller's files into a folder (or, with `None`, into the root folder).
+pub fn move_file(
+    caller: Principal,
+    file_id: u64,
+    folder_id: Option<u64>,
+    state: &mut State,
+) -> Result<(), FolderError> {
+    let file = owned_file(state, caller, file_id)?;
+    check_parent(state, caller, folder_id)?;
+
+    if state.file_folders.get(&file_id).copied() == folder_id {
+        return Ok(());
+    }
+    if name_taken(state, caller, folder_id, &file.metadata.file_name) {
+        return Err(FolderError::NameConflict);
+    }
+
+    match folder_id {
+        Some(folder_id) => state.file_folders.insert(file_id, folder_id),
+        None => state.file_folders.remove(&file_id),
+    };
+    Ok(())
+}
+
+/// Renames one of the caller's files, keeping it in its folder.
+pub fn rename_file(caller: Principal, file_id: u64, name: String, state: &mut State) -> Result<(), FolderError> {
+    let file = owned_file(state, caller, file_id)?;
+    if encrypted_metadata::is_encrypted(state, file_id) {
+        // Encrypted names are changed with `set_encrypted_metadata`.
+        return Err(FolderError::InvalidName);
+    }
+    let name = validate_name(&name)?;
+    if file.metadata.file_name == name {
+        return Ok(());
+    }
+    if name_taken(state, caller, state.file_folders.get(&file_id).copied(), &name) {
+        return Err(FolderError::NameConflict);
+    }
+
+    if let Some(file) = state.file_data.get_mut(&file_id) {
+        file.metadata.file_name = name;
+    }
+    Ok(())
+}
+
+/// Copies one of the caller's files into the same folder and returns the ID of the copy.
+///
+/// The copy shares the chunks of the original instead of duplicating them; they are
+/// only deleted once neither file uses them anymore.
+pub fn copy_file(caller: Principal, request: CopyFileRequest, state: &mut State) -> Result<u64, FolderError> {
+    let file = owned_file(state, caller, request.file_id)?;
+    if state.is_suspended(caller) {
+        return Err(FolderError::PermissionError);
+    }
+    if !matches!(file.content, FileContent::Uploaded { .. })
+        || state.burn_after_reading.contains_key(&request.file_id)
+    {
+        return Err(FolderError::NotCopyable);
+    }
+
+    let encrypted = state.encrypted_file_metadata.get(&request.file_id).cloned();
+    let explicit_name = request.name.is_some();
+    let name = match (&encrypted, request.name) {
+        // The copy gets its own placeholder below.
+        (Some(_), None) => String::new(),
+        // A plaintext name would leak what the encrypted one hides.
+        (Some(_), Some(_)) => return Err(FolderError::InvalidName),
+        (None, Some(name)) => validate_name(&name)?,
+        (None, None) => validate_name(&format!("Copy of {}", file.metadata.file_name))?,
+    };
+    let folder_id = state.file_folders.get(&request.file_id).copied();
+    let name = match (&encrypted, explicit_name) {
+        (Some(_), _) => name,
+        (None, true) if name_taken(state, caller, folder_id, &name) => return Err(FolderError::NameConflict),
+        (None, true) => name,
+        // Default names are made unique instead of rejected.
+        (None, false) => unique_name(state, caller, folder_id, &name),
+    };
+
+    let mut copy = file.clone();
+    copy.metadata.file_name = name;
+    copy.metadata.requested_at = crate::get_time();
+    copy.metadata.uploaded_at = Some(crate::get_time());
+    if let FileContent::Uploaded { vetkey_metadata, .. } = &mut copy.content {
+        // The content lives in the shared chunks.
+        vetkey_metadata.encrypted_content = vec![];
+    }
+
+    let copy_id = state.generate_file_id();
+    if let Some(encrypted) = encrypted {
+        copy.metadata.file_name = encrypted_metadata::placeholder_name(copy_id);
+        state.encrypted_file_metadata.insert(copy_id, encrypted);
+    }
+    // The shared chunks stay encrypted under the original file's key.
+    if let Some(key) = state.file_group_keys.get(&request.file_id).copied() {
+        state.file_group_keys.insert(copy_id, key);
+    }
+    state.file_data.insert(copy_id, copy);
+    state.add_owned_file(caller, copy_id);
+    if let Some(folder_id) = folder_id {
+        state.file_folders.insert(copy_id, folder_id);
+    }
+    state.share_chunks(request.file_id, copy_id);
+    crate::api::file_tags::copy_metadata(state, caller, request.file_id, copy_id);
+
+    Ok(copy_id)
+}
+
+/// Lists one page of the children of a folder.
+pub fn list_folder(caller: Principal, request: ListFolderRequest, state: &State) -> Result<FolderListing, FolderError> {
+    if caller == Principal::anonymous() {
+        return Err(FolderError::NotAuthenticated);
+    }
+    check_parent(state, caller, request.folder_id)?;
+
+    let mut folders: Vec<&Folder> = child_folders(state, caller, request.folder_id).collect();
+    folders.sort_by(|a, b| a.name.cmp(&b.name).then(a.folder_id.cmp(&b.folder_id)));
+    let mut files: Vec<(&str, u64)> = child_files(state, caller, request.folder_id).collect();
+    files.sort();
+
+    let total = (folders.len() + files.len()) as u64;
+    let offset = request.offset.min(total) as usize;
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize;
+
+    // Folders come before files, so the page may span both lists.
+    let folder_page: Vec<FolderInfo> = folders
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|folder| folder_info(state, folder.folder_id))
+        .collect();
+    let file_page = files
+        .iter()
+        .skip(offset.saturating_sub(folders.len()))
+        .take(limit - folder_page.len())
+        .filter_map(|&(_, file_id)| public_file_metadata(state, caller, file_id))
+        .collect();
+
+    Ok(FolderListing {
+        folders: folder_page,
+        files: file_page,
+        total,
+    })
+}
+
+/// Resolves an absolute path such as `/reports/2026/q3.pdf` in the caller's file tree.
+///
+/// Files uploaded before folders existed may share a name in the root folder; in that case
+/// the oldest one is returned.
+pub fn resolve_path(caller: Principal, path: String, state: &State) -> Result<PathEntry, FolderError> {
+    if caller == Principal::anonymous() {
+        return Err(FolderError::NotAuthenticated);
+    }
+    let Some(relative) = path.strip_prefix('/') else {
+        return Err(FolderError::InvalidName);
+    };
+
+    let segments: Vec<&str> = relative.split('/').filter(|s| !s.is_empty()).collect();
+    let Some((last, parents)) = segments.split_last() else {
+        return Ok(PathEntry::Root);
+    };
+
+    let mut current = None;
+    for segment in parents {
+        current = Some(find_folder(state, caller, current, segment).ok_or(FolderError::NotFound)?);
+    }
+
+    if let Some(folder_id) = find_folder(state, caller, current, last) {
+        return Ok(PathEntry::Folder(folder_info(state, folder_id)));
+    }
+    child_files(state, caller, current)
+        .filter(|&(name, _)| name == *last)
+        .map(|(_, file_id)| file_id)
+        .min()
+        .and_then(|file_id| public_file_metadata(state, caller, file_id))
+        .map(|file| PathEntry::File(Box::new(file)))
+        .ok_or(FolderError::NotFound)
+}
+
+/// Detaches a deleted file from its folder.
+pub(crate) fn remove_file(state: &mut State, file_id: u64) {
+    state.file_folders.remove(&file_id);
+}
+
+fn owned_folder(state: &State, caller: Principal, folder_id: u64) -> Result<&Folder, FolderError> {
+    if caller == Principal::anonymous() {
+        return Err(FolderError::NotAuthenticated);
+    }
+    match state.folders.get(&folder_id) {
+        None => Err(FolderError::NotFound),
+        Some(folder) if folder.owner != caller => Err(FolderError::PermissionError),
+        Some(folder) => Ok(folder),
+    }
+}
+
+fn owned_file(state: &State, caller: Principal, file_id: u64) -> Result<&File, FolderError> {
+    if caller == Principal::anonymous() {
+        return Err(FolderError::NotAuthenticated);
+    }
+    let file = state.file_data.get(&file_id).ok_or(FolderError::NotFound)?;
+    if !state.is_file_owner(caller, file_id) {
+        return Err(FolderError::PermissionError);
+    }
+    Ok(file)
+}
+
+fn check_parent(state: &State, caller: Principal, parent_id: Option<u64>) -> Result<(), FolderError> {
+    match parent_id {
+        Some(parent_id) => owned_folder(state, caller, parent_id).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+fn validate_name(name: &str) -> Result<String, FolderError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || name.contains('/') || name == "." || name == ".." {
+        return Err(FolderError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+fn child_folders(state: &State, owner: Principal, parent_id: Option<u64>) -> impl Iterator<Item = &Folder> {
+    state
+        .user_folders
+        .get(&owner)
+        .into_iter()
+        .flatten()
+        .filter_map(|folder_id| state.folders.get(folder_id))
+        .filter(move |folder| folder.parent_id == parent_id)
+}
+
+fn child_files(state: &State, owner: Principal, folder_id: Option<u64>) -> impl Iterator<Item = (&str, u64)> {
+    state
+        .file_owners
+        .get(&owner)
+        .into_iter()
+        .flatten()
+        .filter(move |file_id| state.file_folders.get(file_id).copied() == folder_id)
+        .filter_map(|&file_id| {
+            let file = state.file_data.get(&file_id)?;
+            Some((file.metadata.file_name.as_str(), file_id))
+        })
+}
+
+fn find_folder(state: &State, owner: Principal, parent_id: Option<u64>, name: &str) -> Option<u64> {
+    child_folders(state, owner, parent_id)
+        .find(|folder| folder.name == name)
+        .map(|folder| folder.folder_id)
+}
+
+/// Names must be unique among the subfolders and files of a folder.
+pub(crate) fn name_taken(state: &State, owner: Principal, parent_id: Option<u64>, name: &str) -> bool {
+    find_folder(state, owner, parent_id, name).is_some()
+        || child_files(state, owner, parent_id).any(|(file_name, _)| file_name == name)
+}
+
+/// `name`, or `name (n)` with the lowest `n` that is free in the folder.
+pub(crate) fn unique_name(state: &State, owner: Principal, parent_id: Option<u64>, name: &str) -> String {
+    let mut unique_name = name.to_string();
+    let mut n = 1;
+    while name_taken(state, owner, parent_id, &unique_name) {
+        unique_name = format!("{} ({})", name, n);
+        n += 1;
+    }
+    unique_name
+}
+
+fn folder_info(state: &State, folder_id: u64) -> FolderInfo {
+    let folder = &state.folders[&folder_id];
+
+    let mut names = vec![folder.name.as_str()];
+    let mut parent_id = folder.parent_id;
+    while let Some(parent) = parent_id.and_then(|id| state.folders.get(&id)) {
+        names.push(parent.name.as_str());
+        parent_id = parent.parent_id;
+    }
+    names.reverse();
+
+    FolderInfo {
+        folder_id,
+        name: folder.name.clone(),
+        parent_id: folder.parent_id,
+        path: format!("/{}", names.join("/")),
+        created_at: folder.created_at,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{upload_file_atomic, UploadFileAtomicRequest};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn upload(state: &mut State, name: &str) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content: vec![1, 2, 3],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state).unwrap()
+    }
+
+    fn mkdir(state: &mut State, name: &str, parent_id: Option<u64>) -> Result<FolderInfo, FolderError> {
+        let request = CreateFolderRequest {
+            name: name.to_string(),
+            parent_id,
+        };
+        create_folder(owner(), request, state)
+    }
+
+    #[test]
+    fn resolve_nested_path() {
+        let mut state = State::default();
+        let reports = mkdir(&mut state, "reports", None).unwrap();
+        let year = mkdir(&mut state, "2026", Some(reports.folder_id)).unwrap();
+        assert_eq!(year.path, "/reports/2026");
+
+        let file_id = upload(&mut state, "q3.pdf");
+        move_file(owner(), file_id, Some(year.folder_id), &mut state).unwrap();
+
+        match resolve_path(owner(), "/reports/2026/q3.pdf".to_string(), &state) {
+            Ok(PathEntry::File(file)) => {
+                assert_eq!(file.file_id, file_id);
+                assert_eq!(file.folder_id, Some(year.folder_id));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(resolve_path(owner(), "/reports/2026/".to_string(), &state), Ok(PathEntry::Folder(year)));
+        assert_eq!(resolve_path(owner(), "/".to_string(), &state), Ok(PathEntry::Root));
+        assert_eq!(
+            resolve_path(owner(), "/q3.pdf".to_string(), &state),
+            Err(FolderError::NotFound)
+        );
+        // Paths are resolved in the caller's own tree.
+        assert_eq!(
+            resolve_path(other(), "/reports".to_string(), &state),
+            Err(FolderError::NotFound)
+        );
+    }
+
+    #[test]
+    fn names_must_be_unique_within_a_folder() {
+        let mut state = State::default();
+        let reports = mkdir(&mut state, "reports", None).unwrap();
+        assert_eq!(mkdir(&mut state, "reports", None), Err(FolderError::NameConflict));
+        assert!(mkdir(&mut state, "reports", Some(reports.folder_id)).is_ok());
+
+        let first = upload(&mut state, "q3.pdf");
+        move_file(owner(), first, Some(reports.folder_id), &mut state).unwrap();
+        let second = upload(&mut state, "q3.pdf");
+        assert_eq!(
+            move_file(owner(), second, Some(reports.folder_id), &mut state),
+            Err(FolderError::NameConflict)
+        );
+        assert_eq!(mkdir(&mut state, "q3.pdf", Some(reports.folder_id)), Err(FolderError::NameConflict));
+
+        let archive = mkdir(&mut state, "archive", None).unwrap();
+        assert_eq!(
+            rename_folder(owner(), archive.folder_id, "reports".to_string(), &mut state),
+            Err(FolderError::NameConflict)
+        );
+        assert_eq!(mkdir(&mut state, "a/b", None), Err(FolderError::InvalidName));
+    }
+
+    #[test]
+    fn list_folder_paginates_folders_then_files() {
+        let mut state = State::default();
+        for name in ["b", "a"] {
+            mkdir(&mut state, name, None).unwrap();
+        }
+        for name in ["z.txt", "y.txt", "x.txt"] {
+            upload(&mut state, name);
+        }
+
+        let page = |offset, limit| {
+            let request = ListFolderRequest {
+                folder_id: None,
+                offset,
+                limit: Some(limit),
+            };
+            let listing = list_folder(owner(), request, &state).unwrap();
+            assert_eq!(listing.total, 5);
+            let folders = listing.folders.into_iter().map(|f| f.name);
+            folders.chain(listing.files.into_iter().map(|f| f.file_name)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(page(0, 3), vec!["a", "b", "x.txt"]);
+        assert_eq!(page(3, 3), vec!["y.txt", "z.txt"]);
+        assert!(page(5, 3).is_empty());
+    }
+
+    #[test]
+    fn rename_file_checks_collisions() {
+        let mut state = State::default();
+        let first = upload(&mut state, "q3.pdf");
+        upload(&mut state, "q4.pdf");
+
+        assert_eq!(
+            rename_file(owner(), first, "q4.pdf".to_string(), &mut state),
+            Err(FolderError::NameConflict)
+        );
+        assert_eq!(
+            rename_file(other(), first, "q2.pdf".to_string(), &mut state),
+            Err(FolderError::PermissionError)
+        );
+        assert_eq!(rename_file(owner(), first, "q2.pdf".to_string(), &mut state), Ok(()));
+        assert_eq!(state.file_data[&first].metadata.file_name, "q2.pdf");
+    }
+
+    #[test]
+    fn new_files_get_unique_names() {
+        let mut state = State::seeded();
+        let first = upload(&mut state, "q3.pdf");
+        let second = upload(&mut state, "q3.pdf");
+        let request = crate::api::RequestFileRequest {
+            file_name: "q3.pdf".to_string(),
+            allow_anonymous: false,
+        };
+        let requested = crate::api::request_file(owner(), request, &mut state).unwrap().file_id;
+        let request = crate::api::RegisterFileRequest {
+            file_name: "q3.pdf".to_string(),
+            storage_provider: crate::StorageProvider::Icp,
+            blob_id: None,
+            requested_at: 0,
+            uploaded_at: None,
+            size: None,
+        };
+        let registered = crate::api::register_file(owner(), request, &mut state).unwrap().file_id;
+        let copy = copy_file(owner(), CopyFileRequest { file_id: first, name: None }, &mut state).unwrap();
+        let second_copy = copy_file(owner(), CopyFileRequest { file_id: first, name: None }, &mut state).unwrap();
+
+        let names = [first, second, requested, registered, copy, second_copy]
+            .map(|file_id| state.file_data[&file_id].metadata.file_name.clone());
+        assert_eq!(
+            names,
+            ["q3.pdf", "q3.pdf (1)", "q3.pdf (2)", "q3.pdf (3)", "Copy of q3.pdf", "Copy of q3.pdf (1)"]
+        );
+        // Explicit names are not changed behind the caller's back.
+        assert_eq!(
+            copy_file(owner(), CopyFileRequest { file_id: first, name: Some("q3.pdf".to_string()) }, &mut state),
+            Err(FolderError::NameConflict)
+        );
+        match resolve_path(owner(), "/q3.pdf".to_string(), &state) {
+            Ok(PathEntry::File(file)) => assert_eq!(file.file_id, first),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn copies_share_chunks_until_the_last_one_is_deleted() {
+        let mut state = State::default();
+        let original = upload(&mut state, "q3.pdf");
+        let copy = copy_file(owner(), CopyFileRequest { file_id: original, name: None }, &mut state).unwrap();
+        let copy_of_copy = copy_file(owner(), CopyFileRequest { file_id: copy, name: None }, &mut state).unwrap();
+
+        assert_eq!(state.file_data[&copy].metadata.file_name, "Copy of q3.pdf");
+        assert_eq!(state.file_data[&copy_of_copy].metadata.file_name, "Copy of Copy of q3.pdf");
+        // No bytes were duplicated.
+        assert!(!state.file_contents.contains_key(&(copy, 0)));
+        assert_eq!(state.storage_refs[&original], 3);
+
+        crate::api::delete_file(&mut state, owner(), original);
+        crate::api::delete_file(&mut state, owner(), copy);
+        assert!(matches!(
+            crate::api::download_file(&state, owner(), copy_of_copy, 0),
+            crate::FileDownloadResponse::FoundFile(_)
+        ));
+
+        crate::api::delete_file(&mut state, owner(), copy_of_copy);
+        crate::api::empty_trash(owner(), &mut state).unwrap();
+        assert!(!state.file_contents.contains_key(&(original, 0)));
+        assert!(state.storage_refs.is_empty());
+        assert!(state.file_storage.is_empty());
+    }
+
+    #[test]
+    fn burn_after_reading_files_cannot_be_copied() {
+        let mut state = State::default();
+        let request = UploadFileAtomicRequest {
+            name: "secret.txt".to_string(),
+            content: vec![1],
+            file_type: "txt".to_string(),
+            num_chunks: 1,
+            burn_after_reading: Some(true),
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = upload_file_atomic(owner(), request, &mut state).unwrap();
+        assert_eq!(
+            copy_file(owner(), CopyFileRequest { file_id, name: None }, &mut state),
+            Err(FolderError::NotCopyable)
+        );
+    }
+
+    #[test]
+    fn only_empty_folders_can_be_deleted() {
+        let mut state = State::default();
+        let reports = mkdir(&mut state, "reports", None).unwrap();
+        let file_id = upload(&mut state, "q3.pdf");
+        move_file(owner(), file_id, Some(reports.folder_id), &mut state).unwrap();
+
+        assert_eq!(delete_folder(other(), reports.folder_id, &mut state), Err(FolderError::PermissionError));
+        assert_eq!(delete_folder(owner(), reports.folder_id, &mut state), Err(FolderError::FolderNotEmpty));
+
+        move_file(owner(), file_id, None, &mut state).unwrap();
+        assert_eq!(delete_folder(owner(), reports.folder_id, &mut state), Ok(()));
+        assert!(!state.folders.contains_key(&reports.folder_id));
+    }
+}
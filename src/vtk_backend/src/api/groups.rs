@@ -0,0 +1,664 @@
+use crate::{Group, GroupInvite, GroupKey, GroupRole, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum GroupError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "user_not_found")]
+    UserNotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// The user is already a member of the group, or already invited to it.
+    #[serde(rename = "already_member")]
+    AlreadyMember,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GroupMemberInfo {
+    pub principal: Principal,
+    pub username: Option<String>,
+    pub role: GroupRole,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub group_id: u64,
+    pub name: String,
+    pub alias: String,
+    pub created_at: u64,
+    /// The caller's role in the group.
+    pub role: GroupRole,
+    pub members: Vec<GroupMemberInfo>,
+    /// Users invited to the group who have not accepted yet, with the role they were offered.
+    pub invited: Vec<GroupMemberInfo>,
+    pub file_ids: Vec<u64>,
+    /// Version of the group's vetKey that new group files are encrypted under.
+    pub key_version: u64,
+}
+
+/// An invitation to join a group, as shown to the invited user.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GroupInviteInfo {
+    pub group_id: u64,
+    pub group_name: String,
+    pub role: GroupRole,
+    pub invited_by: Principal,
+    pub invited_at: u64,
+}
+
+/// Creates a group with the caller as its only owner.
+pub fn create_group(caller: Principal, name: String, state: &mut State) -> Result<GroupInfo, GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > 100 {
+        return Err(GroupError::InvalidInput);
+    }
+
+    let group_id = state.generate_group_id();
+    let alias = state.generate_token();
+    state.groups.insert(
+        group_id,
+        Group {
+            group_id,
+            name,
+            alias,
+            created_by: caller,
+            created_at: crate::get_time(),
+            members: BTreeMap::from([(caller, GroupRole::Owner)]),
+            key_version: 0,
+        },
+    );
+    state.user_groups.entry(caller).or_default().push(group_id);
+
+    Ok(group_info(state, group_id, caller))
+}
+
+/// Invites a user to a group with the given role. They become a member once they accept
+/// with `accept_group_invite`. Only group owners can invite users.
+pub fn invite_group_member(
+    caller: Principal,
+    group_id: u64,
+    username: String,
+    role: GroupRole,
+    state: &mut State,
+) -> Result<GroupInfo, GroupError> {
+    require_role(state, caller, group_id, GroupRole::Owner)?;
+    if state.is_suspended(caller) {
+        return Err(GroupError::PermissionError);
+    }
+    let user = resolve_username(state, &username)?;
+    if state.group_role(group_id, user).is_some() || has_invite(state, user, group_id) {
+        return Err(GroupError::AlreadyMember);
+    }
+
+    let invite = GroupInvite {
+        role,
+        invited_by: caller,
+        invited_at: crate::get_time(),
+    };
+    state.group_invites.entry(user).or_default().insert(group_id, invite);
+    Ok(group_info(state, group_id, caller))
+}
+
+/// Changes the role of a member of a group. Only group owners can change roles.
+pub fn set_group_member_role(
+    caller: Principal,
+    group_id: u64,
+    username: String,
+    role: GroupRole,
+    state: &mut State,
+) -> Result<GroupInfo, GroupError> {
+    require_role(state, caller, group_id, GroupRole::Owner)?;
+    if state.is_suspended(caller) {
+        return Err(GroupError::PermissionError);
+    }
+    let member = resolve_username(state, &username)?;
+    if state.group_role(group_id, member).is_none() {
+        return Err(GroupError::UserNotFound);
+    }
+    if member == caller && role != GroupRole::Owner && owner_count(state, group_id) == 1 {
+        // The last owner cannot demote themselves.
+        return Err(GroupError::InvalidInput);
+    }
+
+    if let Some(group) = state.groups.get_mut(&group_id) {
+        group.members.insert(member, role);
+    }
+    Ok(group_info(state, group_id, caller))
+}
+
+/// Joins a group the caller was invited to, with the role they were offered.
+pub fn accept_group_invite(caller: Principal, group_id: u64, state: &mut State) -> Result<GroupInfo, GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    let invite = take_invite(state, caller, group_id).ok_or(GroupError::NotFound)?;
+    let group = state.groups.get_mut(&group_id).ok_or(GroupError::NotFound)?;
+    group.members.insert(caller, invite.role);
+    state.user_groups.entry(caller).or_default().push(group_id);
+    Ok(group_info(state, group_id, caller))
+}
+
+/// Turns down an invitation to join a group.
+pub fn decline_group_invite(caller: Principal, group_id: u64, state: &mut State) -> Result<(), GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    take_invite(state, caller, group_id).map(|_| ()).ok_or(GroupError::NotFound)
+}
+
+/// The invitations waiting for the caller to accept or decline them.
+pub fn list_group_invites(caller: Principal, state: &State) -> Vec<GroupInviteInfo> {
+    state
+        .group_invites
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter_map(|(&group_id, invite)| {
+            let group = state.groups.get(&group_id)?;
+            Some(GroupInviteInfo {
+                group_id,
+                group_name: group.name.clone(),
+                role: invite.role,
+                invited_by: invite.invited_by,
+                invited_at: invite.invited_at,
+            })
+        })
+        .collect()
+}
+
+/// Removes a user from a group. Owners can remove anyone; other members can only leave.
+/// A group always keeps at least one owner.
+pub fn remove_group_member(
+    caller: Principal,
+    group_id: u64,
+    username: String,
+    state: &mut State,
+) -> Result<(), GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    let member = resolve_username(state, &username)?;
+    if member != caller {
+        require_role(state, caller, group_id, GroupRole::Owner)?;
+    }
+
+    match state.group_role(group_id, member) {
+        None => return Err(GroupError::UserNotFound),
+        Some(GroupRole::Owner) if owner_count(state, group_id) == 1 => {
+            return Err(GroupError::InvalidInput)
+        }
+        Some(_) => {}
+    }
+
+    remove_member(state, group_id, member);
+    Ok(())
+}
+
+/// Assigns one of the caller's files to a group (or, with `None`, takes it out of its group).
+/// The caller must own the file and be an owner or editor of the target group.
+pub fn assign_file_to_group(
+    caller: Principal,
+    file_id: u64,
+    group_id: Option<u64>,
+    state: &mut State,
+) -> Result<(), GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    if !state.file_data.contains_key(&file_id) {
+        return Err(GroupError::NotFound);
+    }
+    if !state.is_file_owner(caller, file_id) {
+        return Err(GroupError::PermissionError);
+    }
+
+    if let Some(group_id) = group_id {
+        require_role(state, caller, group_id, GroupRole::Editor)?;
+        if state.is_suspended(caller) {
+            return Err(GroupError::PermissionError);
+        }
+        // Reads by group members are not tracked, so they would bypass burn-after-reading.
+        if state.burn_after_reading.contains_key(&file_id) {
+            return Err(GroupError::InvalidInput);
+        }
+    }
+
+    unassign_file(state, file_id);
+    if let Some(group_id) = group_id {
+        state.file_groups.insert(file_id, group_id);
+        state.group_files.entry(group_id).or_default().push(file_id);
+    }
+    Ok(())
+}
+
+/// The groups the caller is a member of.
+pub fn list_groups(caller: Principal, state: &State) -> Vec<GroupInfo> {
+    state
+        .user_groups
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter(|group_id| state.groups.contains_key(group_id))
+        .map(|&group_id| group_info(state, group_id, caller))
+        .collect()
+}
+
+/// The vetKey derivation ID a file is encrypted under, if `caller` may decrypt it.
+///
+/// A file's content is encrypted under its owner's key unless it was uploaded for a group,
+/// in which case it is encrypted under a version of the group's key. Assigning a file to a
+/// group does not change the key: members can decrypt it once its owner has uploaded it
+/// again, encrypted under the group's key. Owners can always decrypt their files.
+pub fn vetkey_derivation_id(state: &State, caller: Principal, file_id: u64) -> Result<Vec<u8>, String> {
+    let file = state.file_data.get(&file_id).ok_or_else(|| "File not found".to_string())?;
+    if !state.can_read_file(caller, file_id) {
+        return Err("Permission denied".to_string());
+    }
+    let is_owner = state.is_file_owner(caller, file_id);
+    match state.file_group_keys.get(&file_id) {
+        Some(&key) if is_owner || state.file_groups.get(&file_id) == Some(&key.group_id) => {
+            Ok(group_derivation_id(key))
+        }
+        None if is_owner => Ok(file.metadata.requester_principal.as_slice().to_vec()),
+        _ => Err("The file is not encrypted for its group".to_string()),
+    }
+}
+
+/// The vetKey derivation ID a file is encrypted under, whoever asks.
+pub(crate) fn file_derivation_id(state: &State, file_id: u64) -> Vec<u8> {
+    match (state.file_group_keys.get(&file_id), state.file_data.get(&file_id)) {
+        (Some(&key), _) => group_derivation_id(key),
+        (None, Some(file)) => file.metadata.requester_principal.as_slice().to_vec(),
+        (None, None) => Vec::new(),
+    }
+}
+
+/// The vetKey derivation ID that files uploaded into a group must be encrypted under.
+/// Only editors and owners of the group may upload into it.
+pub fn group_upload_derivation_id(state: &State, caller: Principal, group_id: u64) -> Result<Vec<u8>, String> {
+    let key = upload_key(state, caller, group_id).map_err(|_| "Permission denied".to_string())?;
+    Ok(group_derivation_id(key))
+}
+
+pub fn group_derivation_id(key: GroupKey) -> Vec<u8> {
+    [b"group:".as_slice(), &key.group_id.to_be_bytes(), &key.version.to_be_bytes()].concat()
+}
+
+/// Checks that `caller` may upload files into a group and returns the key they must be
+/// encrypted under.
+pub(crate) fn upload_key(state: &State, caller: Principal, group_id: u64) -> Result<GroupKey, GroupError> {
+    require_role(state, caller, group_id, GroupRole::Editor)?;
+    Ok(GroupKey {
+        group_id,
+        version: state.groups[&group_id].key_version,
+    })
+}
+
+/// Records that a file was uploaded for a group: it is assigned to the group and its
+/// content is encrypted under `key`.
+pub(crate) fn add_group_file(state: &mut State, file_id: u64, key: GroupKey) {
+    unassign_file(state, file_id);
+    state.file_groups.insert(file_id, key.group_id);
+    state.group_files.entry(key.group_id).or_default().push(file_id);
+    state.file_group_keys.insert(file_id, key);
+}
+
+/// Removes a file from the group it is assigned to, if any.
+pub(crate) fn unassign_file(state: &mut State, file_id: u64) {
+    if let Some(group_id) = state.file_groups.remove(&file_id) {
+        if let Some(files) = state.group_files.get_mut(&group_id) {
+            files.retain(|&id| id != file_id);
+        }
+    }
+}
+
+/// Removes a member from a group and rotates the group's key.
+pub(crate) fn remove_member(state: &mut State, group_id: u64, member: Principal) {
+    if let Some(group) = state.groups.get_mut(&group_id) {
+        group.members.remove(&member);
+        group.key_version += 1;
+    }
+    if let Some(groups) = state.user_groups.get_mut(&member) {
+        groups.retain(|&id| id != group_id);
+    }
+}
+
+/// Removes a deleted user from all their groups and returns how many they left. A group
+/// losing its last owner passes ownership to its most privileged remaining member, and a
+/// group left without members is deleted.
+pub(crate) fn leave_all_groups(state: &mut State, member: Principal) -> u64 {
+    state.group_invites.remove(&member);
+    let group_ids = state.user_groups.remove(&member).unwrap_or_default();
+    for &group_id in &group_ids {
+        let Some(group) = state.groups.get_mut(&group_id) else {
+            continue;
+        };
+        group.members.remove(&member);
+        group.key_version += 1;
+        if group.members.is_empty() {
+            state.groups.remove(&group_id);
+            for invites in state.group_invites.values_mut() {
+                invites.remove(&group_id);
+            }
+            for file_id in state.group_files.remove(&group_id).unwrap_or_default() {
+                state.file_groups.remove(&file_id);
+            }
+        } else if !group.members.values().any(|&role| role == GroupRole::Owner) {
+            if let Some((_, role)) = group.members.iter_mut().min_by_key(|(_, role)| **role) {
+                *role = GroupRole::Owner;
+            }
+        }
+    }
+    group_ids.len() as u64
+}
+
+fn require_role(state: &State, caller: Principal, group_id: u64, minimum: GroupRole) -> Result<(), GroupError> {
+    if caller == Principal::anonymous() {
+        return Err(GroupError::NotAuthenticated);
+    }
+    if !state.groups.contains_key(&group_id) {
+        return Err(GroupError::NotFound);
+    }
+    // Roles are ordered from most to least privileged.
+    match state.group_role(group_id, caller) {
+        Some(role) if role <= minimum => Ok(()),
+        _ => Err(GroupError::PermissionError),
+    }
+}
+
+fn has_invite(state: &State, user: Principal, group_id: u64) -> bool {
+    state.group_invites.get(&user).is_some_and(|invites| invites.contains_key(&group_id))
+}
+
+fn take_invite(state: &mut State, user: Principal, group_id: u64) -> Option<GroupInvite> {
+    let invites = state.group_invites.get_mut(&user)?;
+    let invite = invites.remove(&group_id);
+    if invites.is_empty() {
+        state.group_invites.remove(&user);
+    }
+    invite
+}
+
+fn owner_count(state: &State, group_id: u64) -> usize {
+    state.groups.get(&group_id).map_or(0, |group| {
+        group.members.values().filter(|&&role| role == GroupRole::Owner).count()
+    })
+}
+
+fn resolve_username(state: &State, username: &str) -> Result<Principal, GroupError> {
+    state
+        .username_to_principal
+        .get(&username.trim().to_lowercase())
+        .copied()
+        .ok_or(GroupError::UserNotFound)
+}
+
+fn group_info(state: &State, group_id: u64, caller: Principal) -> GroupInfo {
+    let group = &state.groups[&group_id];
+    GroupInfo {
+        group_id,
+        name: group.name.clone(),
+        alias: group.alias.clone(),
+        created_at: group.created_at,
+        role: group.members[&caller],
+        members: group
+            .members
+            .iter()
+            .map(|(&principal, &role)| GroupMemberInfo {
+                principal,
+                username: state.user_profiles.get(&principal).map(|p| p.username.clone()),
+                role,
+            })
+            .collect(),
+        invited: state
+            .group_invites
+            .iter()
+            .filter_map(|(&principal, invites)| invites.get(&group_id).map(|invite| (principal, invite.role)))
+            .map(|(principal, role)| GroupMemberInfo {
+                principal,
+                username: state.user_profiles.get(&principal).map(|p| p.username.clone()),
+                role,
+            })
+            .collect(),
+        file_ids: state
+            .group_files
+            .get(&group_id)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&file_id| !state.is_trashed(file_id))
+            .collect(),
+        key_version: group.key_version,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{create_user_profile, download_file, list_files, upload_file_atomic, UploadFileAtomicRequest};
+    use crate::{CreateUserRequest, FileDownloadResponse};
+
+    fn alice() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn bob() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn carol() -> Principal {
+        Principal::from_slice(&[1, 2, 3])
+    }
+
+    fn setup() -> State {
+        let mut state = State::seeded();
+        for (principal, username) in [(alice(), "alice"), (bob(), "bob"), (carol(), "carol")] {
+            let request = CreateUserRequest {
+                username: username.to_string(),
+                display_name: None,
+                email: None,
+            };
+            create_user_profile(principal, request, &mut state);
+        }
+        state
+    }
+
+    fn join(state: &mut State, group_id: u64, member: Principal, role: GroupRole) {
+        let username = state.user_profiles[&member].username.clone();
+        invite_group_member(alice(), group_id, username, role, state).unwrap();
+        accept_group_invite(member, group_id, state).unwrap();
+    }
+
+    fn upload(state: &mut State, owner: Principal) -> u64 {
+        upload_to(state, owner, None).unwrap()
+    }
+
+    fn upload_to(state: &mut State, owner: Principal, group_id: Option<u64>) -> Result<u64, crate::UploadFileError> {
+        let request = UploadFileAtomicRequest {
+            name: "report.pdf".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id,
+        };
+        upload_file_atomic(owner, request, state)
+    }
+
+    #[test]
+    fn members_see_and_download_group_files() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        join(&mut state, group.group_id, bob(), GroupRole::Viewer);
+        let file_id = upload(&mut state, alice());
+
+        assert_eq!(download_file(&state, bob(), file_id, 0), FileDownloadResponse::PermissionError);
+        assign_file_to_group(alice(), file_id, Some(group.group_id), &mut state).unwrap();
+
+        assert!(matches!(download_file(&state, bob(), file_id, 0), FileDownloadResponse::FoundFile(_)));
+        let files = list_files(bob(), Default::default(), &state).unwrap().files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].group_name, "Finance");
+        assert_eq!(files[0].group_alias, Some(group.alias.clone()));
+
+        // Non-members still cannot read it.
+        assert_eq!(download_file(&state, carol(), file_id, 0), FileDownloadResponse::PermissionError);
+        assert!(list_files(carol(), Default::default(), &state).unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn removed_members_lose_access() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        join(&mut state, group.group_id, bob(), GroupRole::Viewer);
+        let file_id = upload(&mut state, alice());
+        assign_file_to_group(alice(), file_id, Some(group.group_id), &mut state).unwrap();
+
+        remove_group_member(alice(), group.group_id, "bob".to_string(), &mut state).unwrap();
+        assert_eq!(download_file(&state, bob(), file_id, 0), FileDownloadResponse::PermissionError);
+        assert!(list_groups(bob(), &state).is_empty());
+    }
+
+    #[test]
+    fn roles_are_enforced() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        join(&mut state, group.group_id, bob(), GroupRole::Viewer);
+
+        // Viewers cannot manage members or assign files.
+        assert_eq!(
+            invite_group_member(bob(), group.group_id, "carol".to_string(), GroupRole::Viewer, &mut state),
+            Err(GroupError::PermissionError)
+        );
+        let bobs_file = upload(&mut state, bob());
+        assert_eq!(
+            assign_file_to_group(bob(), bobs_file, Some(group.group_id), &mut state),
+            Err(GroupError::PermissionError)
+        );
+
+        // Editors can assign their own files.
+        set_group_member_role(alice(), group.group_id, "bob".to_string(), GroupRole::Editor, &mut state).unwrap();
+        assert_eq!(assign_file_to_group(bob(), bobs_file, Some(group.group_id), &mut state), Ok(()));
+
+        // Nobody can assign a file they do not own.
+        assert_eq!(
+            assign_file_to_group(alice(), bobs_file, None, &mut state),
+            Err(GroupError::PermissionError)
+        );
+    }
+
+    #[test]
+    fn last_owner_cannot_leave() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        assert_eq!(
+            remove_group_member(alice(), group.group_id, "alice".to_string(), &mut state),
+            Err(GroupError::InvalidInput)
+        );
+        assert_eq!(
+            set_group_member_role(alice(), group.group_id, "alice".to_string(), GroupRole::Viewer, &mut state),
+            Err(GroupError::InvalidInput)
+        );
+
+        join(&mut state, group.group_id, bob(), GroupRole::Owner);
+        assert_eq!(remove_group_member(alice(), group.group_id, "alice".to_string(), &mut state), Ok(()));
+    }
+
+    #[test]
+    fn invited_users_join_once_they_accept() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        let info = invite_group_member(alice(), group.group_id, "bob".to_string(), GroupRole::Editor, &mut state).unwrap();
+        assert_eq!(info.members.len(), 1);
+        assert_eq!(info.invited[0].principal, bob());
+        assert_eq!(
+            invite_group_member(alice(), group.group_id, "bob".to_string(), GroupRole::Viewer, &mut state),
+            Err(GroupError::AlreadyMember)
+        );
+
+        // Invited users are not members yet.
+        let file_id = upload(&mut state, alice());
+        assign_file_to_group(alice(), file_id, Some(group.group_id), &mut state).unwrap();
+        assert_eq!(download_file(&state, bob(), file_id, 0), FileDownloadResponse::PermissionError);
+        let invites = list_group_invites(bob(), &state);
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].group_name, "Finance");
+        assert_eq!(invites[0].invited_by, alice());
+
+        let info = accept_group_invite(bob(), group.group_id, &mut state).unwrap();
+        assert_eq!(info.role, GroupRole::Editor);
+        assert!(info.invited.is_empty());
+        assert!(list_group_invites(bob(), &state).is_empty());
+        assert!(matches!(download_file(&state, bob(), file_id, 0), FileDownloadResponse::FoundFile(_)));
+        assert_eq!(accept_group_invite(bob(), group.group_id, &mut state), Err(GroupError::NotFound));
+    }
+
+    #[test]
+    fn declined_invites_are_dropped() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        invite_group_member(alice(), group.group_id, "carol".to_string(), GroupRole::Viewer, &mut state).unwrap();
+
+        assert_eq!(decline_group_invite(carol(), group.group_id, &mut state), Ok(()));
+        assert!(list_group_invites(carol(), &state).is_empty());
+        assert_eq!(accept_group_invite(carol(), group.group_id, &mut state), Err(GroupError::NotFound));
+        assert!(list_groups(carol(), &state).is_empty());
+    }
+
+    #[test]
+    fn group_files_are_encrypted_under_the_group_key() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        join(&mut state, group.group_id, bob(), GroupRole::Viewer);
+        let file_id = upload_to(&mut state, alice(), Some(group.group_id)).unwrap();
+        let key = GroupKey {
+            group_id: group.group_id,
+            version: 0,
+        };
+
+        assert_eq!(state.file_groups.get(&file_id), Some(&group.group_id));
+        assert_eq!(vetkey_derivation_id(&state, alice(), file_id), Ok(group_derivation_id(key)));
+        assert_eq!(vetkey_derivation_id(&state, bob(), file_id), Ok(group_derivation_id(key)));
+        assert!(vetkey_derivation_id(&state, carol(), file_id).is_err());
+
+        // Viewers cannot upload into the group.
+        assert_eq!(
+            upload_to(&mut state, bob(), Some(group.group_id)),
+            Err(crate::UploadFileError::InvalidInput)
+        );
+
+        // Removing a member rotates the key for new files. The owner keeps the old one.
+        remove_group_member(alice(), group.group_id, "bob".to_string(), &mut state).unwrap();
+        assert!(vetkey_derivation_id(&state, bob(), file_id).is_err());
+        assign_file_to_group(alice(), file_id, None, &mut state).unwrap();
+        assert_eq!(vetkey_derivation_id(&state, alice(), file_id), Ok(group_derivation_id(key)));
+        assert_eq!(
+            group_upload_derivation_id(&state, alice(), group.group_id),
+            Ok(group_derivation_id(GroupKey { version: 1, ..key }))
+        );
+    }
+
+    #[test]
+    fn files_under_the_owner_key_are_not_shared_with_members() {
+        let mut state = setup();
+        let group = create_group(alice(), "Finance".to_string(), &mut state).unwrap();
+        join(&mut state, group.group_id, bob(), GroupRole::Viewer);
+        let file_id = upload(&mut state, alice());
+        assign_file_to_group(alice(), file_id, Some(group.group_id), &mut state).unwrap();
+
+        assert_eq!(vetkey_derivation_id(&state, alice(), file_id), Ok(alice().as_slice().to_vec()));
+        assert!(vetkey_derivation_id(&state, bob(), file_id).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use crate::api::encrypted_metadata::{placeholder_name, ENCRYPTED_FILE_TYPE};
+use crate::api::folders::unique_name;
+use crate::api::takeout::{decode_manifest, TakeoutFile, TAKEOUT_FORMAT_VERSION};
+use crate::api::{create_folder, create_user_profile, quotas, update_file_metadata, CreateFolderRequest, UpdateFileMetadataRequest};
+use crate::vetkeys::EncryptedFileData;
+use crate::{CreateUserRequest, File, FileContent, FileMetadata, State, StorageProvider, UserResponse};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ImportError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The manifest could not be decoded, or was made by a newer version of the canister.
+    #[serde(rename = "invalid_manifest")]
+    InvalidManifest,
+    /// The imported files would take the caller over their storage quota.
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded,
+    #[serde(rename = "account_suspended")]
+    AccountSuspended,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImportAccountRequest {
+    /// A CBOR-encoded manifest, as downloaded with `download_takeout`.
+    pub manifest: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ImportConflict {
+    /// The username of the exported profile belongs to someone else here, so no profile
+    /// was created.
+    #[serde(rename = "username_taken")]
+    UsernameTaken(String),
+    /// The caller already has a profile here; it was kept as is.
+    #[serde(rename = "profile_exists")]
+    ProfileExists,
+    /// A file or folder was renamed because its name was already taken.
+    #[serde(rename = "name_collision")]
+    NameCollision { name: String, renamed_to: String },
+    /// Files without content (pending requests, burned files) and trashed files are not
+    /// imported.
+    #[serde(rename = "skipped_file")]
+    SkippedFile(u64),
+    /// A folder could not be created. Its files and subfolders were imported into the
+    /// root folder instead.
+    #[serde(rename = "skipped_folder")]
+    SkippedFolder(u64),
+    /// The tags of a file were rejected by this deployment's limits and were not imported.
+    #[serde(rename = "tags_rejected")]
+    TagsRejected(u64),
+    /// The attributes of a file were rejected by this deployment's limits and were not
+    /// imported.
+    #[serde(rename = "attributes_rejected")]
+    AttributesRejected(u64),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImportedFile {
+    /// The file's ID in the exporting deployment.
+    pub original_file_id: u64,
+    pub file_id: u64,
+    /// The chunks to upload with `upload_file_continue`, downloaded from the exporting
+    /// deployment with `download_file(original_file_id, chunk_id)`. Zero for Walrus files,
+    /// which are imported with their blob ID.
+    pub num_chunks: u64,
+    /// The vetKey derivation ID the chunks and encrypted metadata were encrypted under in
+    /// the exporting deployment.
+    pub original_derivation_id: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImportReport {
+    pub files: Vec<ImportedFile>,
+    pub folders_created: u64,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Recreates the profile, folders and files of a takeout manifest under the caller's
+/// principal.
+///
+/// Files are created without content. Their chunks are then streamed in with
+/// `upload_file_continue`, and the size declared in the manifest is reserved against the
+/// caller's quota until they are. Walrus files are imported as uploaded, pointing at the
+/// same blob. Share links, group memberships and earlier versions are specific to the
+/// exporting deployment and are not imported.
+///
+/// The vetKeys of this deployment cannot decrypt what the exporting deployment encrypted.
+/// The client must decrypt each chunk with the key of `original_derivation_id` there and
+/// re-encrypt it under the caller's key here before uploading it, and likewise re-encrypt
+/// encrypted metadata with `set_encrypted_metadata`. Walrus blobs stay encrypted under the
+/// exporting deployment's key until they are uploaded again.
+pub fn import_account(
+    caller: Principal,
+    request: ImportAccountRequest,
+    state: &mut State,
+) -> Result<ImportReport, ImportError> {
+    if caller == Principal::anonymous() {
+        return Err(ImportError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(ImportError::AccountSuspended);
+    }
+    let manifest = decode_manifest(&request.manifest)
+        .filter(|manifest| manifest.format_version <= TAKEOUT_FORMAT_VERSION)
+        .ok_or(ImportError::InvalidManifest)?;
+
+    let (files, skipped): (Vec<TakeoutFile>, Vec<TakeoutFile>) = manifest
+        .files
+        .into_iter()
+        .partition(|file| file.trashed_at.is_none() && (file.num_chunks > 0 || is_walrus_blob(file)));
+    let total_size = files.iter().filter(|file| !is_walrus_blob(file)).map(|file| file.size).sum();
+    quotas::check_quota(state, caller, total_size).map_err(|_| ImportError::QuotaExceeded)?;
+
+    let mut conflicts: Vec<ImportConflict> = skipped
+        .into_iter()
+        .map(|file| ImportConflict::SkippedFile(file.file_id))
+        .collect();
+
+    if let Some(profile) = manifest.profile {
+        if state.user_profiles.contains_key(&caller) {
+            conflicts.push(ImportConflict::ProfileExists);
+        } else {
+            let request = CreateUserRequest {
+                username: profile.username.clone(),
+                display_name: profile.display_name,
+                email: profile.email,
+            };
+            if let UserResponse::AlreadyExists = create_user_profile(caller, request, state) {
+                conflicts.push(ImportConflict::UsernameTaken(profile.username));
+            }
+        }
+    }
+
+    // Parents are created before their subfolders, so they have lower IDs.
+    let mut folders = manifest.folders;
+    folders.sort_by_key(|folder| folder.folder_id);
+    let mut folder_ids: BTreeMap<u64, u64> = BTreeMap::new();
+    for folder in folders {
+        let parent_id = folder.parent_id.and_then(|id| folder_ids.get(&id).copied());
+        let name = unique_name(state, caller, parent_id, &folder.name);
+        if name != folder.name {
+            conflicts.push(ImportConflict::NameCollision { name: folder.name, renamed_to: name.clone() });
+        }
+        match create_folder(caller, CreateFolderRequest { name, parent_id }, state) {
+            Ok(info) => {
+                folder_ids.insert(folder.folder_id, info.folder_id);
+            }
+            Err(_) => conflicts.push(ImportConflict::SkippedFolder(folder.folder_id)),
+        }
+    }
+
+    let mut imported = Vec::new();
+    for file in files {
+        let is_walrus = is_walrus_blob(&file);
+        let folder_id = file.folder_id.and_then(|id| folder_ids.get(&id).copied());
+        let file_id = state.generate_file_id();
+
+        let (name, file_type) = match file.encrypted_metadata {
+            Some(metadata) => {
+                state.encrypted_file_metadata.insert(file_id, metadata);
+                (placeholder_name(file_id), ENCRYPTED_FILE_TYPE.to_string())
+            }
+            None => {
+                let name = unique_name(state, caller, folder_id, &file.file_name);
+                if name != file.file_name {
+                    conflicts.push(ImportConflict::NameCollision {
+                        name: file.file_name.clone(),
+                        renamed_to: name.clone(),
+                    });
+                }
+                (name, file.file_type)
+            }
+        };
+
+        let vetkey_metadata = EncryptedFileData {
+            encrypted_content: Vec::new(),
+            file_owners: vec![caller],
+            encryption_metadata: HashMap::new(),
+        };
+        let content = if is_walrus {
+            // The blob is already on Walrus, so there is nothing left to upload.
+            FileContent::Uploaded { num_chunks: 0, file_type, vetkey_metadata }
+        } else {
+            FileContent::PartiallyUploaded { num_chunks: file.num_chunks, file_type, vetkey_metadata }
+        };
+        state.file_data.insert(
+            file_id,
+            File {
+                metadata: FileMetadata {
+                    file_name: name,
+                    requester_principal: caller,
+                    requested_at: file.requested_at,
+                    uploaded_at: file.uploaded_at,
+                    storage_provider: file.storage_provider,
+                    blob_id: file.blob_id,
+                    is_encrypted: file.is_encrypted,
+                },
+                content,
+            },
+        );
+        state.add_owned_file(caller, file_id);
+        if !is_walrus {
+            quotas::reserve(state, caller, file_id, file.size);
+        }
+        if let Some(folder_id) = folder_id {
+            state.file_folders.insert(file_id, folder_id);
+        }
+        if file.burn_after_reading {
+            state.burn_after_reading.insert(file_id, BTreeMap::new());
+        }
+        let tags = UpdateFileMetadataRequest {
+            file_id,
+            tags: Some(file.tags),
+            attributes: None,
+        };
+        if update_file_metadata(caller, tags, state).is_err() {
+            conflicts.push(ImportConflict::TagsRejected(file.file_id));
+        }
+        let attributes = UpdateFileMetadataRequest {
+            file_id,
+            tags: None,
+            attributes: Some(file.attributes),
+        };
+        if update_file_metadata(caller, attributes, state).is_err() {
+            conflicts.push(ImportConflict::AttributesRejected(file.file_id));
+        }
+
+        imported.push(ImportedFile {
+            original_file_id: file.file_id,
+            file_id,
+            num_chunks: file.num_chunks,
+            original_derivation_id: file.derivation_id,
+        });
+    }
+
+    Ok(ImportReport {
+        files: imported,
+        folders_created: folder_ids.len() as u64,
+        conflicts,
+    })
+}
+
+fn is_walrus_blob(file: &TakeoutFile) -> bool {
+    file.storage_provider == StorageProvider::Walrus && file.blob_id.is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        download_file, download_takeout, export_account, list_files, upload_file_atomic, upload_file_continue,
+        UploadFileAtomicRequest,
+    };
+    use crate::{FileDownloadResponse, UploadFileContinueRequest};
+
+    fn alice() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn bob() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn create_user(state: &mut State, principal: Principal, username: &str) {
+        let request = CreateUserRequest {
+            username: username.to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(principal, request, state);
+    }
+
+    fn upload(state: &mut State, principal: Principal, name: &str, content: Vec<u8>) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content,
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(principal, request, state).unwrap()
+    }
+
+    fn export(state: &mut State, principal: Principal) -> Vec<u8> {
+        export_account(principal, state).unwrap();
+        download_takeout(principal, 0, state).unwrap()
+    }
+
+    #[test]
+    fn files_are_recreated_and_streamed_in() {
+        let mut source = State::default();
+        create_user(&mut source, alice(), "alice");
+        let folder = create_folder(alice(), CreateFolderRequest { name: "Docs".to_string(), parent_id: None }, &mut source)
+            .unwrap();
+        let file_id = upload(&mut source, alice(), "cv.pdf", vec![1, 2, 3]);
+        crate::api::move_file(alice(), file_id, Some(folder.folder_id), &mut source).unwrap();
+        let manifest = export(&mut source, alice());
+
+        let mut target = State::default();
+        let report = import_account(alice(), ImportAccountRequest { manifest }, &mut target).unwrap();
+        assert_eq!(report.folders_created, 1);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(target.user_profiles[&alice()].username, "alice");
+        assert_eq!(quotas::reserved(&target, alice()), 3);
+
+        let imported = &report.files[0];
+        assert_eq!((imported.original_file_id, imported.num_chunks), (file_id, 1));
+        let contents = match download_file(&source, alice(), file_id, 0) {
+            FileDownloadResponse::FoundFile(data) => data.contents,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        let chunk = UploadFileContinueRequest { file_id: imported.file_id, chunk_id: 0, contents };
+        upload_file_continue(alice(), chunk, &mut target).unwrap();
+
+        let files = list_files(alice(), Default::default(), &target).unwrap().files;
+        assert_eq!(files[0].file_name, "cv.pdf");
+        assert!(files[0].folder_id.is_some());
+        assert_eq!(download_file(&target, alice(), imported.file_id, 0), download_file(&source, alice(), file_id, 0));
+        assert!(target.upload_reservations.is_empty());
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let mut source = State::default();
+        create_user(&mut source, alice(), "alice");
+        upload(&mut source, alice(), "cv.pdf", vec![1]);
+        let manifest = export(&mut source, alice());
+
+        let mut target = State::default();
+        create_user(&mut target, bob(), "alice");
+        upload(&mut target, alice(), "cv.pdf", vec![2]);
+        let report = import_account(alice(), ImportAccountRequest { manifest }, &mut target).unwrap();
+        assert_eq!(
+            report.conflicts,
+            vec![
+                ImportConflict::UsernameTaken("alice".to_string()),
+                ImportConflict::NameCollision { name: "cv.pdf".to_string(), renamed_to: "cv.pdf (1)".to_string() },
+            ]
+        );
+        assert!(!target.user_profiles.contains_key(&alice()));
+
+        assert_eq!(
+            import_account(alice(), ImportAccountRequest { manifest: vec![1, 2, 3] }, &mut target),
+            Err(ImportError::InvalidManifest)
+        );
+    }
+
+    #[test]
+    fn walrus_files_and_rejected_metadata() {
+        let mut source = State::default();
+        let blob_id = "M4hsZGQ1oCktdzegB6HnI6Mi28S2nqOPHxK-W7_4BUk".to_string();
+        let walrus_id = source.generate_file_id();
+        let file = File {
+            metadata: FileMetadata {
+                file_name: "video.mp4".to_string(),
+                requester_principal: alice(),
+                requested_at: 0,
+                uploaded_at: Some(0),
+                storage_provider: StorageProvider::Walrus,
+                blob_id: Some(blob_id.clone()),
+                is_encrypted: false,
+            },
+            content: FileContent::Uploaded {
+                num_chunks: 0,
+                file_type: String::new(),
+                vetkey_metadata: EncryptedFileData {
+                    encrypted_content: Vec::new(),
+                    file_owners: vec![alice()],
+                    encryption_metadata: HashMap::new(),
+                },
+            },
+        };
+        source.file_data.insert(walrus_id, file);
+        source.add_owned_file(alice(), walrus_id);
+        let folder = create_folder(alice(), CreateFolderRequest { name: "Docs".to_string(), parent_id: None }, &mut source)
+            .unwrap();
+        let file_id = upload(&mut source, alice(), "cv.pdf", vec![1]);
+        crate::api::move_file(alice(), file_id, Some(folder.folder_id), &mut source).unwrap();
+
+        // Tamper with the manifest so that the folder and the tags are rejected here.
+        let mut manifest = decode_manifest(&export(&mut source, alice())).unwrap();
+        manifest.folders[0].name = "a/b".to_string();
+        manifest.files[1].tags = vec!["x".repeat(100)];
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(&manifest, &mut data).unwrap();
+
+        let mut target = State::default();
+        let report = import_account(alice(), ImportAccountRequest { manifest: data }, &mut target).unwrap();
+        assert_eq!(
+            report.conflicts,
+            vec![ImportConflict::SkippedFolder(folder.folder_id), ImportConflict::TagsRejected(file_id)]
+        );
+        assert_eq!(report.folders_created, 0);
+
+        let walrus = &report.files[0];
+        assert_eq!((walrus.original_file_id, walrus.num_chunks), (walrus_id, 0));
+        assert_eq!(walrus.original_derivation_id, alice().as_slice().to_vec());
+        let imported = &target.file_data[&walrus.file_id];
+        assert!(matches!(imported.content, FileContent::Uploaded { num_chunks: 0, .. }));
+        assert_eq!((imported.metadata.blob_id.clone(), imported.metadata.is_encrypted), (Some(blob_id), false));
+        assert_eq!(quotas::reserved(&target, alice()), 1);
+        // The file of the skipped folder lands in the root folder.
+        assert_eq!(target.file_folders.get(&report.files[1].file_id), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::State;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// How long a link request can be confirmed: 1 day.
+pub const LINK_REQUEST_TTL: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// A principal cannot be linked to its own account, nor can the anonymous principal.
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// There is no such link, or no unexpired request for it.
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "already_linked")]
+    AlreadyLinked,
+    /// The principal to link has its own profile, files or linked principals. Those would
+    /// become unreachable once it is linked, so the account has to be deleted first.
+    #[serde(rename = "account_in_use")]
+    AccountInUse,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LinkedPrincipals {
+    /// The principal everything in the account is stored under.
+    pub account: Principal,
+    /// The other principals that act as the account.
+    pub principals: Vec<Principal>,
+    /// Principals that were asked to link and have not confirmed yet.
+    pub pending: Vec<Principal>,
+}
+
+/// Asks `principal` to join the caller's account. The link is only made once `principal`
+/// confirms it with `confirm_principal_link`.
+pub fn request_principal_link(caller: Principal, principal: Principal, state: &mut State) -> Result<(), LinkError> {
+    if caller == Principal::anonymous() {
+        return Err(LinkError::NotAuthenticated);
+    }
+    let account = state.account_of(caller);
+    if principal == Principal::anonymous() || principal == account {
+        return Err(LinkError::InvalidInput);
+    }
+    if state.linked_principals.contains_key(&principal) {
+        return Err(LinkError::AlreadyLinked);
+    }
+    state.link_requests.insert((account, principal), crate::get_time());
+    Ok(())
+}
+
+/// Confirms a link requested by `account`: from then on, the caller acts as that account.
+pub fn confirm_principal_link(caller: Principal, account: Principal, state: &mut State) -> Result<(), LinkError> {
+    if caller == Principal::anonymous() {
+        return Err(LinkError::NotAuthenticated);
+    }
+    let requested_at = state.link_requests.get(&(account, caller)).copied().ok_or(LinkError::NotFound)?;
+    if requested_at.saturating_add(LINK_REQUEST_TTL) < crate::get_time() {
+        state.link_requests.remove(&(account, caller));
+        return Err(LinkError::NotFound);
+    }
+    if state.linked_principals.contains_key(&caller) {
+        return Err(LinkError::AlreadyLinked);
+    }
+    if is_in_use(state, caller) {
+        return Err(LinkError::AccountInUse);
+    }
+    state.link_requests.remove(&(account, caller));
+    state.linked_principals.insert(caller, account);
+    Ok(())
+}
+
+/// Removes a principal from the caller's account. A linked principal can also unlink itself.
+/// The principal the account is stored under cannot be unlinked.
+pub fn unlink_principal(caller: Principal, principal: Principal, state: &mut State) -> Result<(), LinkError> {
+    if caller == Principal::anonymous() {
+        return Err(LinkError::NotAuthenticated);
+    }
+    let account = state.account_of(caller);
+    if state.link_requests.remove(&(account, principal)).is_some() {
+        return Ok(());
+    }
+    if state.linked_principals.get(&principal) != Some(&account) {
+        return Err(LinkError::NotFound);
+    }
+    state.linked_principals.remove(&principal);
+    Ok(())
+}
+
+pub fn list_linked_principals(caller: Principal, state: &State) -> Result<LinkedPrincipals, LinkError> {
+    if caller == Principal::anonymous() {
+        return Err(LinkError::NotAuthenticated);
+    }
+    let account = state.account_of(caller);
+    let now = crate::get_time();
+    Ok(LinkedPrincipals {
+        account,
+        principals: linked_to(state, account),
+        pending: state
+            .link_requests
+            .range((account, Principal::management_canister())..)
+            .take_while(|((requester, _), _)| *requester == account)
+            .filter(|(_, &requested_at)| requested_at.saturating_add(LINK_REQUEST_TTL) >= now)
+            .map(|(&(_, principal), _)| principal)
+            .collect(),
+    })
+}
+
+/// The principals linked to an account, not including the account's own principal.
+pub(crate) fn linked_to(state: &State, account: Principal) -> Vec<Principal> {
+    state
+        .linked_principals
+        .iter()
+        .filter(|(_, &linked_account)| linked_account == account)
+        .map(|(&principal, _)| principal)
+        .collect()
+}
+
+/// Removes the links and link requests of an account.
+pub(crate) fn remove_links(state: &mut State, account: Principal) {
+    state.linked_principals.retain(|_, linked_account| *linked_account != account);
+    state.link_requests.retain(|(requester, principal), _| *requester != account && *principal != account);
+}
+
+fn is_in_use(state: &State, principal: Principal) -> bool {
+    state.user_profiles.contains_key(&principal)
+        || state.file_owners.get(&principal).is_some_and(|files| !files.is_empty())
+        || state.user_trash.get(&principal).is_some_and(|files| !files.is_empty())
+        || state.linked_principals.values().any(|&account| account == principal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{create_user_profile, upload_file_atomic, UploadFileAtomicRequest};
+    use crate::CreateUserRequest;
+
+    fn account() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn device() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    #[test]
+    fn links_need_confirmation_from_both_principals() {
+        let mut state = State::default();
+        assert_eq!(confirm_principal_link(device(), account(), &mut state), Err(LinkError::NotFound));
+        assert_eq!(request_principal_link(account(), account(), &mut state), Err(LinkError::InvalidInput));
+
+        request_principal_link(account(), device(), &mut state).unwrap();
+        assert_eq!(list_linked_principals(account(), &state).unwrap().pending, vec![device()]);
+        assert_eq!(state.account_of(device()), device());
+
+        confirm_principal_link(device(), account(), &mut state).unwrap();
+        assert_eq!(state.account_of(device()), account());
+        let linked = list_linked_principals(device(), &state).unwrap();
+        assert_eq!((linked.account, linked.principals, linked.pending), (account(), vec![device()], vec![]));
+
+        // Requests made from a linked principal are made on behalf of the account.
+        let other = Principal::from_slice(&[1, 2, 3]);
+        request_principal_link(device(), other, &mut state).unwrap();
+        confirm_principal_link(other, account(), &mut state).unwrap();
+        assert_eq!(state.account_of(other), account());
+
+        unlink_principal(other, other, &mut state).unwrap();
+        unlink_principal(account(), device(), &mut state).unwrap();
+        assert_eq!(state.account_of(device()), device());
+        assert_eq!(unlink_principal(account(), device(), &mut state), Err(LinkError::NotFound));
+    }
+
+    #[test]
+    fn principals_in_use_cannot_be_linked() {
+        let mut state = State::default();
+        let request = CreateUserRequest {
+            username: "bob".to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(device(), request, &mut state);
+        request_principal_link(account(), device(), &mut state).unwrap();
+        assert_eq!(confirm_principal_link(device(), account(), &mut state), Err(LinkError::AccountInUse));
+
+        // Files and quotas follow the account.
+        let other = Principal::from_slice(&[1, 2, 3]);
+        request_principal_link(account(), other, &mut state).unwrap();
+        confirm_principal_link(other, account(), &mut state).unwrap();
+        let request = UploadFileAtomicRequest {
+            name: "a.txt".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "txt".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = upload_file_atomic(state.account_of(other), request, &mut state).unwrap();
+        assert!(state.is_file_owner(account(), file_id));
+        assert_eq!(state.storage_usage[&account()].bytes, 3);
+    }
+}
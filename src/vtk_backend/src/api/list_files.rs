@@ -0,0 +1,357 @@
+use crate::api::file_tags::{files_with_tag, has_tag};
+use crate::api::folders::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::{File, FileContent, FileStatus, PublicFileMetadata, State, StorageProvider};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ListFilesError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The cursor was not made for the requested sort order.
+    #[serde(rename = "invalid_cursor")]
+    InvalidCursor,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileSortField {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "uploaded_at")]
+    UploadedAt,
+    #[serde(rename = "size")]
+    Size,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatusFilter {
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "partially_uploaded")]
+    PartiallyUploaded,
+    #[serde(rename = "uploaded")]
+    Uploaded,
+    #[serde(rename = "consumed")]
+    Consumed,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct ListFilesRequest {
+    /// The `next_cursor` of the previous page, `None` for the first page.
+    pub cursor: Option<FileCursor>,
+    pub limit: Option<u64>,
+    /// Defaults to the order in which files were created.
+    pub sort_by: Option<FileSortField>,
+    pub descending: Option<bool>,
+    pub status: Option<FileStatusFilter>,
+    pub storage_provider: Option<StorageProvider>,
+    pub file_type: Option<String>,
+    pub folder_id: Option<u64>,
+    pub tag: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ListFilesResponse {
+    pub files: Vec<PublicFileMetadata>,
+    /// Pass this as `cursor` to get the next page. `None` on the last page.
+    pub next_cursor: Option<FileCursor>,
+    /// Number of files matching the filters, across all pages.
+    pub total: u64,
+}
+
+/// The position of a file in the sort order: the value it is sorted by, then its ID.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileCursor {
+    pub sort_value: FileSortValue,
+    pub file_id: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileSortValue {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "text")]
+    Text(String),
+    #[serde(rename = "number")]
+    Number(u64),
+}
+
+/// Lists the files owned by the caller and the files shared with them through groups,
+/// one page at a time.
+///
+/// The cursor is the position of the last file of the previous page in the sort order.
+/// Pages continue after that position, so files added or removed in between, including the
+/// cursor's own file, do not cause others to be skipped or repeated.
+pub fn list_files(caller: Principal, request: ListFilesRequest, s: &State) -> Result<ListFilesResponse, ListFilesError> {
+    if caller == Principal::anonymous() {
+        return Err(ListFilesError::NotAuthenticated);
+    }
+
+    // With a tag filter, the caller's own files are looked up in the tag index.
+    let owned_files: Box<dyn Iterator<Item = &u64>> = match &request.tag {
+        Some(tag) => Box::new(files_with_tag(s, caller, tag)),
+        None => Box::new(s.file_owners.get(&caller).into_iter().flatten()),
+    };
+    let group_files = s
+        .user_groups
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter_map(|group_id| s.group_files.get(group_id))
+        .flatten();
+
+    let mut seen = BTreeSet::new();
+    let mut files: Vec<(FileCursor, &File)> = owned_files
+        .chain(group_files)
+        .filter(|&&file_id| !s.is_trashed(file_id) && seen.insert(file_id))
+        .filter_map(|&file_id| Some((file_id, s.file_data.get(&file_id)?)))
+        .filter(|&(file_id, file)| matches_filters(s, caller, &request, file_id, file))
+        .map(|(file_id, file)| {
+            let sort_value = sort_value(s, request.sort_by, file_id, file);
+            (FileCursor { sort_value, file_id }, file)
+        })
+        .collect();
+
+    let descending = request.descending.unwrap_or(false);
+    files.sort_by(|(a, _), (b, _)| if descending { b.cmp(a) } else { a.cmp(b) });
+
+    let start = match &request.cursor {
+        None => 0,
+        Some(cursor) => {
+            let expected = sort_value_kind(request.sort_by);
+            if std::mem::discriminant(&cursor.sort_value) != std::mem::discriminant(&expected) {
+                return Err(ListFilesError::InvalidCursor);
+            }
+            files.partition_point(|(k, _)| if descending { k >= cursor } else { k <= cursor })
+        }
+    };
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+
+    let total = files.len() as u64;
+    let page: Vec<&FileCursor> = files.iter().skip(start).take(limit).map(|(key, _)| key).collect();
+    let next_cursor = match page.last() {
+        Some(&last) if start + page.len() < files.len() => Some(last.clone()),
+        _ => None,
+    };
+
+    Ok(ListFilesResponse {
+        files: page
+            .into_iter()
+            .filter_map(|key| public_file_metadata(s, caller, key.file_id))
+            .collect(),
+        next_cursor,
+        total,
+    })
+}
+
+fn matches_filters(s: &State, caller: Principal, request: &ListFilesRequest, file_id: u64, file: &File) -> bool {
+    let status_matches = request.status.is_none_or(|status| {
+        matches!(
+            (status, &file.content),
+            (FileStatusFilter::Pending, FileContent::Pending { .. })
+                | (FileStatusFilter::PartiallyUploaded, FileContent::PartiallyUploaded { .. })
+                | (FileStatusFilter::Uploaded, FileContent::Uploaded { .. })
+                | (FileStatusFilter::Consumed, FileContent::Consumed { .. })
+        )
+    });
+    let file_type_matches = request.file_type.as_ref().is_none_or(|wanted| match &file.content {
+        FileContent::Uploaded { file_type, .. } | FileContent::PartiallyUploaded { file_type, .. } => {
+            file_type == wanted
+        }
+        _ => false,
+    });
+    // Folders are private to the owner, so group members never match a folder filter.
+    let folder_matches = request.folder_id.is_none_or(|folder_id| {
+        s.is_file_owner(caller, file_id) && s.file_folders.get(&file_id) == Some(&folder_id)
+    });
+
+    status_matches
+        && file_type_matches
+        && folder_matches
+        && request.tag.as_ref().is_none_or(|tag| has_tag(s, file_id, tag))
+        && request.storage_provider.is_none_or(|provider| file.metadata.storage_provider == provider)
+}
+
+fn sort_value(s: &State, sort_by: Option<FileSortField>, file_id: u64, file: &File) -> FileSortValue {
+    match sort_by {
+        None => FileSortValue::None,
+        Some(FileSortField::Name) => FileSortValue::Text(file.metadata.file_name.to_lowercase()),
+        Some(FileSortField::UploadedAt) => FileSortValue::Number(file.metadata.uploaded_at.unwrap_or(0)),
+        Some(FileSortField::Size) => FileSortValue::Number(file_size(s, file_id, file)),
+    }
+}
+
+/// A sort value of the kind files are sorted by with `sort_by`.
+fn sort_value_kind(sort_by: Option<FileSortField>) -> FileSortValue {
+    match sort_by {
+        None => FileSortValue::None,
+        Some(FileSortField::Name) => FileSortValue::Text(String::new()),
+        Some(FileSortField::UploadedAt | FileSortField::Size) => FileSortValue::Number(0),
+    }
+}
+
+/// Total size of the current content of a file, in bytes.
+pub(crate) fn file_size(s: &State, file_id: u64, file: &File) -> u64 {
+    match &file.content {
+        FileContent::Uploaded { .. } | FileContent::PartiallyUploaded { .. } => {
+            s.stored_bytes.get(&s.storage_id(file_id)).map_or(0, |&(_, bytes)| bytes)
+        }
+        FileContent::Pending { .. } | FileContent::Consumed { .. } => 0,
+    }
+}
+
+/// The metadata of a file as presented to `caller`.
+pub(crate) fn public_file_metadata(s: &State, caller: Principal, file_id: u64) -> Option<PublicFileMetadata> {
+    let file = s.file_data.get(&file_id)?;
+
+    let file_status = match &file.content {
+        FileContent::Pending { alias } => FileStatus::Pending {
+            alias: alias.clone(),
+            requested_at: file.metadata.requested_at,
+        },
+        FileContent::PartiallyUploaded { .. } => FileStatus::PartiallyUploaded,
+        FileContent::Uploaded { .. } => FileStatus::Uploaded {
+            uploaded_at: file.metadata.uploaded_at.unwrap_or(file.metadata.requested_at),
+        },
+        FileContent::Consumed { consumed_at, .. } => FileStatus::Consumed {
+            consumed_at: *consumed_at,
+        },
+    };
+
+    let group = s.file_groups.get(&file_id).and_then(|group_id| s.groups.get(group_id));
+
+    // Share link tokens and folders are only shown to the owner, not to group members.
+    let is_owner = s.is_file_owner(caller, file_id);
+    let share_links = if is_owner {
+        crate::api::active_share_links(s, file_id)
+    } else {
+        vec![]
+    };
+
+    Some(PublicFileMetadata {
+        file_id,
+        file_name: file.metadata.file_name.clone(),
+        group_name: group.map(|g| g.name.clone()).unwrap_or_default(),
+        group_alias: group.map(|g| g.alias.clone()),
+        file_status,
+        shared_with: vec![], // Empty vector for now since we don't support sharing yet
+        share_links,
+        burn_after_reading: s.burn_after_reading.contains_key(&file_id)
+            || matches!(file.content, FileContent::Consumed { .. }),
+        folder_id: s.file_folders.get(&file_id).copied().filter(|_| is_owner),
+        tags: s.file_tags.get(&file_id).into_iter().flatten().cloned().collect(),
+        attributes: s
+            .file_attributes
+            .get(&file_id)
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        encrypted_metadata: s.encrypted_file_metadata.get(&file_id).cloned(),
+        registered_by_sui_address: s.walrus_registrants.get(&file_id).cloned(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{delete_file, upload_file_atomic, UploadFileAtomicRequest};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn upload(state: &mut State, name: &str, content: Vec<u8>, file_type: &str) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content,
+            file_type: file_type.to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state).unwrap()
+    }
+
+    fn names(response: &ListFilesResponse) -> Vec<&str> {
+        response.files.iter().map(|f| f.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn anonymous_callers_get_an_error() {
+        let state = State::default();
+        assert_eq!(
+            list_files(Principal::anonymous(), ListFilesRequest::default(), &state),
+            Err(ListFilesError::NotAuthenticated)
+        );
+    }
+
+    #[test]
+    fn cursor_pagination_with_sorting() {
+        let mut state = State::default();
+        upload(&mut state, "b.txt", vec![1, 1, 1], "txt");
+        upload(&mut state, "a.txt", vec![1], "txt");
+        upload(&mut state, "c.txt", vec![1, 1], "txt");
+
+        let mut request = ListFilesRequest {
+            limit: Some(2),
+            sort_by: Some(FileSortField::Name),
+            ..Default::default()
+        };
+        let first = list_files(owner(), request.clone(), &state).unwrap();
+        assert_eq!(names(&first), vec!["a.txt", "b.txt"]);
+        assert_eq!(first.total, 3);
+
+        request.cursor = first.next_cursor;
+        let second = list_files(owner(), request, &state).unwrap();
+        assert_eq!(names(&second), vec!["c.txt"]);
+        assert_eq!(second.next_cursor, None);
+
+        let request = ListFilesRequest {
+            sort_by: Some(FileSortField::Size),
+            descending: Some(true),
+            ..Default::default()
+        };
+        let by_size = list_files(owner(), request, &state).unwrap();
+        assert_eq!(names(&by_size), vec!["b.txt", "c.txt", "a.txt"]);
+    }
+
+    #[test]
+    fn cursor_survives_deletion_of_the_last_file() {
+        let mut state = State::default();
+        let ids: Vec<u64> = ["a", "b", "c"].iter().map(|n| upload(&mut state, n, vec![1], "txt")).collect();
+
+        let request = ListFilesRequest { limit: Some(2), ..Default::default() };
+        let first = list_files(owner(), request.clone(), &state).unwrap();
+        assert_eq!(first.next_cursor.as_ref().map(|cursor| cursor.file_id), Some(ids[1]));
+        delete_file(&mut state, owner(), ids[1]);
+        let request = ListFilesRequest { cursor: first.next_cursor, ..request };
+        assert_eq!(names(&list_files(owner(), request.clone(), &state).unwrap()), vec!["c"]);
+
+        // The cursor still works once its file is gone for good.
+        crate::api::empty_trash(owner(), &mut state).unwrap();
+        assert_eq!(names(&list_files(owner(), request.clone(), &state).unwrap()), vec!["c"]);
+
+        let request = ListFilesRequest { sort_by: Some(FileSortField::Name), ..request };
+        assert_eq!(list_files(owner(), request, &state), Err(ListFilesError::InvalidCursor));
+    }
+
+    #[test]
+    fn filters() {
+        let mut state = State::default();
+        upload(&mut state, "a.txt", vec![1], "txt");
+        upload(&mut state, "b.pdf", vec![1], "pdf");
+
+        let request = ListFilesRequest { file_type: Some("pdf".to_string()), ..Default::default() };
+        assert_eq!(names(&list_files(owner(), request, &state).unwrap()), vec!["b.pdf"]);
+
+        let request = ListFilesRequest { status: Some(FileStatusFilter::Pending), ..Default::default() };
+        assert!(list_files(owner(), request, &state).unwrap().files.is_empty());
+
+        let request = ListFilesRequest { storage_provider: Some(StorageProvider::Walrus), ..Default::default() };
+        assert_eq!(list_files(owner(), request, &state).unwrap().total, 0);
+    }
+}
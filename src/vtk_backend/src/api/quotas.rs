@@ -0,0 +1,358 @@
+use crate::{State, StorageUsage, UploadFileError};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum QuotaError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    /// The plan has no quota configured.
+    #[serde(rename = "unknown_plan")]
+    UnknownPlan,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QuotaInfo {
+    pub plan: Option<String>,
+    /// Storage quota in bytes.
+    pub quota: u64,
+    /// Bytes taken by the user's files, including trashed files and previous versions.
+    pub used: u64,
+    /// Bytes declared for uploads that are still in progress.
+    pub reserved: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UsageMismatch {
+    pub user: Principal,
+    pub recorded: StorageUsage,
+    pub actual: StorageUsage,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorageUsageReport {
+    /// The canister-wide total as maintained incrementally.
+    pub total_recorded: u64,
+    /// The canister-wide total recomputed from the stored chunks.
+    pub total_actual: u64,
+    /// Users whose recorded usage differs from the recomputed one.
+    pub mismatches: Vec<UsageMismatch>,
+    /// Uploaded files whose content is not where their storage provider expects it.
+    pub unverified_files: Vec<u64>,
+}
+
+pub fn get_quota(caller: Principal, state: &State) -> Result<QuotaInfo, QuotaError> {
+    if caller == Principal::anonymous() {
+        return Err(QuotaError::NotAuthenticated);
+    }
+    Ok(QuotaInfo {
+        plan: state.user_plans.get(&caller).cloned(),
+        quota: quota_for(state, caller),
+        used: storage_used(state, caller),
+        reserved: reserved(state, caller),
+    })
+}
+
+pub fn set_default_quota(quota: u64, state: &mut State) {
+    state.default_quota = quota;
+}
+
+/// Sets the quota of a plan, or removes the plan if `quota` is `None`. Users on a removed
+/// plan fall back to the default quota.
+pub fn set_plan_quota(plan: String, quota: Option<u64>, state: &mut State) {
+    match quota {
+        Some(quota) => {
+            state.plan_quotas.insert(plan, quota);
+        }
+        None => {
+            state.plan_quotas.remove(&plan);
+            state.user_plans.retain(|_, user_plan| *user_plan != plan);
+        }
+    }
+}
+
+/// Puts a user on a plan, or back on the default quota if `plan` is `None`.
+pub fn set_user_plan(user: Principal, plan: Option<String>, state: &mut State) -> Result<(), QuotaError> {
+    let user = state.account_of(user);
+    match plan {
+        Some(plan) if !state.plan_quotas.contains_key(&plan) => return Err(QuotaError::UnknownPlan),
+        Some(plan) => {
+            state.user_plans.insert(user, plan);
+        }
+        None => {
+            state.user_plans.remove(&user);
+        }
+    }
+    Ok(())
+}
+
+pub fn quota_for(state: &State, user: Principal) -> u64 {
+    state
+        .user_plans
+        .get(&user)
+        .and_then(|plan| state.plan_quotas.get(plan))
+        .copied()
+        .unwrap_or(state.default_quota)
+}
+
+/// Bytes stored for the user's files, in the trash or not, and their previous versions.
+/// Chunks shared between copies are counted once.
+pub fn storage_used(state: &State, user: Principal) -> u64 {
+    state.storage_usage.get(&user).map_or(0, |usage| usage.bytes)
+}
+
+/// Recomputes storage usage from the stored chunks and compares it with the recorded
+/// usage. With `repair`, the recorded usage is replaced by the recomputed one.
+pub fn check_storage_usage(repair: bool, state: &mut State) -> StorageUsageReport {
+    let (stored_bytes, actual) = recompute_usage(state);
+    let total_actual = stored_bytes.values().map(|(_, bytes)| bytes).sum();
+
+    let users: BTreeSet<Principal> = state.storage_usage.keys().chain(actual.keys()).copied().collect();
+    let mismatches: Vec<UsageMismatch> = users
+        .into_iter()
+        .filter_map(|user| {
+            let recorded = state.storage_usage.get(&user).copied().unwrap_or_default();
+            let actual = actual.get(&user).copied().unwrap_or_default();
+            (recorded != actual).then_some(UsageMismatch { user, recorded, actual })
+        })
+        .collect();
+    let unverified_files = state
+        .file_data
+        .iter()
+        .filter(|(&file_id, file)| !file.metadata.storage_provider.backend().verify(state, file, file_id))
+        .map(|(&file_id, _)| file_id)
+        .collect();
+    let report = StorageUsageReport {
+        total_recorded: state.total_storage_used,
+        total_actual,
+        mismatches,
+        unverified_files,
+    };
+
+    if repair {
+        state.stored_bytes = stored_bytes;
+        state.storage_usage = actual;
+        state.total_storage_used = total_actual;
+        for mismatch in &report.mismatches {
+            state.sync_profile_usage(mismatch.user);
+        }
+    }
+    report
+}
+
+/// Storage usage derived from scratch: the owner and size of every storage ID in use, and
+/// the usage of every user owning files.
+fn recompute_usage(state: &State) -> (BTreeMap<u64, (Principal, u64)>, BTreeMap<Principal, StorageUsage>) {
+    let mut stored_bytes = BTreeMap::new();
+    let mut usage: BTreeMap<Principal, StorageUsage> = BTreeMap::new();
+
+    let owned_files = state.file_owners.iter().chain(&state.user_trash);
+    for (&owner, file_ids) in owned_files {
+        for &file_id in file_ids {
+            usage.entry(owner).or_default().file_count += 1;
+            let versions = state.file_versions.get(&file_id).into_iter().flat_map(|history| &history.previous);
+            let storage_ids = std::iter::once(state.storage_id(file_id)).chain(versions.map(|v| v.storage_id));
+            for storage_id in storage_ids {
+                if stored_bytes.contains_key(&storage_id) {
+                    continue;
+                }
+                let bytes: u64 = state
+                    .file_contents
+                    .range((storage_id, 0)..=(storage_id, u64::MAX))
+                    .map(|(_, chunk)| chunk.len() as u64)
+                    .sum();
+                if bytes > 0 {
+                    stored_bytes.insert(storage_id, (owner, bytes));
+                    usage.entry(owner).or_default().bytes += bytes;
+                }
+            }
+        }
+    }
+    (stored_bytes, usage)
+}
+
+/// Bytes reserved for the user's uploads in progress.
+pub fn reserved(state: &State, user: Principal) -> u64 {
+    state
+        .upload_reservations
+        .values()
+        .filter(|(owner, _)| *owner == user)
+        .map(|(_, bytes)| bytes)
+        .sum()
+}
+
+/// Fails if storing `bytes` more would take `owner` over their quota.
+pub(crate) fn check_quota(state: &State, owner: Principal, bytes: u64) -> Result<(), UploadFileError> {
+    let total = storage_used(state, owner)
+        .saturating_add(reserved(state, owner))
+        .saturating_add(bytes);
+    if total > quota_for(state, owner) {
+        return Err(UploadFileError::QuotaExceeded);
+    }
+    Ok(())
+}
+
+/// Reserves the declared size of an upload that has not been written yet. The caller is
+/// expected to have checked it against the quota.
+pub(crate) fn reserve(state: &mut State, owner: Principal, file_id: u64, bytes: u64) {
+    if bytes > 0 {
+        state.upload_reservations.insert(file_id, (owner, bytes));
+    }
+}
+
+/// Accounts for a chunk about to be written for `file_id`. The chunk is taken out of the
+/// upload's reservation; whatever exceeds the reservation has to fit in the quota.
+pub(crate) fn charge_chunk(state: &mut State, owner: Principal, file_id: u64, bytes: u64) -> Result<(), UploadFileError> {
+    let reserved = state.upload_reservations.get(&file_id).map_or(0, |(_, reserved)| *reserved);
+    let covered = bytes.min(reserved);
+    check_quota(state, owner, bytes - covered)?;
+    if covered == reserved {
+        state.upload_reservations.remove(&file_id);
+    } else if let Some((_, reserved)) = state.upload_reservations.get_mut(&file_id) {
+        *reserved -= covered;
+    }
+    Ok(())
+}
+
+/// Frees what is left of an upload's reservation once it completes or the file is deleted.
+pub(crate) fn release_reservation(state: &mut State, file_id: u64) {
+    state.upload_reservations.remove(&file_id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        copy_file, delete_file, empty_trash, register_file, upload_file_atomic, upload_file_continue,
+        upload_new_version, CopyFileRequest, RegisterFileRequest, UploadFileAtomicRequest, UploadNewVersionRequest,
+    };
+    use crate::{StorageProvider, UploadFileContinueRequest};
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn upload(state: &mut State, content: Vec<u8>, num_chunks: u64, total_size: Option<u64>) -> Result<u64, UploadFileError> {
+        let request = UploadFileAtomicRequest {
+            name: format!("file-{}", state.file_data.len()),
+            content,
+            file_type: "txt".to_string(),
+            num_chunks,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state)
+    }
+
+    fn chunk(state: &mut State, file_id: u64, chunk_id: u64, contents: Vec<u8>) -> Result<(), UploadFileError> {
+        upload_file_continue(owner(), UploadFileContinueRequest { file_id, chunk_id, contents }, state)
+    }
+
+    #[test]
+    fn uploads_are_limited_by_the_quota() {
+        let mut state = State::default();
+        set_default_quota(10, &mut state);
+
+        let file_id = upload(&mut state, vec![0; 6], 1, None).unwrap();
+        assert_eq!(upload(&mut state, vec![0; 5], 1, None), Err(UploadFileError::QuotaExceeded));
+        assert_eq!(state.file_data.len(), 1);
+
+        // Trashed files still count until they are purged.
+        delete_file(&mut state, owner(), file_id);
+        assert_eq!(storage_used(&state, owner()), 6);
+        empty_trash(owner(), &mut state).unwrap();
+        upload(&mut state, vec![0; 10], 1, None).unwrap();
+    }
+
+    #[test]
+    fn declared_sizes_are_reserved() {
+        let mut state = State::default();
+        set_default_quota(10, &mut state);
+
+        assert_eq!(upload(&mut state, vec![0; 2], 3, Some(11)), Err(UploadFileError::QuotaExceeded));
+        let file_id = upload(&mut state, vec![0; 2], 3, Some(8)).unwrap();
+        assert_eq!(reserved(&state, owner()), 6);
+
+        // The reservation is held for the rest of the upload.
+        assert_eq!(upload(&mut state, vec![0; 3], 1, None), Err(UploadFileError::QuotaExceeded));
+        chunk(&mut state, file_id, 1, vec![0; 3]).unwrap();
+        // Chunks beyond the declared size must fit in the quota.
+        assert_eq!(chunk(&mut state, file_id, 2, vec![0; 6]), Err(UploadFileError::QuotaExceeded));
+        chunk(&mut state, file_id, 2, vec![0; 3]).unwrap();
+        assert!(state.upload_reservations.is_empty());
+        assert_eq!(storage_used(&state, owner()), 8);
+
+        let request = RegisterFileRequest {
+            file_name: "walrus.bin".to_string(),
+            storage_provider: StorageProvider::Icp,
+            blob_id: None,
+            requested_at: 0,
+            uploaded_at: None,
+            size: Some(3),
+        };
+        assert_eq!(register_file(owner(), request, &mut state).err(), Some(UploadFileError::QuotaExceeded));
+    }
+
+    #[test]
+    fn plans_override_the_default_quota() {
+        let mut state = State::default();
+        assert_eq!(set_user_plan(owner(), Some("pro".to_string()), &mut state), Err(QuotaError::UnknownPlan));
+
+        set_plan_quota("pro".to_string(), Some(100 << 30), &mut state);
+        set_user_plan(owner(), Some("pro".to_string()), &mut state).unwrap();
+        assert_eq!(get_quota(owner(), &state).unwrap().quota, 100 << 30);
+
+        set_plan_quota("pro".to_string(), None, &mut state);
+        let info = get_quota(owner(), &state).unwrap();
+        assert_eq!((info.plan, info.quota), (None, crate::DEFAULT_STORAGE_QUOTA));
+    }
+
+    #[test]
+    fn usage_is_maintained_incrementally() {
+        let mut state = State::default();
+        let file_id = upload(&mut state, vec![0; 4], 2, None).unwrap();
+        chunk(&mut state, file_id, 1, vec![0; 2]).unwrap();
+        // Copies share the chunks of the original.
+        let copy_id = copy_file(owner(), CopyFileRequest { file_id, name: None }, &mut state).unwrap();
+        let request = UploadNewVersionRequest { file_id, content: vec![0; 3], file_type: "txt".to_string(), num_chunks: 1 };
+        upload_new_version(owner(), request, &mut state).unwrap();
+
+        let usage = state.storage_usage[&owner()];
+        assert_eq!((usage.bytes, usage.file_count), (9, 2));
+        assert_eq!(state.total_storage_used, 9);
+        assert!(check_storage_usage(false, &mut state).mismatches.is_empty());
+
+        delete_file(&mut state, owner(), file_id);
+        empty_trash(owner(), &mut state).unwrap();
+        // The copy still holds the original chunks.
+        assert_eq!(state.storage_usage[&owner()], StorageUsage { bytes: 6, file_count: 1 });
+
+        delete_file(&mut state, owner(), copy_id);
+        empty_trash(owner(), &mut state).unwrap();
+        assert_eq!(state.storage_usage[&owner()], StorageUsage::default());
+        assert_eq!(state.total_storage_used, 0);
+    }
+
+    #[test]
+    fn drifted_usage_is_repaired() {
+        let mut state = State::default();
+        upload(&mut state, vec![0; 5], 1, None).unwrap();
+        state.storage_usage.clear();
+        state.total_storage_used = 1;
+
+        let report = check_storage_usage(false, &mut state);
+        assert_eq!((report.total_recorded, report.total_actual), (1, 5));
+        assert_eq!(report.mismatches.len(), 1);
+        assert!(state.storage_usage.is_empty());
+
+        check_storage_usage(true, &mut state);
+        assert_eq!(state.storage_usage[&owner()], StorageUsage { bytes: 5, file_count: 1 });
+        assert_eq!(state.total_storage_used, 5);
+        assert!(check_storage_usage(false, &mut state).mismatches.is_empty());
+    }
+}
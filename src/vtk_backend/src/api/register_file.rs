@@ -1,15 +1,22 @@
-use crate::{FileMetadata, State, with_state_mut};
+use crate::api::folders::unique_name;
+use crate::api::quotas;
+use crate::storage::is_valid_blob_id;
+use crate::vetkeys::EncryptedFileData;
+use crate::{FileContent, FileMetadata, State, StorageProvider, UploadFileError};
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
 pub struct RegisterFileRequest {
     pub file_name: String,
-    pub storage_provider: String, // "icp" or "walrus"
-    pub blob_id: Option<String>,  // Only for Walrus
+    pub storage_provider: StorageProvider,
+    pub blob_id: Option<String>, // Required for Walrus, must be empty for ICP
     pub requested_at: u64,
     pub uploaded_at: Option<u64>,
+    /// Expected size of the content, reserved against the caller's storage quota.
+    pub size: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
@@ -17,31 +24,95 @@ pub struct RegisterFileResponse {
     pub file_id: u64,
 }
 
-pub fn register_file(caller: Principal, req: RegisterFileRequest) -> RegisterFileResponse {
-    with_state_mut(|state: &mut State| {
-        let file_id = state.generate_file_id();
-        let metadata = FileMetadata {
-            file_name: req.file_name,
-            requester_principal: caller,
-            requested_at: req.requested_at,
-            uploaded_at: req.uploaded_at,
-            storage_provider: req.storage_provider,
-            blob_id: req.blob_id,
-            is_encrypted: false, // Not encrypted yet, will be encrypted on upload
+pub fn register_file(
+    caller: Principal,
+    req: RegisterFileRequest,
+    state: &mut State,
+) -> Result<RegisterFileResponse, UploadFileError> {
+    if caller == Principal::anonymous() {
+        return Err(UploadFileError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(UploadFileError::AccountSuspended);
+    }
+    let is_walrus = req.storage_provider == StorageProvider::Walrus;
+    match &req.blob_id {
+        Some(blob_id) if is_walrus && is_valid_blob_id(blob_id) => {}
+        None if !is_walrus => {}
+        _ => return Err(UploadFileError::InvalidInput),
+    }
+    // Walrus blobs take no space in the canister.
+    let size = if is_walrus { 0 } else { req.size.unwrap_or(0) };
+    quotas::check_quota(state, caller, size)?;
+
+    let file_id = state.generate_file_id();
+    quotas::reserve(state, caller, file_id, size);
+
+    // Walrus files record the Sui address the registrant had linked. Ownership of the blob
+    // on Walrus is not checked.
+    if is_walrus {
+        if let Some(address) = state.user_profiles.get(&caller).and_then(|p| p.sui_address.clone()) {
+            state.walrus_registrants.insert(file_id, address);
+        }
+    }
+
+    let metadata = FileMetadata {
+        file_name: unique_name(state, caller, None, &req.file_name),
+        requester_principal: caller,
+        requested_at: req.requested_at,
+        uploaded_at: req.uploaded_at,
+        storage_provider: req.storage_provider,
+        blob_id: req.blob_id,
+        is_encrypted: false, // Not encrypted yet, will be encrypted on upload
+    };
+    if is_walrus {
+        // The blob is already on Walrus, so there is nothing left to upload.
+        let content = FileContent::Uploaded {
+            num_chunks: 0,
+            file_type: String::new(),
+            vetkey_metadata: EncryptedFileData {
+                encrypted_content: Vec::new(),
+                file_owners: vec![caller],
+                encryption_metadata: HashMap::new(),
+            },
         };
-        // Insert into file_data with empty content for now
+        state.file_data.insert(file_id, crate::File { metadata, content });
+    } else {
+        // Insert into file_data with empty content for now. The alias lets the
+        // content be uploaded later, see `upload_file_to_alias`.
+        let alias = state.generate_token();
         state.file_data.insert(file_id, crate::File {
             metadata,
-            content: crate::FileContent::Pending { alias: String::new() },
+            content: FileContent::Pending { alias: alias.clone() },
         });
+        crate::api::file_requests::index_alias(state, file_id, alias, false);
+    }
+    
+    // Add the caller as the owner of this file
+    state.add_owned_file(caller, file_id);
         
-        // Add the caller as the owner of this file
-        state
-            .file_owners
-            .entry(caller)
-            .or_insert_with(Vec::new)
-            .push(file_id);
-            
-        RegisterFileResponse { file_id }
-    })
-} 
\ No newline at end of file
+    Ok(RegisterFileResponse { file_id })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn anonymous_callers_cannot_register_files() {
+        let mut state = State::default();
+        let request = RegisterFileRequest {
+            file_name: "video.mp4".to_string(),
+            storage_provider: StorageProvider::Icp,
+            blob_id: None,
+            requested_at: 0,
+            uploaded_at: None,
+            size: None,
+        };
+        assert_eq!(
+            register_file(Principal::anonymous(), request, &mut state).map(|response| response.file_id),
+            Err(UploadFileError::NotAuthenticated)
+        );
+        assert!(state.file_data.is_empty());
+    }
+}
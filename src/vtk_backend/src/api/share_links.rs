@@ -0,0 +1,411 @@
+use crate::api::download_file::read_chunk;
+use crate::{FileDownloadResponse, PasswordHash, ShareLink, ShareLinkInfo, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use subtle::ConstantTimeEq;
+
+/// PBKDF2 rounds for new link passwords. The password is checked on every chunk, so this is
+/// kept moderate; guessing is limited by locking the link instead.
+pub const PASSWORD_HASH_ITERATIONS: u32 = 10_000;
+/// Wrong passwords in a row after which a link is locked.
+pub const MAX_PASSWORD_ATTEMPTS: u32 = 5;
+/// How long a link stays locked after too many wrong passwords: 15 minutes.
+pub const PASSWORD_LOCKOUT: u64 = 15 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CreateShareLinkRequest {
+    pub file_id: u64,
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u64>,
+    pub password: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ShareLinkError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    #[serde(rename = "expired")]
+    Expired,
+    #[serde(rename = "download_limit_reached")]
+    DownloadLimitReached,
+    #[serde(rename = "invalid_password")]
+    InvalidPassword,
+    /// Too many wrong passwords were given; the link is locked for `PASSWORD_LOCKOUT`.
+    #[serde(rename = "too_many_attempts")]
+    TooManyAttempts,
+    /// The caller's account, or the account of the link's owner, is suspended.
+    #[serde(rename = "account_suspended")]
+    AccountSuspended,
+}
+
+/// Mints a new share link for a file owned by the caller and returns its token.
+pub fn create_share_link(
+    caller: Principal,
+    request: CreateShareLinkRequest,
+    state: &mut State,
+) -> Result<String, ShareLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(ShareLinkError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(ShareLinkError::AccountSuspended);
+    }
+
+    if !state.file_data.contains_key(&request.file_id) {
+        return Err(ShareLinkError::NotFound);
+    }
+    if !state.is_file_owner(caller, request.file_id) {
+        return Err(ShareLinkError::PermissionError);
+    }
+
+    if request.max_downloads == Some(0)
+        || request.expires_at.is_some_and(|t| t <= crate::get_time())
+        || request.password.as_ref().is_some_and(|p| p.is_empty())
+    {
+        return Err(ShareLinkError::InvalidInput);
+    }
+
+    let password = request.password.map(|password| {
+        let salt = state.random_bytes::<16>().to_vec();
+        let hash = hash_password(&salt, &password, PASSWORD_HASH_ITERATIONS);
+        PasswordHash {
+            salt,
+            hash,
+            iterations: PASSWORD_HASH_ITERATIONS,
+        }
+    });
+
+    let token = state.generate_token();
+    state.share_links.insert(
+        token.clone(),
+        ShareLink {
+            file_id: request.file_id,
+            owner: caller,
+            created_at: crate::get_time(),
+            expires_at: request.expires_at,
+            max_downloads: request.max_downloads,
+            chunk_downloads: BTreeMap::new(),
+            password,
+            failed_attempts: 0,
+            locked_until: None,
+        },
+    );
+    state
+        .file_share_links
+        .entry(request.file_id)
+        .or_default()
+        .push(token.clone());
+
+    Ok(token)
+}
+
+/// Deletes a share link. Only the owner of the link can revoke it.
+pub fn revoke_link(caller: Principal, token: String, state: &mut State) -> Result<(), ShareLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(ShareLinkError::NotAuthenticated);
+    }
+
+    match state.share_links.get(&token) {
+        None => return Err(ShareLinkError::NotFound),
+        Some(link) if link.owner != caller => return Err(ShareLinkError::PermissionError),
+        Some(_) => {}
+    }
+
+    remove_link(state, &token);
+    Ok(())
+}
+
+/// Downloads one chunk of a file through a share link. Anyone holding the token may call
+/// this, including anonymous principals. Links stop working while their owner is suspended.
+///
+/// Each chunk can be served `max_downloads` times. The password, if the link has one, is
+/// checked on every call, and too many wrong passwords lock the link for a while.
+pub fn download_by_link(
+    caller: Principal,
+    token: String,
+    chunk_id: u64,
+    password: Option<String>,
+    state: &mut State,
+) -> Result<FileDownloadResponse, ShareLinkError> {
+    let link = state.share_links.get(&token).ok_or(ShareLinkError::NotFound)?;
+    check_link(state, link)?;
+    let now = crate::get_time();
+    if link.locked_until.is_some_and(|t| t > now) {
+        return Err(ShareLinkError::TooManyAttempts);
+    }
+
+    if let Some(stored) = &link.password {
+        let Some(password) = password else {
+            return Err(ShareLinkError::InvalidPassword);
+        };
+        let matches = password_matches(stored, &password);
+        let link = state.share_links.get_mut(&token).ok_or(ShareLinkError::NotFound)?;
+        if !matches {
+            link.failed_attempts += 1;
+            if link.failed_attempts >= MAX_PASSWORD_ATTEMPTS {
+                link.failed_attempts = 0;
+                link.locked_until = Some(now.saturating_add(PASSWORD_LOCKOUT));
+            }
+            return Err(ShareLinkError::InvalidPassword);
+        }
+        link.failed_attempts = 0;
+    }
+
+    let link = &state.share_links[&token];
+    if link.is_chunk_exhausted(chunk_id) {
+        return Err(ShareLinkError::DownloadLimitReached);
+    }
+    let file_id = link.file_id;
+    if state.is_trashed(file_id) {
+        return Err(ShareLinkError::NotFound);
+    }
+    let response = match state.file_data.get(&file_id) {
+        Some(file) => read_chunk(state, file, file_id, chunk_id),
+        None => return Err(ShareLinkError::NotFound),
+    };
+
+    if matches!(response, FileDownloadResponse::FoundFile(_) | FileDownloadResponse::WalrusBlob(_)) {
+        if let Some(link) = state.share_links.get_mut(&token) {
+            *link.chunk_downloads.entry(chunk_id).or_default() += 1;
+        }
+        crate::api::burn_after_reading::record_download(state, file_id, caller, chunk_id);
+    }
+
+    Ok(response)
+}
+
+/// The links of a file that can still be used, as shown to the owner in `list_files`.
+pub fn active_share_links(state: &State, file_id: u64) -> Vec<ShareLinkInfo> {
+    let now = crate::get_time();
+    state
+        .file_share_links
+        .get(&file_id)
+        .into_iter()
+        .flatten()
+        .filter_map(|token| state.share_links.get(token).map(|link| (token, link)))
+        .filter(|(_, link)| !link.is_expired(now) && !link.is_exhausted())
+        .map(|(token, link)| ShareLinkInfo {
+            token: token.clone(),
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+            max_downloads: link.max_downloads,
+            download_count: link.download_count(),
+            has_password: link.password.is_some(),
+        })
+        .collect()
+}
+
+/// Removes every share link of a file, e.g. when the file is deleted.
+pub(crate) fn remove_file_links(state: &mut State, file_id: u64) {
+    for token in state.file_share_links.remove(&file_id).unwrap_or_default() {
+        state.share_links.remove(&token);
+    }
+}
+
+fn check_link(state: &State, link: &ShareLink) -> Result<(), ShareLinkError> {
+    if state.is_suspended(link.owner) {
+        return Err(ShareLinkError::AccountSuspended);
+    }
+    if link.is_expired(crate::get_time()) {
+        return Err(ShareLinkError::Expired);
+    }
+    Ok(())
+}
+
+fn remove_link(state: &mut State, token: &str) {
+    if let Some(link) = state.share_links.remove(token) {
+        if let Some(tokens) = state.file_share_links.get_mut(&link.file_id) {
+            tokens.retain(|t| t != token);
+            if tokens.is_empty() {
+                state.file_share_links.remove(&link.file_id);
+            }
+        }
+    }
+}
+
+fn hash_password(salt: &[u8], password: &str, iterations: u32) -> Vec<u8> {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+    hash.to_vec()
+}
+
+/// Compares in constant time, so response times do not reveal how much of the hash matched.
+fn password_matches(stored: &PasswordHash, password: &str) -> bool {
+    let hash = hash_password(&stored.salt, password, stored.iterations);
+    bool::from(hash.ct_eq(&stored.hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{vetkeys::EncryptedFileData, File, FileContent, FileData, FileMetadata, StorageProvider};
+    use std::collections::HashMap;
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn state_with_file() -> State {
+        let mut state = State::seeded();
+        state.file_data.insert(
+            0,
+            File {
+                metadata: FileMetadata {
+                    file_name: "test_file.txt".to_string(),
+                    requester_principal: owner(),
+                    requested_at: 12345,
+                    uploaded_at: Some(12345),
+                    storage_provider: StorageProvider::Icp,
+                    blob_id: None,
+                    is_encrypted: true,
+                },
+                content: FileContent::Uploaded {
+                    num_chunks: 2,
+                    file_type: "txt".to_string(),
+                    vetkey_metadata: EncryptedFileData {
+                        encrypted_content: vec![],
+                        file_owners: vec![owner()],
+                        encryption_metadata: HashMap::new(),
+                    },
+                },
+            },
+        );
+        state.file_contents.insert((0, 0), vec![1, 2, 3]);
+        state.file_contents.insert((0, 1), vec![4, 5]);
+        state.file_owners.insert(owner(), vec![0]);
+        state
+    }
+
+    fn request(password: Option<&str>, max_downloads: Option<u64>) -> CreateShareLinkRequest {
+        CreateShareLinkRequest {
+            file_id: 0,
+            expires_at: None,
+            max_downloads,
+            password: password.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn download_through_link() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(None, None), &mut state).unwrap();
+
+        let result = download_by_link(other(), token.clone(), 0, None, &mut state).unwrap();
+        assert_eq!(
+            result,
+            FileDownloadResponse::FoundFile(FileData {
+                contents: vec![1, 2, 3],
+                file_type: "txt".to_string(),
+                num_chunks: 2,
+            })
+        );
+        let result = download_by_link(other(), token.clone(), 1, None, &mut state).unwrap();
+        assert!(matches!(result, FileDownloadResponse::FoundFile(_)));
+        assert_eq!(state.share_links[&token].download_count(), 1);
+        assert_eq!(active_share_links(&state, 0)[0].download_count, 1);
+        assert_eq!(download_by_link(other(), "nope".to_string(), 0, None, &mut state), Err(ShareLinkError::NotFound));
+    }
+
+    #[test]
+    fn only_owner_can_create_link() {
+        let mut state = state_with_file();
+        assert_eq!(
+            create_share_link(other(), request(None, None), &mut state),
+            Err(ShareLinkError::PermissionError)
+        );
+        assert_eq!(
+            create_share_link(Principal::anonymous(), request(None, None), &mut state),
+            Err(ShareLinkError::NotAuthenticated)
+        );
+    }
+
+    #[test]
+    fn password_is_required_and_checked() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(Some("hunter2"), None), &mut state).unwrap();
+
+        let stored = state.share_links[&token].password.clone().unwrap();
+        assert_eq!(stored.iterations, PASSWORD_HASH_ITERATIONS);
+        assert_eq!(stored.hash, hash_password(&stored.salt, "hunter2", PASSWORD_HASH_ITERATIONS));
+
+        assert_eq!(download_by_link(other(), token.clone(), 0, None, &mut state), Err(ShareLinkError::InvalidPassword));
+        assert_eq!(
+            download_by_link(other(), token.clone(), 0, Some("wrong".to_string()), &mut state),
+            Err(ShareLinkError::InvalidPassword)
+        );
+        for chunk_id in 0..2 {
+            assert!(download_by_link(other(), token.clone(), chunk_id, Some("hunter2".to_string()), &mut state).is_ok());
+        }
+        assert_eq!(state.share_links[&token].failed_attempts, 0);
+    }
+
+    #[test]
+    fn wrong_passwords_lock_the_link() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(Some("hunter2"), None), &mut state).unwrap();
+        let attempt = |state: &mut State, password: &str| {
+            download_by_link(other(), token.clone(), 0, Some(password.to_string()), state)
+        };
+
+        for _ in 0..MAX_PASSWORD_ATTEMPTS {
+            assert_eq!(attempt(&mut state, "wrong"), Err(ShareLinkError::InvalidPassword));
+        }
+        // Locked links refuse even the right password, without hashing anything.
+        assert_eq!(attempt(&mut state, "hunter2"), Err(ShareLinkError::TooManyAttempts));
+
+        state.share_links.get_mut(&token).unwrap().locked_until = Some(crate::get_time());
+        assert!(attempt(&mut state, "hunter2").is_ok());
+    }
+
+    #[test]
+    fn download_limit_is_enforced() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(None, Some(1)), &mut state).unwrap();
+
+        assert!(download_by_link(other(), token.clone(), 0, None, &mut state).is_ok());
+        assert_eq!(
+            download_by_link(other(), token.clone(), 0, None, &mut state),
+            Err(ShareLinkError::DownloadLimitReached)
+        );
+        assert!(active_share_links(&state, 0).is_empty());
+
+        // The download that was started can be finished, but each chunk is served only once.
+        assert!(download_by_link(other(), token.clone(), 1, None, &mut state).is_ok());
+        assert_eq!(download_by_link(other(), token, 1, None, &mut state), Err(ShareLinkError::DownloadLimitReached));
+    }
+
+    #[test]
+    fn expired_link_is_rejected() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(None, None), &mut state).unwrap();
+        state.share_links.get_mut(&token).unwrap().expires_at = Some(crate::get_time());
+
+        assert_eq!(download_by_link(other(), token, 1, None, &mut state), Err(ShareLinkError::Expired));
+        assert!(active_share_links(&state, 0).is_empty());
+    }
+
+    #[test]
+    fn revoked_link_stops_working() {
+        let mut state = state_with_file();
+        let token = create_share_link(owner(), request(None, None), &mut state).unwrap();
+        assert_eq!(active_share_links(&state, 0).len(), 1);
+
+        assert_eq!(revoke_link(other(), token.clone(), &mut state), Err(ShareLinkError::PermissionError));
+        assert_eq!(revoke_link(owner(), token.clone(), &mut state), Ok(()));
+
+        assert_eq!(download_by_link(other(), token, 1, None, &mut state), Err(ShareLinkError::NotFound));
+        assert!(active_share_links(&state, 0).is_empty());
+    }
+}
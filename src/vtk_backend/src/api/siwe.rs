@@ -0,0 +1,340 @@
+use crate::{PublicProfile, State, WalletChallenge};
+use candid::{CandidType, Principal};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// How long a challenge can be signed: 5 minutes.
+pub const WALLET_CHALLENGE_TTL: u64 = 5 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum WalletLinkError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The caller has no profile or no outstanding challenge, or no account has the address.
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// The challenge was not signed in time. A new one has to be requested.
+    #[serde(rename = "expired")]
+    Expired,
+    /// The signature was not made by the challenged address.
+    #[serde(rename = "invalid_signature")]
+    InvalidSignature,
+    /// The address is linked to another account.
+    #[serde(rename = "address_in_use")]
+    AddressInUse,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SiweChallengeRequest {
+    pub address: String,
+    /// The host of the frontend requesting the signature, e.g. `vault.example.com`.
+    pub domain: String,
+    /// The URI of the frontend, e.g. `https://vault.example.com`.
+    pub uri: String,
+    /// The chain the wallet is connected to, e.g. 8453 for Base.
+    pub chain_id: u64,
+}
+
+/// Issues a Sign-In with Ethereum (EIP-4361) message for the caller to sign with `address`,
+/// replacing any previous challenge. The signature is then submitted with `link_eth_address`.
+pub fn prepare_eth_link(
+    caller: Principal,
+    request: SiweChallengeRequest,
+    state: &mut State,
+) -> Result<WalletChallenge, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    if !state.user_profiles.contains_key(&caller) {
+        return Err(WalletLinkError::NotFound);
+    }
+    let address = parse_address(&request.address).ok_or(WalletLinkError::InvalidInput)?;
+    let is_token = |s: &str| !s.is_empty() && !s.chars().any(char::is_whitespace);
+    if !is_token(&request.domain) || !is_token(&request.uri) || request.chain_id == 0 {
+        return Err(WalletLinkError::InvalidInput);
+    }
+
+    let now = crate::get_time();
+    let expires_at = now.saturating_add(WALLET_CHALLENGE_TTL);
+    let address = checksum_address(&address);
+    let message = format!(
+        "{domain} wants you to sign in with your Ethereum account:\n\
+         {address}\n\
+         \n\
+         Link this address to the account {principal}.\n\
+         \n\
+         URI: {uri}\n\
+         Version: 1\n\
+         Chain ID: {chain_id}\n\
+         Nonce: {nonce}\n\
+         Issued At: {issued_at}\n\
+         Expiration Time: {expiration_time}",
+        domain = request.domain,
+        // The domain and URI are chosen by the caller, so the statement names the account
+        // the wallet is signing for.
+        principal = caller,
+        uri = request.uri,
+        chain_id = request.chain_id,
+        nonce = state.generate_nonce(),
+        issued_at = rfc3339(now),
+        expiration_time = rfc3339(expires_at),
+    );
+    let challenge = WalletChallenge {
+        address,
+        message,
+        expires_at,
+    };
+    state.eth_challenges.insert(caller, challenge.clone());
+    Ok(challenge)
+}
+
+/// Verifies the signature of the caller's challenge, made with `personal_sign`, and links the
+/// challenged address to the caller's profile. A challenge can only be used once.
+pub fn link_eth_address(caller: Principal, signature: String, state: &mut State) -> Result<String, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let challenge = state.eth_challenges.remove(&caller).ok_or(WalletLinkError::NotFound)?;
+    if challenge.expires_at < crate::get_time() {
+        return Err(WalletLinkError::Expired);
+    }
+    let signature = hex::decode(signature.trim_start_matches("0x")).map_err(|_| WalletLinkError::InvalidInput)?;
+    let signer = recover_address(&challenge.message, &signature).ok_or(WalletLinkError::InvalidSignature)?;
+    let address = challenge.address.to_lowercase();
+    if signer != address {
+        return Err(WalletLinkError::InvalidSignature);
+    }
+    if state.eth_addresses.get(&address).is_some_and(|&owner| owner != caller) {
+        return Err(WalletLinkError::AddressInUse);
+    }
+
+    let profile = state.user_profiles.get_mut(&caller).ok_or(WalletLinkError::NotFound)?;
+    if let Some(previous) = profile.eth_address.replace(challenge.address.clone()) {
+        state.eth_addresses.remove(&previous.to_lowercase());
+    }
+    state.eth_addresses.insert(address, caller);
+    Ok(challenge.address)
+}
+
+pub fn unlink_eth_address(caller: Principal, state: &mut State) -> Result<(), WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let address = state
+        .user_profiles
+        .get_mut(&caller)
+        .and_then(|profile| profile.eth_address.take())
+        .ok_or(WalletLinkError::NotFound)?;
+    state.eth_addresses.remove(&address.to_lowercase());
+    Ok(())
+}
+
+/// Looks up the account an Ethereum address is linked to, e.g. to attribute a payment.
+pub fn resolve_eth_address(caller: Principal, address: String, state: &State) -> Result<PublicProfile, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let address = parse_address(&address).ok_or(WalletLinkError::InvalidInput)?;
+    state
+        .eth_addresses
+        .get(&address)
+        .and_then(|principal| state.user_profiles.get(principal))
+        .map(PublicProfile::from)
+        .ok_or(WalletLinkError::NotFound)
+}
+
+/// Removes the Ethereum address of an account from the index, e.g. when it is deleted.
+pub(crate) fn remove_eth_address(state: &mut State, account: Principal) {
+    state.eth_challenges.remove(&account);
+    state.eth_addresses.retain(|_, owner| *owner != account);
+}
+
+/// Parses a `0x`-prefixed, 20-byte hex address into its lowercase form.
+fn parse_address(address: &str) -> Option<String> {
+    let hex = address.strip_prefix("0x")?;
+    (hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| format!("0x{}", hex.to_lowercase()))
+}
+
+/// EIP-55 mixed-case checksum encoding of a lowercase address.
+fn checksum_address(address: &str) -> String {
+    let hex = &address[2..];
+    let hash = Keccak256::digest(hex.as_bytes());
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// Recovers the lowercase address that signed `message` with `personal_sign` (EIP-191).
+fn recover_address(message: &str, signature: &[u8]) -> Option<String> {
+    if signature.len() != 65 {
+        return None;
+    }
+    let prefixed = format!("\x19Ethereum Signed Message:\n{}{}", message.len(), message);
+    let hash = Keccak256::digest(prefixed.as_bytes());
+    let recovery_id = RecoveryId::from_byte(signature[64].checked_sub(27).unwrap_or(signature[64]))?;
+    let signature = Signature::from_slice(&signature[..64]).ok()?;
+    let key = VerifyingKey::recover_from_prehash(&hash, &signature, recovery_id).ok()?;
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    Some(format!("0x{}", hex::encode(&hash[12..])))
+}
+
+/// Formats a timestamp in nanoseconds as an RFC 3339 UTC date-time, as EIP-4361 requires.
+pub(crate) fn rfc3339(nanos: u64) -> String {
+    let secs = nanos / 1_000_000_000;
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::create_user_profile;
+    use crate::CreateUserRequest;
+    use k256::ecdsa::SigningKey;
+
+    fn alice() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn bob() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn setup() -> State {
+        let mut state = State::seeded();
+        for (principal, username) in [(alice(), "alice"), (bob(), "bob")] {
+            let request = CreateUserRequest {
+                username: username.to_string(),
+                display_name: None,
+                email: None,
+            };
+            create_user_profile(principal, request, &mut state);
+        }
+        state
+    }
+
+    fn wallet(seed: u8) -> (SigningKey, String) {
+        let key = SigningKey::from_bytes(&[seed; 32].into()).unwrap();
+        let point = key.verifying_key().to_encoded_point(false);
+        let hash = Keccak256::digest(&point.as_bytes()[1..]);
+        (key, format!("0x{}", hex::encode(&hash[12..])))
+    }
+
+    fn sign(key: &SigningKey, message: &str) -> String {
+        let prefixed = format!("\x19Ethereum Signed Message:\n{}{}", message.len(), message);
+        let hash = Keccak256::digest(prefixed.as_bytes());
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let mut bytes = signature.to_bytes().to_vec();
+        bytes.push(recovery_id.to_byte() + 27);
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    fn request_challenge(state: &mut State, principal: Principal, address: &str) -> WalletChallenge {
+        let request = SiweChallengeRequest {
+            address: address.to_string(),
+            domain: "vault.example.com".to_string(),
+            uri: "https://vault.example.com".to_string(),
+            chain_id: 8453,
+        };
+        prepare_eth_link(principal, request, state).unwrap()
+    }
+
+    #[test]
+    fn signed_challenges_link_the_address() {
+        let mut state = setup();
+        let (key, address) = wallet(1);
+        let challenge = request_challenge(&mut state, alice(), &address);
+        assert!(challenge.message.starts_with("vault.example.com wants you to sign in with your Ethereum account:\n"));
+        assert!(challenge.message.contains("\nChain ID: 8453\n"));
+        assert!(challenge.message.contains(&format!("\nLink this address to the account {}.\n", alice())));
+        assert!(challenge.message.contains("\nIssued At: 1970-01-01T00:00:00Z\n"));
+        assert_eq!(challenge.address.to_lowercase(), address);
+
+        let linked = link_eth_address(alice(), sign(&key, &challenge.message), &mut state).unwrap();
+        assert_eq!(state.user_profiles[&alice()].eth_address, Some(linked));
+        assert_eq!(resolve_eth_address(bob(), address.to_uppercase().replace("0X", "0x"), &state).unwrap().username, "alice");
+
+        // Challenges are single use.
+        assert_eq!(
+            link_eth_address(alice(), sign(&key, &challenge.message), &mut state),
+            Err(WalletLinkError::NotFound)
+        );
+        // The address cannot be linked to a second account.
+        let challenge = request_challenge(&mut state, bob(), &address);
+        assert_eq!(
+            link_eth_address(bob(), sign(&key, &challenge.message), &mut state),
+            Err(WalletLinkError::AddressInUse)
+        );
+
+        unlink_eth_address(alice(), &mut state).unwrap();
+        assert_eq!(resolve_eth_address(bob(), address, &state), Err(WalletLinkError::NotFound));
+    }
+
+    #[test]
+    fn signatures_by_other_keys_are_rejected() {
+        let mut state = setup();
+        let (_, address) = wallet(1);
+        let (other_key, _) = wallet(2);
+        let challenge = request_challenge(&mut state, alice(), &address);
+        assert_eq!(
+            link_eth_address(alice(), sign(&other_key, &challenge.message), &mut state),
+            Err(WalletLinkError::InvalidSignature)
+        );
+        assert_eq!(state.user_profiles[&alice()].eth_address, None);
+
+        let request = SiweChallengeRequest {
+            address: "0x1234".to_string(),
+            domain: "vault.example.com".to_string(),
+            uri: "https://vault.example.com".to_string(),
+            chain_id: 8453,
+        };
+        assert_eq!(prepare_eth_link(alice(), request, &mut state), Err(WalletLinkError::InvalidInput));
+    }
+
+    #[test]
+    fn addresses_are_checksummed_and_dates_formatted() {
+        // Test vectors from EIP-55.
+        assert_eq!(
+            checksum_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert_eq!(
+            checksum_address("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"),
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"
+        );
+        assert_eq!(rfc3339(1_700_000_000 * 1_000_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(rfc3339(951_782_400 * 1_000_000_000), "2000-02-29T00:00:00Z");
+    }
+}
@@ -0,0 +1,265 @@
+use crate::api::siwe::{rfc3339, WalletLinkError, WALLET_CHALLENGE_TTL};
+use crate::{PublicProfile, State, WalletChallenge};
+use base64::Engine;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use candid::Principal;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Flag byte of Ed25519 signatures and public keys in Sui's serialized formats.
+const ED25519_FLAG: u8 = 0x00;
+/// Intent prefix of personal messages: scope `PersonalMessage`, version 0, app `Sui`.
+const PERSONAL_MESSAGE_INTENT: [u8; 3] = [3, 0, 0];
+
+type Blake2b256 = Blake2b<U32>;
+
+/// Issues a message for the caller to sign with the wallet controlling `address`, replacing any
+/// previous challenge. The signature is then submitted with `link_sui_address`.
+pub fn prepare_sui_link(caller: Principal, address: String, state: &mut State) -> Result<WalletChallenge, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    if !state.user_profiles.contains_key(&caller) {
+        return Err(WalletLinkError::NotFound);
+    }
+    let address = parse_address(&address).ok_or(WalletLinkError::InvalidInput)?;
+
+    let now = crate::get_time();
+    let expires_at = now.saturating_add(WALLET_CHALLENGE_TTL);
+    let message = format!(
+        "Link this Sui address to an account.\n\
+         \n\
+         Account: {principal}\n\
+         Address: {address}\n\
+         Nonce: {nonce}\n\
+         Issued At: {issued_at}\n\
+         Expiration Time: {expiration_time}",
+        principal = caller,
+        nonce = state.generate_nonce(),
+        issued_at = rfc3339(now),
+        expiration_time = rfc3339(expires_at),
+    );
+    let challenge = WalletChallenge {
+        address,
+        message,
+        expires_at,
+    };
+    state.sui_challenges.insert(caller, challenge.clone());
+    Ok(challenge)
+}
+
+/// Verifies the caller's challenge, signed with `signPersonalMessage`, and links the challenged
+/// address to the caller's profile. Only Ed25519 keys are supported. `signature` is the
+/// base64-encoded serialized signature: flag, signature and public key.
+pub fn link_sui_address(caller: Principal, signature: String, state: &mut State) -> Result<String, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let challenge = state.sui_challenges.remove(&caller).ok_or(WalletLinkError::NotFound)?;
+    if challenge.expires_at < crate::get_time() {
+        return Err(WalletLinkError::Expired);
+    }
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature)
+        .map_err(|_| WalletLinkError::InvalidInput)?;
+    let signer = verify_personal_message(challenge.message.as_bytes(), &signature)
+        .ok_or(WalletLinkError::InvalidSignature)?;
+    if signer != challenge.address {
+        return Err(WalletLinkError::InvalidSignature);
+    }
+    if state.sui_addresses.get(&signer).is_some_and(|&owner| owner != caller) {
+        return Err(WalletLinkError::AddressInUse);
+    }
+
+    let profile = state.user_profiles.get_mut(&caller).ok_or(WalletLinkError::NotFound)?;
+    if let Some(previous) = profile.sui_address.replace(signer.clone()) {
+        state.sui_addresses.remove(&previous);
+    }
+    state.sui_addresses.insert(signer.clone(), caller);
+    Ok(signer)
+}
+
+/// Unlinks the caller's Sui address. Walrus files registered while it was linked keep it as
+/// the address they were registered by.
+pub fn unlink_sui_address(caller: Principal, state: &mut State) -> Result<(), WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let address = state
+        .user_profiles
+        .get_mut(&caller)
+        .and_then(|profile| profile.sui_address.take())
+        .ok_or(WalletLinkError::NotFound)?;
+    state.sui_addresses.remove(&address);
+    Ok(())
+}
+
+/// Looks up the account a Sui address is linked to, e.g. the registrant of a Walrus file.
+pub fn resolve_sui_address(caller: Principal, address: String, state: &State) -> Result<PublicProfile, WalletLinkError> {
+    if caller == Principal::anonymous() {
+        return Err(WalletLinkError::NotAuthenticated);
+    }
+    let address = parse_address(&address).ok_or(WalletLinkError::InvalidInput)?;
+    state
+        .sui_addresses
+        .get(&address)
+        .and_then(|principal| state.user_profiles.get(principal))
+        .map(PublicProfile::from)
+        .ok_or(WalletLinkError::NotFound)
+}
+
+/// Removes the Sui address of an account from the index, e.g. when it is deleted.
+pub(crate) fn remove_sui_address(state: &mut State, account: Principal) {
+    state.sui_challenges.remove(&account);
+    state.sui_addresses.retain(|_, owner| *owner != account);
+}
+
+/// Parses a `0x`-prefixed, 32-byte hex address into its lowercase form.
+fn parse_address(address: &str) -> Option<String> {
+    let hex = address.strip_prefix("0x")?;
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| format!("0x{}", hex.to_lowercase()))
+}
+
+/// The address of an Ed25519 public key: BLAKE2b-256 of the flag byte and the key.
+fn address_of(public_key: &[u8; 32]) -> String {
+    let hash = Blake2b256::new().chain_update([ED25519_FLAG]).chain_update(public_key).finalize();
+    format!("0x{}", hex::encode(hash))
+}
+
+/// Verifies a serialized Ed25519 signature of a personal message and returns the signer's
+/// address.
+fn verify_personal_message(message: &[u8], signature: &[u8]) -> Option<String> {
+    let (&flag, rest) = signature.split_first()?;
+    if flag != ED25519_FLAG || rest.len() != 96 {
+        return None;
+    }
+    let signature = Signature::from_slice(&rest[..64]).ok()?;
+    let public_key: [u8; 32] = rest[64..].try_into().ok()?;
+    let key = VerifyingKey::from_bytes(&public_key).ok()?;
+    key.verify_strict(&personal_message_digest(message), &signature).ok()?;
+    Some(address_of(&public_key))
+}
+
+/// What wallets sign for a personal message: BLAKE2b-256 of the intent and the BCS-encoded
+/// message, i.e. its ULEB128 length followed by its bytes.
+fn personal_message_digest(message: &[u8]) -> [u8; 32] {
+    let mut length = Vec::new();
+    let mut n = message.len();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            length.push(byte);
+            break;
+        }
+        length.push(byte | 0x80);
+    }
+    Blake2b256::new()
+        .chain_update(PERSONAL_MESSAGE_INTENT)
+        .chain_update(length)
+        .chain_update(message)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{create_user_profile, list_files, register_file, RegisterFileRequest};
+    use crate::{CreateUserRequest, StorageProvider};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn alice() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn bob() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn setup() -> State {
+        let mut state = State::seeded();
+        for (principal, username) in [(alice(), "alice"), (bob(), "bob")] {
+            let request = CreateUserRequest {
+                username: username.to_string(),
+                display_name: None,
+                email: None,
+            };
+            create_user_profile(principal, request, &mut state);
+        }
+        state
+    }
+
+    fn wallet(seed: u8) -> (SigningKey, String) {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let address = address_of(key.verifying_key().as_bytes());
+        (key, address)
+    }
+
+    fn sign(key: &SigningKey, message: &str) -> String {
+        let signature = key.sign(&personal_message_digest(message.as_bytes()));
+        let serialized = [&[ED25519_FLAG][..], &signature.to_bytes(), key.verifying_key().as_bytes()].concat();
+        base64::engine::general_purpose::STANDARD.encode(serialized)
+    }
+
+    #[test]
+    fn signed_challenges_link_the_address_and_mark_walrus_registrations() {
+        let mut state = setup();
+        let (key, address) = wallet(1);
+        let challenge = prepare_sui_link(alice(), address.to_uppercase().replace("0X", "0x"), &mut state).unwrap();
+        assert_eq!(challenge.address, address);
+        assert!(challenge.message.contains(&format!("\nAccount: {}\n", alice())));
+
+        assert_eq!(link_sui_address(alice(), sign(&key, &challenge.message), &mut state), Ok(address.clone()));
+        assert_eq!(state.user_profiles[&alice()].sui_address, Some(address.clone()));
+        assert_eq!(resolve_sui_address(bob(), address.clone(), &state).unwrap().username, "alice");
+
+        let request = RegisterFileRequest {
+            file_name: "video.mp4".to_string(),
+            storage_provider: StorageProvider::Walrus,
+            blob_id: Some("M4hsZGQ1oCktdzegB6HnI6Mi28S2nqOPHxK-W7_4BUk".to_string()),
+            requested_at: 0,
+            uploaded_at: None,
+            size: None,
+        };
+        register_file(alice(), request, &mut state).unwrap();
+        let files = list_files(alice(), Default::default(), &state).unwrap().files;
+        assert_eq!(files[0].registered_by_sui_address, Some(address.clone()));
+
+        // The address cannot be linked to a second account.
+        let challenge = prepare_sui_link(bob(), address.clone(), &mut state).unwrap();
+        assert_eq!(
+            link_sui_address(bob(), sign(&key, &challenge.message), &mut state),
+            Err(WalletLinkError::AddressInUse)
+        );
+
+        unlink_sui_address(alice(), &mut state).unwrap();
+        assert_eq!(resolve_sui_address(bob(), address, &state), Err(WalletLinkError::NotFound));
+    }
+
+    #[test]
+    fn signatures_by_other_keys_are_rejected() {
+        let mut state = setup();
+        let (_, address) = wallet(1);
+        let (other_key, _) = wallet(2);
+        let challenge = prepare_sui_link(alice(), address, &mut state).unwrap();
+        assert_eq!(
+            link_sui_address(alice(), sign(&other_key, &challenge.message), &mut state),
+            Err(WalletLinkError::InvalidSignature)
+        );
+        assert_eq!(link_sui_address(alice(), "AA==".to_string(), &mut state), Err(WalletLinkError::NotFound));
+        assert_eq!(prepare_sui_link(alice(), "0x12".to_string(), &mut state), Err(WalletLinkError::InvalidInput));
+    }
+
+    #[test]
+    fn message_lengths_are_uleb128_encoded() {
+        let short = personal_message_digest(&[7; 5]);
+        let expected: [u8; 32] = Blake2b256::new().chain_update([3, 0, 0, 5]).chain_update([7; 5]).finalize().into();
+        assert_eq!(short, expected);
+
+        let long = personal_message_digest(&[7; 300]);
+        let expected: [u8; 32] =
+            Blake2b256::new().chain_update([3, 0, 0, 0xac, 0x02]).chain_update([7; 300]).finalize().into();
+        assert_eq!(long, expected);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::api::file_versions::previous_versions;
+use crate::api::groups::file_derivation_id;
+use crate::api::list_files::file_size;
+use crate::api::FileVersionInfo;
+use crate::api::share_links::active_share_links;
+use crate::{
+    EncryptedFileMetadata, FileContent, Folder, GroupRole, ShareLinkInfo, State, StorageProvider, Takeout, UserProfile,
+};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the layout of `TakeoutManifest` changes.
+pub const TAKEOUT_FORMAT_VERSION: u32 = 2;
+/// Size of the chunks a takeout is downloaded in.
+pub const TAKEOUT_CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TakeoutError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// There is no export, or no such chunk.
+    #[serde(rename = "not_found")]
+    NotFound,
+    /// Suspended users cannot take their data out until they are reactivated.
+    #[serde(rename = "account_suspended")]
+    AccountSuspended,
+}
+
+/// Everything the canister holds about a user, except for the file contents.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TakeoutManifest {
+    pub format_version: u32,
+    pub exported_at: u64,
+    pub principal: Principal,
+    pub profile: Option<UserProfile>,
+    pub folders: Vec<Folder>,
+    pub files: Vec<TakeoutFile>,
+    pub share_links: Vec<TakeoutShareLink>,
+    pub groups: Vec<TakeoutGroup>,
+    /// Recent sign-ins, most recent first. Added in version 2.
+    #[serde(default)]
+    pub logins: Vec<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TakeoutFile {
+    pub file_id: u64,
+    pub file_name: String,
+    pub file_type: String,
+    pub folder_id: Option<u64>,
+    pub requested_at: u64,
+    pub uploaded_at: Option<u64>,
+    pub storage_provider: StorageProvider,
+    pub blob_id: Option<String>,
+    pub is_encrypted: bool,
+    /// The contents are not part of the manifest. They can be downloaded, still encrypted
+    /// under the file's vetKey, with `download_file(file_id, chunk_id)` for each chunk.
+    pub num_chunks: u64,
+    pub size: u64,
+    /// The vetKey derivation ID the contents and `encrypted_metadata` are encrypted under.
+    pub derivation_id: Vec<u8>,
+    /// Earlier versions of the file, oldest first. Their contents are downloaded with
+    /// `download_version(file_id, version, chunk_id)`.
+    pub versions: Vec<FileVersionInfo>,
+    /// When the file was moved to the trash. Trashed files must be restored with
+    /// `restore_file` before their contents can be downloaded.
+    pub trashed_at: Option<u64>,
+    pub encrypted_metadata: Option<EncryptedFileMetadata>,
+    pub tags: Vec<String>,
+    pub attributes: Vec<(String, String)>,
+    pub group_id: Option<u64>,
+    pub burn_after_reading: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TakeoutShareLink {
+    pub file_id: u64,
+    pub link: ShareLinkInfo,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TakeoutGroup {
+    pub group_id: u64,
+    pub name: String,
+    pub alias: String,
+    pub role: GroupRole,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TakeoutInfo {
+    pub created_at: u64,
+    /// Size of the CBOR-encoded manifest in bytes.
+    pub size: u64,
+    pub num_chunks: u64,
+}
+
+/// Assembles the caller's data into a CBOR-encoded `TakeoutManifest`, replacing any
+/// previous export. The manifest is then downloaded with `download_takeout`.
+pub fn export_account(caller: Principal, state: &mut State) -> Result<TakeoutInfo, TakeoutError> {
+    if caller == Principal::anonymous() {
+        return Err(TakeoutError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(TakeoutError::AccountSuspended);
+    }
+    let manifest = build_manifest(state, caller);
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&manifest, &mut data).expect("failed to encode the takeout manifest");
+
+    let takeout = Takeout {
+        created_at: manifest.exported_at,
+        data,
+    };
+    let info = takeout_info(&takeout);
+    state.takeouts.insert(caller, takeout);
+    Ok(info)
+}
+
+pub fn get_takeout_info(caller: Principal, state: &State) -> Result<TakeoutInfo, TakeoutError> {
+    if caller == Principal::anonymous() {
+        return Err(TakeoutError::NotAuthenticated);
+    }
+    state.takeouts.get(&caller).map(takeout_info).ok_or(TakeoutError::NotFound)
+}
+
+pub fn download_takeout(caller: Principal, chunk_id: u64, state: &State) -> Result<Vec<u8>, TakeoutError> {
+    if caller == Principal::anonymous() {
+        return Err(TakeoutError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(TakeoutError::AccountSuspended);
+    }
+    let takeout = state.takeouts.get(&caller).ok_or(TakeoutError::NotFound)?;
+    takeout
+        .data
+        .chunks(TAKEOUT_CHUNK_SIZE)
+        .nth(chunk_id as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or(TakeoutError::NotFound)
+}
+
+/// Decodes a manifest produced by `export_account`.
+pub fn decode_manifest(data: &[u8]) -> Option<TakeoutManifest> {
+    ciborium::de::from_reader(data).ok()
+}
+
+pub(crate) fn build_manifest(state: &State, caller: Principal) -> TakeoutManifest {
+    let owned = state.file_owners.get(&caller).into_iter().flatten();
+    let trashed = state.user_trash.get(&caller).into_iter().flatten();
+    let files: Vec<TakeoutFile> = owned
+        .chain(trashed)
+        .filter_map(|&file_id| takeout_file(state, file_id))
+        .collect();
+    let share_links = files
+        .iter()
+        .flat_map(|file| {
+            active_share_links(state, file.file_id)
+                .into_iter()
+                .map(|link| TakeoutShareLink { file_id: file.file_id, link })
+        })
+        .collect();
+    let folders = state
+        .user_folders
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter_map(|folder_id| state.folders.get(folder_id).cloned())
+        .collect();
+    let groups = state
+        .user_groups
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter_map(|group_id| state.groups.get(group_id))
+        .filter_map(|group| {
+            group.members.get(&caller).map(|&role| TakeoutGroup {
+                group_id: group.group_id,
+                name: group.name.clone(),
+                alias: group.alias.clone(),
+                role,
+            })
+        })
+        .collect();
+
+    TakeoutManifest {
+        format_version: TAKEOUT_FORMAT_VERSION,
+        exported_at: crate::get_time(),
+        principal: caller,
+        profile: state.user_profiles.get(&caller).cloned(),
+        folders,
+        files,
+        share_links,
+        groups,
+        logins: state.login_history.get(&caller).into_iter().flatten().copied().collect(),
+    }
+}
+
+fn takeout_file(state: &State, file_id: u64) -> Option<TakeoutFile> {
+    let file = state.file_data.get(&file_id)?;
+    let (num_chunks, file_type) = match &file.content {
+        FileContent::Uploaded { num_chunks, file_type, .. }
+        | FileContent::PartiallyUploaded { num_chunks, file_type, .. } => (*num_chunks, file_type.clone()),
+        FileContent::Pending { .. } | FileContent::Consumed { .. } => (0, String::new()),
+    };
+    Some(TakeoutFile {
+        file_id,
+        file_name: file.metadata.file_name.clone(),
+        file_type,
+        folder_id: state.file_folders.get(&file_id).copied(),
+        requested_at: file.metadata.requested_at,
+        uploaded_at: file.metadata.uploaded_at,
+        storage_provider: file.metadata.storage_provider,
+        blob_id: file.metadata.blob_id.clone(),
+        is_encrypted: file.metadata.is_encrypted,
+        num_chunks,
+        size: file_size(state, file_id, file),
+        derivation_id: file_derivation_id(state, file_id),
+        versions: previous_versions(state, file_id),
+        trashed_at: state.trashed_files.get(&file_id).map(|trashed| trashed.trashed_at),
+        encrypted_metadata: state.encrypted_file_metadata.get(&file_id).cloned(),
+        tags: state.file_tags.get(&file_id).into_iter().flatten().cloned().collect(),
+        attributes: state
+            .file_attributes
+            .get(&file_id)
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        group_id: state.file_groups.get(&file_id).copied(),
+        burn_after_reading: state.burn_after_reading.contains_key(&file_id),
+    })
+}
+
+fn takeout_info(takeout: &Takeout) -> TakeoutInfo {
+    TakeoutInfo {
+        created_at: takeout.created_at,
+        size: takeout.data.len() as u64,
+        num_chunks: takeout.data.len().div_ceil(TAKEOUT_CHUNK_SIZE) as u64,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        create_folder, create_share_link, create_user_profile, delete_file, move_file, record_login,
+        update_file_metadata, upload_file_atomic, upload_new_version,
+        CreateFolderRequest, CreateShareLinkRequest, UpdateFileMetadataRequest, UploadFileAtomicRequest,
+        UploadNewVersionRequest,
+    };
+    use crate::CreateUserRequest;
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn upload(state: &mut State, principal: Principal, name: &str) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content: vec![1, 2, 3],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(principal, request, state).unwrap()
+    }
+
+    fn download(state: &State, principal: Principal) -> Vec<u8> {
+        let info = get_takeout_info(principal, state).unwrap();
+        (0..info.num_chunks)
+            .flat_map(|chunk_id| download_takeout(principal, chunk_id, state).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn export_contains_the_callers_data_only() {
+        let mut state = State::seeded();
+        let request = CreateUserRequest {
+            username: "alice".to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(owner(), request, &mut state);
+        let file_id = upload(&mut state, owner(), "invoice.pdf");
+        upload(&mut state, other(), "private.pdf");
+
+        let folder = create_folder(owner(), CreateFolderRequest { name: "Invoices".to_string(), parent_id: None }, &mut state)
+            .unwrap();
+        move_file(owner(), file_id, Some(folder.folder_id), &mut state).unwrap();
+        let request = UpdateFileMetadataRequest {
+            file_id,
+            tags: Some(vec!["finance".to_string()]),
+            attributes: None,
+        };
+        update_file_metadata(owner(), request, &mut state).unwrap();
+        let request = CreateShareLinkRequest {
+            file_id,
+            expires_at: None,
+            max_downloads: None,
+            password: None,
+        };
+        let token = create_share_link(owner(), request, &mut state).unwrap();
+        record_login(owner(), &mut state).unwrap();
+
+        export_account(owner(), &mut state).unwrap();
+        let manifest = decode_manifest(&download(&state, owner())).unwrap();
+
+        assert_eq!(manifest.principal, owner());
+        assert_eq!(manifest.profile.unwrap().username, "alice");
+        assert_eq!(manifest.folders.len(), 1);
+        assert_eq!(manifest.files.len(), 1);
+        let file = &manifest.files[0];
+        assert_eq!((file.file_name.as_str(), file.size, file.num_chunks), ("invoice.pdf", 3, 1));
+        assert_eq!(file.folder_id, Some(folder.folder_id));
+        assert_eq!(file.tags, vec!["finance"]);
+        assert_eq!(manifest.share_links[0].link.token, token);
+        assert_eq!(manifest.logins, vec![crate::get_time()]);
+    }
+
+    #[test]
+    fn export_includes_trashed_files_and_earlier_versions() {
+        let mut state = State::seeded();
+        let file_id = upload(&mut state, owner(), "invoice.pdf");
+        let request = UploadNewVersionRequest {
+            file_id,
+            content: vec![4, 5],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+        };
+        upload_new_version(owner(), request, &mut state).unwrap();
+        let trashed_id = upload(&mut state, owner(), "old.pdf");
+        delete_file(&mut state, owner(), trashed_id);
+
+        export_account(owner(), &mut state).unwrap();
+        let manifest = decode_manifest(&download(&state, owner())).unwrap();
+
+        assert_eq!(manifest.files.len(), 2);
+        let file = &manifest.files[0];
+        assert_eq!((file.size, file.trashed_at), (2, None));
+        assert_eq!(file.versions.iter().map(|v| (v.version, v.size)).collect::<Vec<_>>(), vec![(1, 3)]);
+        assert_eq!(file.derivation_id, owner().as_slice().to_vec());
+        let trashed = &manifest.files[1];
+        assert_eq!((trashed.file_id, trashed.trashed_at), (trashed_id, Some(crate::get_time())));
+    }
+
+    #[test]
+    fn version_1_manifests_decode_without_logins() {
+        #[derive(Serialize)]
+        struct ManifestV1 {
+            format_version: u32,
+            exported_at: u64,
+            principal: Principal,
+            profile: Option<UserProfile>,
+            folders: Vec<Folder>,
+            files: Vec<TakeoutFile>,
+            share_links: Vec<TakeoutShareLink>,
+            groups: Vec<TakeoutGroup>,
+        }
+        let manifest = ManifestV1 {
+            format_version: 1,
+            exported_at: 0,
+            principal: owner(),
+            profile: None,
+            folders: Vec::new(),
+            files: Vec::new(),
+            share_links: Vec::new(),
+            groups: Vec::new(),
+        };
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(&manifest, &mut data).unwrap();
+        let decoded = decode_manifest(&data).unwrap();
+        assert_eq!((decoded.format_version, decoded.logins.len()), (1, 0));
+    }
+
+    #[test]
+    fn takeouts_are_private_and_chunked() {
+        let mut state = State::seeded();
+        assert_eq!(get_takeout_info(owner(), &state), Err(TakeoutError::NotFound));
+        assert_eq!(export_account(Principal::anonymous(), &mut state), Err(TakeoutError::NotAuthenticated));
+
+        let info = export_account(owner(), &mut state).unwrap();
+        assert_eq!(info.num_chunks, 1);
+        assert_eq!(download_takeout(owner(), 1, &state), Err(TakeoutError::NotFound));
+        assert_eq!(download_takeout(other(), 0, &state), Err(TakeoutError::NotFound));
+    }
+}
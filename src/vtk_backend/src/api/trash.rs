@@ -0,0 +1,238 @@
+use crate::api::delete_file::purge_file;
+use crate::api::folders::unique_name;
+use crate::api::list_files::public_file_metadata;
+use crate::{with_state_mut, PublicFileMetadata, State, TrashedFile};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How often trashed files are checked for expiry.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TrashError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TrashEntry {
+    pub file: PublicFileMetadata,
+    pub trashed_at: u64,
+    /// When the file will be purged if it is not restored.
+    pub purge_at: u64,
+}
+
+/// Lists the files in the caller's trash, most recently deleted first.
+pub fn list_trash(caller: Principal, state: &State) -> Vec<TrashEntry> {
+    let mut entries: Vec<TrashEntry> = state
+        .user_trash
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter_map(|&file_id| {
+            let trashed = state.trashed_files.get(&file_id)?;
+            Some(TrashEntry {
+                file: public_file_metadata(state, caller, file_id)?,
+                trashed_at: trashed.trashed_at,
+                purge_at: trashed.trashed_at.saturating_add(state.trash_retention),
+            })
+        })
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.trashed_at));
+    entries
+}
+
+/// Moves a file out of the trash, back into its folder.
+///
+/// If the folder has been deleted in the meantime the file is restored to the root folder,
+/// and if its name is now taken a numeric suffix is added.
+pub fn restore_file(caller: Principal, file_id: u64, state: &mut State) -> Result<(), TrashError> {
+    if caller == Principal::anonymous() {
+        return Err(TrashError::NotAuthenticated);
+    }
+    match state.trashed_files.get(&file_id) {
+        None => return Err(TrashError::NotFound),
+        Some(trashed) if trashed.owner != caller => return Err(TrashError::PermissionError),
+        Some(_) => {}
+    }
+
+    state.trashed_files.remove(&file_id);
+    if let Some(files) = state.user_trash.get_mut(&caller) {
+        files.retain(|&id| id != file_id);
+    }
+
+    let folder_id = state
+        .file_folders
+        .get(&file_id)
+        .copied()
+        .filter(|folder_id| state.folders.contains_key(folder_id));
+    if folder_id.is_none() {
+        state.file_folders.remove(&file_id);
+    }
+
+    if let Some(file) = state.file_data.get(&file_id) {
+        let unique_name = unique_name(state, caller, folder_id, &file.metadata.file_name);
+        if let Some(file) = state.file_data.get_mut(&file_id) {
+            file.metadata.file_name = unique_name;
+        }
+    }
+
+    state.file_owners.entry(caller).or_default().push(file_id);
+    Ok(())
+}
+
+/// Permanently deletes every file in the caller's trash and returns how many were purged.
+pub fn empty_trash(caller: Principal, state: &mut State) -> Result<u64, TrashError> {
+    if caller == Principal::anonymous() {
+        return Err(TrashError::NotAuthenticated);
+    }
+    let file_ids = state.user_trash.remove(&caller).unwrap_or_default();
+    for &file_id in &file_ids {
+        purge_file(state, file_id);
+    }
+    Ok(file_ids.len() as u64)
+}
+
+/// Sets how long (in nanoseconds) files stay in the trash. Only admins may call this.
+pub fn set_trash_retention(retention: u64, state: &mut State) {
+    state.trash_retention = retention;
+}
+
+/// Purges the trashed files whose retention period has passed and returns how many were purged.
+pub fn purge_expired_trash(state: &mut State, now: u64) -> u64 {
+    let expired: Vec<u64> = state
+        .trashed_files
+        .iter()
+        .filter(|(_, trashed)| trashed.trashed_at.saturating_add(state.trash_retention) <= now)
+        .map(|(&file_id, _)| file_id)
+        .collect();
+    for &file_id in &expired {
+        purge_file(state, file_id);
+    }
+    expired.len() as u64
+}
+
+/// Periodically purges expired trash. Must be called from `init` and `post_upgrade`.
+pub fn schedule_trash_purge() {
+    ic_cdk_timers::set_timer_interval(PURGE_INTERVAL, || {
+        let purged = with_state_mut(|s| purge_expired_trash(s, crate::get_time()));
+        if purged > 0 {
+            ic_cdk::println!("purged {} expired files from the trash", purged);
+        }
+    });
+}
+
+pub(crate) fn move_to_trash(state: &mut State, owner: Principal, file_id: u64) {
+    if let Some(files) = state.file_owners.get_mut(&owner) {
+        files.retain(|&id| id != file_id);
+    }
+    state.trashed_files.insert(
+        file_id,
+        TrashedFile {
+            owner,
+            trashed_at: crate::get_time(),
+        },
+    );
+    state.user_trash.entry(owner).or_default().push(file_id);
+    // Nobody may keep uploading into a file request that has been deleted.
+    crate::api::file_requests::close_request(state, file_id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        create_share_link, delete_file, download_by_link, download_file, list_files, upload_file_atomic,
+        CreateShareLinkRequest, DeleteFileResult, ShareLinkError, UploadFileAtomicRequest,
+    };
+    use crate::FileDownloadResponse;
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn other() -> Principal {
+        Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap()
+    }
+
+    fn upload(state: &mut State, name: &str) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: name.to_string(),
+            content: vec![1, 2, 3],
+            file_type: "pdf".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(owner(), request, state).unwrap()
+    }
+
+    #[test]
+    fn deleted_files_can_be_restored() {
+        let mut state = State::seeded();
+        let file_id = upload(&mut state, "q3.pdf");
+        assert_eq!(delete_file(&mut state, owner(), file_id), DeleteFileResult::Ok);
+
+        assert!(list_files(owner(), Default::default(), &state).unwrap().files.is_empty());
+        assert_eq!(download_file(&state, owner(), file_id, 0), FileDownloadResponse::PermissionError);
+        let trash = list_trash(owner(), &state);
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].purge_at, trash[0].trashed_at + state.trash_retention);
+        assert!(list_trash(other(), &state).is_empty());
+
+        assert_eq!(restore_file(other(), file_id, &mut state), Err(TrashError::PermissionError));
+        assert_eq!(restore_file(owner(), file_id, &mut state), Ok(()));
+        assert!(list_trash(owner(), &state).is_empty());
+        assert!(matches!(download_file(&state, owner(), file_id, 0), FileDownloadResponse::FoundFile(_)));
+    }
+
+    #[test]
+    fn share_links_do_not_serve_trashed_files() {
+        let mut state = State::seeded();
+        let file_id = upload(&mut state, "q3.pdf");
+        let request = CreateShareLinkRequest { file_id, expires_at: None, max_downloads: None, password: None };
+        let token = create_share_link(owner(), request, &mut state).unwrap();
+        assert!(download_by_link(other(), token.clone(), 0, None, &mut state).is_ok());
+
+        delete_file(&mut state, owner(), file_id);
+        assert_eq!(download_by_link(other(), token.clone(), 0, None, &mut state), Err(ShareLinkError::NotFound));
+
+        empty_trash(owner(), &mut state).unwrap();
+        assert_eq!(download_by_link(other(), token, 0, None, &mut state), Err(ShareLinkError::NotFound));
+    }
+
+    #[test]
+    fn restored_file_gets_a_unique_name() {
+        let mut state = State::seeded();
+        let file_id = upload(&mut state, "q3.pdf");
+        delete_file(&mut state, owner(), file_id);
+        upload(&mut state, "q3.pdf");
+
+        restore_file(owner(), file_id, &mut state).unwrap();
+        assert_eq!(state.file_data[&file_id].metadata.file_name, "q3.pdf (1)");
+    }
+
+    #[test]
+    fn expired_files_are_purged() {
+        let mut state = State::seeded();
+        let old = upload(&mut state, "old.pdf");
+        let recent = upload(&mut state, "recent.pdf");
+        delete_file(&mut state, owner(), old);
+        delete_file(&mut state, owner(), recent);
+        state.trashed_files.get_mut(&recent).unwrap().trashed_at += 1_000;
+
+        let now = crate::get_time() + state.trash_retention;
+        assert_eq!(purge_expired_trash(&mut state, now), 1);
+        assert!(!state.file_data.contains_key(&old));
+        assert!(!state.file_contents.contains_key(&(old, 0)));
+        assert!(state.is_trashed(recent));
+        assert_eq!(list_trash(owner(), &state).len(), 1);
+    }
+}
@@ -1,6 +1,11 @@
-use crate::{File, FileContent, FileMetadata, State, vetkeys::VetKeyService};
+use crate::api::encrypted_metadata::{self, ENCRYPTED_FILE_TYPE};
+use crate::api::folders::unique_name;
+use crate::api::groups;
+use crate::api::quotas;
+use crate::{EncryptedFileMetadata, File, FileContent, FileMetadata, State, StorageProvider, UploadFileError, vetkeys::EncryptedFileData};
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct UploadFileAtomicRequest {
@@ -9,6 +14,18 @@ pub struct UploadFileAtomicRequest {
     // pub owner_key: Vec<u8>,
     pub file_type: String,
     pub num_chunks: u64,
+    /// Delete the contents once a recipient has downloaded every chunk.
+    pub burn_after_reading: Option<bool>,
+    /// Name and type encrypted under the file's vetKey. When set, `name` and `file_type`
+    /// must be empty so that no plaintext reaches the canister.
+    pub encrypted_metadata: Option<EncryptedFileMetadata>,
+    /// Size of the whole file for multi-chunk uploads. It is reserved against the caller's
+    /// storage quota up front, so the upload cannot fail halfway for lack of space.
+    pub total_size: Option<u64>,
+    /// Group to upload the file into. The content must then be encrypted under the
+    /// group's current vetKey instead of the owner's, and the caller must be an editor of
+    /// the group.
+    pub group_id: Option<u64>,
 }
 
 // pub fn upload_file_atomic(
@@ -22,7 +39,7 @@ pub struct UploadFileAtomicRequest {
 //         // File is uploaded in one chunk.
 //         FileContent::Uploaded {
 //             num_chunks: request.num_chunks,
-//             file_type: request.file_type,
+//             file_type: file_type.clone(),
 //             // owner_key: request.owner_key,
 //             // Remove shared_keys as it's no longer needed
 //             // shared_keys: BTreeMap::new(),
@@ -31,7 +48,7 @@ pub struct UploadFileAtomicRequest {
 //         // File will be uploaded in multiple chunks.
 //         FileContent::PartiallyUploaded {
 //             num_chunks: request.num_chunks,
-//             file_type: request.file_type,
+//             file_type,
 //             // owner_key: request.owner_key,
 //             // Remove shared_keys as it's no longer needed
 //             // shared_keys: BTreeMap::new(),
@@ -48,7 +65,7 @@ pub struct UploadFileAtomicRequest {
 //         file_id,
 //         File {
 //             metadata: FileMetadata {
-//                 file_name: request.name,
+//                 file_name: name,
 //                 // user_public_key: get_user_key(state, caller),
 //                 requester_principal: caller,
 //                 requested_at: get_time(),
@@ -66,58 +83,95 @@ pub struct UploadFileAtomicRequest {
 //     state
 //         .file_owners
 //         .entry(caller)
-//         .or_insert_with(Vec::new)
+//         .or_default()
 //         .push(file_id);
 
 //     file_id
 // }
 
-pub async fn upload_file_atomic(
+/// Uploads a file in one call, or the first chunk of a multi-chunk upload.
+///
+/// The content is expected to be encrypted client-side with the owner's vetKey, or the
+/// group's when uploading into a group, and is stored as is.
+pub fn upload_file_atomic(
     caller: Principal,
     request: UploadFileAtomicRequest,
     state: &mut State,
-) -> Result<u64, String> {
+) -> Result<u64, UploadFileError> {
     if caller == Principal::anonymous() {
-        return Err("Not authenticated".to_string());
+        return Err(UploadFileError::NotAuthenticated);
+    }
+    if state.is_suspended(caller) {
+        return Err(UploadFileError::AccountSuspended);
+    }
+    if request.num_chunks == 0 {
+        return Err(UploadFileError::InvalidInput);
+    }
+    let group_key = match request.group_id {
+        // Reads by group members are not tracked, so they would bypass burn-after-reading.
+        Some(_) if request.burn_after_reading == Some(true) => return Err(UploadFileError::InvalidInput),
+        Some(group_id) => Some(groups::upload_key(state, caller, group_id).map_err(|_| UploadFileError::InvalidInput)?),
+        None => None,
+    };
+
+    if let Some(metadata) = &request.encrypted_metadata {
+        // Name and type must be empty so that no plaintext reaches the canister.
+        if !request.name.is_empty() || !request.file_type.is_empty() || !encrypted_metadata::is_valid(metadata) {
+            return Err(UploadFileError::InvalidInput);
+        }
     }
 
+    let chunk_size = request.content.len() as u64;
+    let total_size = request.total_size.unwrap_or(0).max(chunk_size);
+    quotas::check_quota(state, caller, total_size)?;
+
     let file_id = state.generate_file_id();
-    
-    // Get all owners for this file (initially just the caller)
-    let owners = vec![caller];
-    
-    // Encrypt the file content using vetKeys
-    let encrypted_data = VetKeyService::encrypt_file_for_owners(
-        request.content.clone(),
-        file_id,
-        owners.clone(),
-    ).await.map_err(|e| format!("Encryption failed: {}", e))?;
-    
+    if let Some(key) = group_key {
+        groups::add_group_file(state, file_id, key);
+    }
+
+    let (name, file_type) = match request.encrypted_metadata {
+        Some(metadata) => {
+            state.encrypted_file_metadata.insert(file_id, metadata);
+            (encrypted_metadata::placeholder_name(file_id), ENCRYPTED_FILE_TYPE.to_string())
+        }
+        // Names are unique within a folder, so `resolve_path` finds a single file.
+        None => (unique_name(state, caller, None, &request.name), request.file_type),
+    };
+
+    let vetkey_metadata = EncryptedFileData {
+        encrypted_content: request.content.clone(),
+        file_owners: vec![caller],
+        encryption_metadata: HashMap::new(),
+    };
+
     let content = if request.num_chunks == 1 {
         FileContent::Uploaded {
             num_chunks: request.num_chunks,
-            file_type: request.file_type.clone(),
-            vetkey_metadata: encrypted_data,
+            file_type: file_type.clone(),
+            vetkey_metadata,
         }
     } else {
         FileContent::PartiallyUploaded {
             num_chunks: request.num_chunks,
-            file_type: request.file_type.clone(),
-            vetkey_metadata: encrypted_data,
+            file_type,
+            vetkey_metadata,
         }
     };
-    
-    // Store the encrypted content
-    state.file_contents.insert((file_id, 0), request.content.clone());
+
+    state.write_chunk(caller, file_id, 0, request.content);
+    if request.num_chunks > 1 {
+        quotas::reserve(state, caller, file_id, total_size - chunk_size);
+    }
     state.file_data.insert(
         file_id,
         File {
             metadata: FileMetadata {
-                file_name: request.name,
+                file_name: name,
                 requester_principal: caller,
                 requested_at: crate::get_time(),
                 uploaded_at: Some(crate::get_time()),
-                storage_provider: "icp".to_string(),
+                storage_provider: StorageProvider::Icp,
                 blob_id: None,
                 is_encrypted: true,
             },
@@ -126,11 +180,11 @@ pub async fn upload_file_atomic(
     );
 
     // Add the caller as the owner of this file
-    state
-        .file_owners
-        .entry(caller)
-        .or_insert_with(Vec::new)
-        .push(file_id);
+    state.add_owned_file(caller, file_id);
+
+    if request.burn_after_reading == Some(true) {
+        state.burn_after_reading.insert(file_id, BTreeMap::new());
+    }
 
     Ok(file_id)
 }
@@ -138,7 +192,7 @@ pub async fn upload_file_atomic(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{File, FileContent, FileMetadata, State};
+    use crate::FileDownloadResponse;
 
     fn make_request(name: &str, content: Vec<u8>, file_type: &str, num_chunks: u64) -> UploadFileAtomicRequest {
         UploadFileAtomicRequest {
@@ -146,30 +200,74 @@ mod test {
             content,
             file_type: file_type.to_string(),
             num_chunks,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
         }
     }
 
+    fn test_principal() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
     #[test]
     fn upload_single_chunk_file() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let file_id = upload_file_atomic(test_principal(), make_request("a.txt", vec![1, 2, 3], "txt", 1), &mut state).unwrap();
+
+        assert!(matches!(state.file_data[&file_id].content, FileContent::Uploaded { num_chunks: 1, .. }));
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1, 2, 3]));
+        assert_eq!(state.file_owners[&test_principal()], vec![file_id]);
+        assert!(matches!(
+            crate::api::download_file(&state, test_principal(), file_id, 0),
+            FileDownloadResponse::FoundFile(_)
+        ));
     }
 
     #[test]
     fn upload_multi_chunk_file_first_chunk() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let file_id = upload_file_atomic(test_principal(), make_request("a.txt", vec![1], "txt", 3), &mut state).unwrap();
+
+        assert!(matches!(
+            state.file_data[&file_id].content,
+            FileContent::PartiallyUploaded { num_chunks: 3, .. }
+        ));
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1]));
     }
 
     #[test]
     fn file_id_increments() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let first = upload_file_atomic(test_principal(), make_request("a", vec![1], "txt", 1), &mut state).unwrap();
+        let second = upload_file_atomic(test_principal(), make_request("b", vec![2], "txt", 1), &mut state).unwrap();
+        assert_eq!(second, first + 1);
     }
 
     #[test]
     fn anonymous_user_cannot_upload() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let result = upload_file_atomic(Principal::anonymous(), make_request("a", vec![1], "txt", 1), &mut state);
+        assert!(result.is_err());
+        assert!(state.file_data.is_empty());
+    }
+
+    #[test]
+    fn zero_chunks_are_rejected() {
+        let mut state = State::default();
+        let result = upload_file_atomic(test_principal(), make_request("a", vec![1], "txt", 0), &mut state);
+        assert_eq!(result, Err(UploadFileError::InvalidInput));
+        assert!(state.file_data.is_empty());
+        assert!(state.upload_reservations.is_empty());
+    }
+
+    #[test]
+    fn burn_after_reading_flag_is_recorded() {
+        let mut state = State::default();
+        let mut request = make_request("secret", vec![1], "txt", 1);
+        request.burn_after_reading = Some(true);
+        let file_id = upload_file_atomic(test_principal(), request, &mut state).unwrap();
+        assert!(state.burn_after_reading.contains_key(&file_id));
     }
 }
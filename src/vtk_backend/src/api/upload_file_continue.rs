@@ -1,3 +1,6 @@
+use crate::api::file_requests::{close_request, is_alias_uploader};
+use crate::api::file_versions;
+use crate::api::quotas;
 use crate::{FileContent, State, UploadFileContinueRequest, UploadFileError};
 use candid::Principal;
 
@@ -10,30 +13,56 @@ pub fn upload_file_continue(
     if caller == Principal::anonymous() {
         return Err(UploadFileError::NotAuthenticated);
     }
+    if state.is_suspended(caller) {
+        return Err(UploadFileError::AccountSuspended);
+    }
 
     let file_id = request.file_id;
     let chunk_id = request.chunk_id;
+    let storage_id = state.storage_id(file_id);
+
+    if state.is_trashed(file_id) {
+        return Err(UploadFileError::NotRequested);
+    }
+
+    // Check if the caller is the owner of this file (or is uploading it against a file
+    // request alias) before taking it out of the map
+    if state.file_data.get(&file_id).is_some_and(|file| {
+        file.metadata.requester_principal != caller && !is_alias_uploader(state, file_id, caller)
+    }) {
+        return Err(UploadFileError::NotAuthenticated);
+    }
+
+    // The chunk counts against the file owner's quota, whoever uploads it.
+    if let Some(owner) = state.file_data.get(&file_id).map(|file| file.metadata.requester_principal) {
+        if state.is_suspended(owner) {
+            return Err(UploadFileError::AccountSuspended);
+        }
+        quotas::charge_chunk(state, owner, file_id, request.contents.len() as u64)?;
+    }
+
+    // New versions of a file are stored under a fresh storage ID until they are complete.
+    if state.pending_versions.contains_key(&file_id) {
+        return file_versions::continue_version(state, file_id, chunk_id, request.contents);
+    }
 
     let updated_file_data = match state.file_data.remove(&file_id) {
         Some(mut file) => {
-            // Check if the caller is the owner of this file
-            if file.metadata.requester_principal != caller {
-                return Err(UploadFileError::NotAuthenticated);
-            }
-
             let updated_contents = match file.content {
                 FileContent::PartiallyUploaded { num_chunks, file_type, vetkey_metadata } => {
                     assert!(chunk_id < num_chunks, "invalid chunk id");
                     assert!(
-                        !state.file_contents.contains_key(&(file_id, chunk_id)),
+                        !state.file_contents.contains_key(&(storage_id, chunk_id)),
                         "chunk already uploaded"
                     );
-                    state.file_contents.insert((file_id, chunk_id), request.contents);
+                    state.write_chunk(file.metadata.requester_principal, storage_id, chunk_id, request.contents);
                     if state.file_contents
-                        .range((file_id, 0)..=(file_id, num_chunks - 1))
+                        .range((storage_id, 0)..=(storage_id, num_chunks - 1))
                         .count() as u64
                         == num_chunks
                     {
+                        close_request(state, file_id);
+                        quotas::release_reservation(state, file_id);
                         FileContent::Uploaded {
                             num_chunks,
                             file_type,
@@ -61,36 +90,59 @@ pub fn upload_file_continue(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{File, FileContent, FileMetadata, State};
-    use crate::api::UploadFileAtomicRequest;
-
-    fn make_atomic_request(name: &str, content: Vec<u8>, file_type: &str, num_chunks: u64) -> UploadFileAtomicRequest {
-        UploadFileAtomicRequest {
-            name: name.to_string(),
-            content,
-            file_type: file_type.to_string(),
+    use crate::api::{upload_file_atomic, UploadFileAtomicRequest};
+
+    fn test_principal() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn start_upload(state: &mut State, num_chunks: u64) -> u64 {
+        let request = UploadFileAtomicRequest {
+            name: "a.txt".to_string(),
+            content: vec![0],
+            file_type: "txt".to_string(),
             num_chunks,
-        }
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        upload_file_atomic(test_principal(), request, state).unwrap()
+    }
+
+    fn chunk(file_id: u64, chunk_id: u64) -> UploadFileContinueRequest {
+        UploadFileContinueRequest { file_id, chunk_id, contents: vec![chunk_id as u8] }
     }
 
     #[test]
     fn upload_file_continue_transitions() {
         let mut state = State::default();
-        let test_principal = Principal::from_text("2vxsx-fae").unwrap();
-        // First chunk (atomic) - Note: This test needs to be updated for async vetKey integration
-        // For now, we'll skip this test since upload_file_atomic is now async
-        // TODO: Update test to handle async upload_file_atomic
+        let file_id = start_upload(&mut state, 3);
+
+        upload_file_continue(test_principal(), chunk(file_id, 1), &mut state).unwrap();
+        assert!(matches!(state.file_data[&file_id].content, FileContent::PartiallyUploaded { .. }));
+
+        upload_file_continue(test_principal(), chunk(file_id, 2), &mut state).unwrap();
+        assert!(matches!(state.file_data[&file_id].content, FileContent::Uploaded { num_chunks: 3, .. }));
+        assert_eq!(state.file_contents.get(&(file_id, 2)), Some(vec![2]));
     }
 
     #[test]
     fn anonymous_user_cannot_continue_upload() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let file_id = start_upload(&mut state, 2);
+        let result = upload_file_continue(Principal::anonymous(), chunk(file_id, 1), &mut state);
+        assert!(matches!(result, Err(UploadFileError::NotAuthenticated)));
     }
 
     #[test]
     fn wrong_user_cannot_continue_upload() {
-        // TODO: Update test to handle async upload_file_atomic
-        // This test needs to be updated for the new async vetKey integration
+        let mut state = State::default();
+        let file_id = start_upload(&mut state, 2);
+        let other = Principal::from_text("jfuex-gikbm-ga2dq-pcair-eey").unwrap();
+        let result = upload_file_continue(other, chunk(file_id, 1), &mut state);
+        assert!(matches!(result, Err(UploadFileError::NotAuthenticated)));
+        assert!(!state.file_contents.contains_key(&(file_id, 1)));
+        assert!(state.file_data.contains_key(&file_id));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,148 @@
+use crate::{PublicProfile, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+
+pub const DEFAULT_SEARCH_LIMIT: u64 = 20;
+pub const MAX_SEARCH_LIMIT: u64 = 100;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum UserDirectoryError {
+    #[serde(rename = "not_authenticated")]
+    NotAuthenticated,
+    /// The search prefix is empty.
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    #[serde(rename = "not_found")]
+    NotFound,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchUsersRequest {
+    pub prefix: String,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SearchUsersResponse {
+    pub users: Vec<PublicProfile>,
+    /// Set if there may be more matches.
+    pub next_cursor: Option<String>,
+}
+
+/// Lists the public profiles of users whose username starts with `prefix`, in username
+/// order.
+pub fn search_users(
+    caller: Principal,
+    request: SearchUsersRequest,
+    state: &State,
+) -> Result<SearchUsersResponse, UserDirectoryError> {
+    if caller == Principal::anonymous() {
+        return Err(UserDirectoryError::NotAuthenticated);
+    }
+    let prefix = request.prefix.trim().to_lowercase();
+    if prefix.is_empty() {
+        return Err(UserDirectoryError::InvalidInput);
+    }
+    let limit = request.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT) as usize;
+
+    let start = match request.cursor {
+        Some(cursor) if cursor >= prefix => Bound::Excluded(cursor),
+        _ => Bound::Included(prefix.clone()),
+    };
+    let users: Vec<PublicProfile> = state
+        .username_to_principal
+        .range((start, Bound::Unbounded))
+        .take_while(|(username, _)| username.starts_with(&prefix))
+        .filter_map(|(_, principal)| state.user_profiles.get(principal).map(PublicProfile::from))
+        .take(limit)
+        .collect();
+
+    let next_cursor = if users.len() == limit {
+        users.last().map(|user| user.username.clone())
+    } else {
+        None
+    };
+    Ok(SearchUsersResponse { users, next_cursor })
+}
+
+/// Looks up the public profile of a user by their exact username.
+pub fn resolve_username(caller: Principal, username: String, state: &State) -> Result<PublicProfile, UserDirectoryError> {
+    if caller == Principal::anonymous() {
+        return Err(UserDirectoryError::NotAuthenticated);
+    }
+    state
+        .username_to_principal
+        .get(&username.trim().to_lowercase())
+        .and_then(|principal| state.user_profiles.get(principal))
+        .map(PublicProfile::from)
+        .ok_or(UserDirectoryError::NotFound)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::create_user_profile;
+    use crate::CreateUserRequest;
+
+    fn caller() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn setup(usernames: &[&str]) -> State {
+        let mut state = State::default();
+        for (i, username) in usernames.iter().enumerate() {
+            let request = CreateUserRequest {
+                username: username.to_string(),
+                display_name: None,
+                email: Some(format!("{}@example.com", username)),
+            };
+            create_user_profile(Principal::from_slice(&[i as u8 + 10]), request, &mut state);
+        }
+        state
+    }
+
+    fn search(state: &State, prefix: &str, cursor: Option<String>) -> SearchUsersResponse {
+        let request = SearchUsersRequest {
+            prefix: prefix.to_string(),
+            cursor,
+            limit: Some(2),
+        };
+        search_users(caller(), request, state).unwrap()
+    }
+
+    fn usernames(response: &SearchUsersResponse) -> Vec<&str> {
+        response.users.iter().map(|user| user.username.as_str()).collect()
+    }
+
+    #[test]
+    fn search_is_paginated_by_username() {
+        let state = setup(&["bob", "alice", "alfred", "alma", "albert"]);
+
+        let first = search(&state, "Al", None);
+        assert_eq!(usernames(&first), vec!["albert", "alfred"]);
+        let second = search(&state, "al", first.next_cursor);
+        assert_eq!(usernames(&second), vec!["alice", "alma"]);
+        let third = search(&state, "al", second.next_cursor);
+        assert!(third.users.is_empty());
+        assert_eq!(third.next_cursor, None);
+
+        let request = SearchUsersRequest::default();
+        assert_eq!(search_users(caller(), request, &state), Err(UserDirectoryError::InvalidInput));
+    }
+
+    #[test]
+    fn resolve_username_returns_public_fields_only() {
+        let state = setup(&["alice"]);
+        let profile = resolve_username(caller(), " Alice".to_string(), &state).unwrap();
+        assert_eq!(profile.principal, Principal::from_slice(&[10]));
+        assert_eq!(profile.username, "alice");
+        assert_eq!(resolve_username(caller(), "bob".to_string(), &state), Err(UserDirectoryError::NotFound));
+        assert_eq!(
+            resolve_username(Principal::anonymous(), "alice".to_string(), &state),
+            Err(UserDirectoryError::NotAuthenticated)
+        );
+    }
+}
@@ -28,7 +28,8 @@ pub fn create_user_profile(
         return UserResponse::AlreadyExists;
     }
 
-    // Create new user profile
+    // Create new user profile. Files may have been uploaded before the profile existed.
+    let usage = state.storage_usage.get(&caller).copied().unwrap_or_default();
     let user_profile = UserProfile {
         principal_id: caller,
         username: username.clone(),
@@ -36,9 +37,12 @@ pub fn create_user_profile(
         email: request.email,
         created_at: crate::get_time(),
         last_login: crate::get_time(),
-        storage_used: 0,
-        file_count: 0,
+        storage_used: usage.bytes,
+        file_count: usage.file_count,
         is_active: true,
+        avatar_file_id: None,
+        eth_address: None,
+        sui_address: None,
     };
 
     // Store user profile
@@ -77,6 +81,10 @@ pub fn update_user_profile(
         None => return UserResponse::NotFound,
     };
 
+    if request.avatar_file_id.is_some_and(|file_id| !state.is_file_owner(caller, file_id)) {
+        return UserResponse::InvalidInput;
+    }
+
     // Update fields if provided
     if let Some(username) = request.username {
         let username = username.trim().to_lowercase();
@@ -107,12 +115,18 @@ pub fn update_user_profile(
         profile.email = Some(email);
     }
 
+    if let Some(avatar_file_id) = request.avatar_file_id {
+        profile.avatar_file_id = Some(avatar_file_id);
+    }
+
     // Update the profile
     state.user_profiles.insert(caller, profile.clone());
 
     UserResponse::Ok(profile)
 }
 
+/// Marks the caller's account for deletion. After a grace period the profile is deleted
+/// along with everything the user owns, see `request_account_deletion`.
 pub fn delete_user_profile(caller: Principal, state: &mut State) -> UserResponse {
     // Check if caller is authenticated (not anonymous)
     if caller == Principal::anonymous() {
@@ -125,25 +139,23 @@ pub fn delete_user_profile(caller: Principal, state: &mut State) -> UserResponse
         None => return UserResponse::NotFound,
     };
 
-    // Remove username mapping
-    state.username_to_principal.remove(&profile.username);
-
-    // Remove user profile
-    state.user_profiles.remove(&caller);
-    state.user_count -= 1;
-
-    // Note: We don't delete user's files here - they should be handled separately
-    // to avoid data loss. Files can be cleaned up in a separate operation.
-
-    UserResponse::Ok(profile)
+    match crate::api::request_account_deletion(caller, state) {
+        Ok(_) => UserResponse::Ok(profile),
+        Err(_) => UserResponse::NotFound,
+    }
 }
 
-pub fn list_users(caller: Principal, state: &State) -> UserListResponse {
+/// Lists every profile, including private fields. Admins only.
+pub fn list_users(caller: Principal, is_controller: bool, state: &State) -> UserListResponse {
     // Check if caller is authenticated (not anonymous)
     if caller == Principal::anonymous() {
         return UserListResponse::NotAuthenticated;
     }
 
+    if !is_controller && !state.is_admin(caller) {
+        return UserListResponse::NotAuthorized;
+    }
+
     let users: Vec<UserProfile> = state.user_profiles.values().cloned().collect();
     UserListResponse::Ok(users)
 }
@@ -154,41 +166,12 @@ pub fn get_user_stats(caller: Principal, state: &State) -> UserResponse {
         return UserResponse::NotAuthenticated;
     }
 
-    let mut profile = match state.user_profiles.get(&caller) {
-        Some(p) => p.clone(),
-        None => return UserResponse::NotFound,
-    };
-
-    // Calculate current stats
-    let empty_vec = Vec::new();
-    let owned_files = state.file_owners.get(&caller).unwrap_or(&empty_vec);
-    let mut total_storage = 0u64;
-
-    for &file_id in owned_files {
-        if let Some(file) = state.file_data.get(&file_id) {
-            match &file.content {
-                crate::FileContent::Uploaded { num_chunks, .. } |
-                crate::FileContent::PartiallyUploaded { num_chunks, .. } => {
-                    // Calculate storage used by this file
-                    for chunk_id in 0..*num_chunks {
-                        if let Some(chunk_data) = state.file_contents.get(&(file_id, chunk_id)) {
-                            total_storage += chunk_data.len() as u64;
-                        }
-                    }
-                }
-                crate::FileContent::Pending { .. } => {
-                    // Pending files don't use storage yet
-                }
-            }
-        }
+    // `storage_used` and `file_count` are kept up to date as chunks are written and deleted,
+    // and `last_login` by `record_login`.
+    match state.user_profiles.get(&caller) {
+        Some(profile) => UserResponse::Ok(profile.clone()),
+        None => UserResponse::NotFound,
     }
-
-    // Update profile with current stats
-    profile.storage_used = total_storage;
-    profile.file_count = owned_files.len() as u64;
-    profile.last_login = crate::get_time();
-
-    UserResponse::Ok(profile)
 }
 
 #[cfg(test)]
@@ -203,7 +186,7 @@ mod tests {
     #[test]
     fn test_create_user_profile() {
         let mut state = State::default();
-        let principal = create_test_principal("2vxsx-fae");
+        let principal = create_test_principal("ivwno-rqaae-bagba-faydq-qci");
         
         let request = CreateUserRequest {
             username: "testuser".to_string(),
@@ -239,8 +222,8 @@ mod tests {
     #[test]
     fn test_duplicate_username_rejected() {
         let mut state = State::default();
-        let principal1 = create_test_principal("2vxsx-fae");
-        let principal2 = create_test_principal("3vxsx-fae");
+        let principal1 = create_test_principal("ivwno-rqaae-bagba-faydq-qci");
+        let principal2 = create_test_principal("jfuex-gikbm-ga2dq-pcair-eey");
         
         let request = CreateUserRequest {
             username: "testuser".to_string(),
@@ -260,7 +243,7 @@ mod tests {
     #[test]
     fn test_update_user_profile() {
         let mut state = State::default();
-        let principal = create_test_principal("2vxsx-fae");
+        let principal = create_test_principal("ivwno-rqaae-bagba-faydq-qci");
         
         // Create user first
         let create_request = CreateUserRequest {
@@ -275,6 +258,7 @@ mod tests {
             username: Some("newusername".to_string()),
             display_name: Some("New Display Name".to_string()),
             email: Some("new@example.com".to_string()),
+            avatar_file_id: None,
         };
 
         let result = update_user_profile(principal, update_request, &mut state);
@@ -286,4 +270,42 @@ mod tests {
             assert_eq!(profile.email, Some("new@example.com".to_string()));
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_avatar_must_be_an_own_file() {
+        let mut state = State::default();
+        let principal = create_test_principal("ivwno-rqaae-bagba-faydq-qci");
+        let create_request = CreateUserRequest {
+            username: "testuser".to_string(),
+            display_name: None,
+            email: None,
+        };
+        create_user_profile(principal, create_request, &mut state);
+        let upload_request = crate::api::UploadFileAtomicRequest {
+            name: "me.png".to_string(),
+            content: vec![1],
+            file_type: "png".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = crate::api::upload_file_atomic(principal, upload_request, &mut state).unwrap();
+
+        let avatar = |file_id| UpdateUserRequest {
+            username: None,
+            display_name: None,
+            email: None,
+            avatar_file_id: Some(file_id),
+        };
+        assert!(matches!(update_user_profile(principal, avatar(file_id + 1), &mut state), UserResponse::InvalidInput));
+        assert!(matches!(update_user_profile(principal, avatar(file_id), &mut state), UserResponse::Ok(_)));
+        assert_eq!(state.user_profiles[&principal].avatar_file_id, Some(file_id));
+
+        // Deleting the file for good clears the avatar.
+        crate::api::delete_file(&mut state, principal, file_id);
+        crate::api::empty_trash(principal, &mut state).unwrap();
+        assert_eq!(state.user_profiles[&principal].avatar_file_id, None);
+    }
+}
@@ -2,6 +2,8 @@ pub mod api;
 pub mod vetkeys;
 pub mod vetkd;
 pub mod declarations;
+pub mod randomness;
+pub mod storage;
 mod memory;
 
 use candid::CandidType;
@@ -13,14 +15,17 @@ use ic_stable_structures::{
 };
 
 use memory::Memory; // Only Memory is needed for file storage
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 // use std::ops::Bound::{Included, Excluded};
 
 thread_local! {
-    /// Initialize the state randomness with the current time.
-    static STATE: RefCell<State> = RefCell::new(State::new(&get_randomness_seed()[..]));
+    /// The state starts without randomness; it is seeded from `raw_rand`
+    /// right after init/upgrade (see `randomness::schedule_reseeding`).
+    static STATE: RefCell<State> = RefCell::new(State::new(&[]));
 }
 
 type FileId = u64;
@@ -39,9 +44,21 @@ pub struct FileMetadata {
     pub requester_principal: Principal,
     pub requested_at: u64,
     pub uploaded_at: Option<u64>,
-    pub storage_provider: String, // "icp" or "walrus"
-    pub blob_id: Option<String>,  // Only for Walrus files
-    pub is_encrypted: bool,       // VetKey encryption flag
+    pub storage_provider: StorageProvider,
+    pub blob_id: Option<String>, // Only for Walrus files
+    pub is_encrypted: bool,      // VetKey encryption flag
+}
+
+/// Where the content of a file is stored. See `storage::StorageBackend`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageProvider {
+    /// Chunks in the canister's stable memory.
+    #[default]
+    #[serde(rename = "icp")]
+    Icp,
+    /// A Walrus blob, referenced by `FileMetadata::blob_id`.
+    #[serde(rename = "walrus")]
+    Walrus,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -56,6 +73,9 @@ pub enum FileStatus {
         // No document_key needed here as we moved to vertkeys
         // document_key: Vec<u8>,
     },
+    /// A burn-after-reading file whose contents were deleted after it was read.
+    #[serde(rename = "consumed")]
+    Consumed { consumed_at: u64 },
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -66,16 +86,95 @@ pub struct PublicFileMetadata {
     pub group_alias: Option<String>,
     pub file_status: FileStatus,
     pub shared_with: Vec<()>,
+    pub share_links: Vec<ShareLinkInfo>,
+    pub burn_after_reading: bool,
+    /// The folder the file is in, `None` for the owner's root folder.
+    pub folder_id: Option<u64>,
+    pub tags: Vec<String>,
+    pub attributes: Vec<(String, String)>,
+    /// Set for files whose name and type are encrypted; `file_name` is then a placeholder.
+    pub encrypted_metadata: Option<EncryptedFileMetadata>,
+    /// For Walrus files, the Sui address linked to the account that registered the blob.
+    /// It is not proof that the address owns the blob on Walrus.
+    pub registered_by_sui_address: Option<String>,
+}
+
+/// A file's name and type, encrypted client-side under the file's vetKey.
+/// The backend stores them as opaque blobs and never sees the plaintext.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedFileMetadata {
+    pub name: Vec<u8>,
+    pub file_type: Vec<u8>,
+}
+
+/// A public link that lets anyone holding the token download a file.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShareLink {
+    pub file_id: u64,
+    pub owner: Principal,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u64>,
+    /// How many times each chunk has been served through the link. Each chunk can be served
+    /// `max_downloads` times, so the whole file can be downloaded at most that often.
+    pub chunk_downloads: BTreeMap<u64, u64>,
+    /// PBKDF2 hash of the link password, if one was set.
+    pub password: Option<PasswordHash>,
+    /// Wrong passwords given since the last correct one.
+    pub failed_attempts: u32,
+    /// Set after too many wrong passwords; the link refuses downloads until then.
+    pub locked_until: Option<u64>,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+impl ShareLink {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// How many downloads have been started, i.e. how often the first chunk was served.
+    pub fn download_count(&self) -> u64 {
+        self.chunk_downloads.get(&0).copied().unwrap_or(0)
+    }
+
+    /// Whether `chunk_id` has been served as often as the link allows.
+    pub fn is_chunk_exhausted(&self, chunk_id: u64) -> bool {
+        let served = self.chunk_downloads.get(&chunk_id).copied().unwrap_or(0);
+        self.max_downloads.is_some_and(|max| served >= max)
+    }
+
+    /// Whether no new download can be started through the link.
+    pub fn is_exhausted(&self) -> bool {
+        self.is_chunk_exhausted(0)
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PasswordHash {
+    pub salt: Vec<u8>,
+    /// PBKDF2-HMAC-SHA256 of the password, with `iterations` rounds.
+    pub hash: Vec<u8>,
+    pub iterations: u32,
+}
+
+/// What the owner sees about a share link in `list_files`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShareLinkInfo {
+    pub token: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u64>,
+    pub download_count: u64,
+    pub has_password: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct File {
     pub metadata: FileMetadata,
     pub content: FileContent,
 }
 
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FileContent {
     Pending {
         alias: String,
@@ -90,6 +189,11 @@ pub enum FileContent {
         file_type: String,
         vetkey_metadata: crate::vetkeys::EncryptedFileData,
     },
+    /// Tombstone of a burn-after-reading file: the chunks are gone, only the record is kept.
+    Consumed {
+        consumed_at: u64,
+        consumed_by: Principal,
+    },
 }
 
 
@@ -110,9 +214,132 @@ pub enum FileDownloadResponse {
     PermissionError,
     #[serde(rename = "found_file")]
     FoundFile(FileData),
+    /// The file is stored on Walrus; clients fetch the blob with this ID from an aggregator.
+    #[serde(rename = "walrus_blob")]
+    WalrusBlob(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GroupRole {
+    /// Manages members and can do everything editors can.
+    #[serde(rename = "owner")]
+    Owner,
+    /// Can assign files to and remove files from the group.
+    #[serde(rename = "editor")]
+    Editor,
+    /// Can read the group's files.
+    #[serde(rename = "viewer")]
+    Viewer,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub group_id: u64,
+    pub name: String,
+    /// Public handle of the group, shown as `group_alias` in `list_files`.
+    pub alias: String,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub members: BTreeMap<Principal, GroupRole>,
+    /// Version of the group's vetKey. It changes whenever a member leaves, so that files
+    /// encrypted afterwards cannot be decrypted by former members.
+    pub key_version: u64,
+}
+
+/// A version of a group's vetKey. See `groups::group_derivation_id`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupKey {
+    pub group_id: u64,
+    pub version: u64,
+}
+
+/// An invitation to join a group, waiting for the invited user to accept it.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GroupInvite {
+    pub role: GroupRole,
+    pub invited_by: Principal,
+    pub invited_at: u64,
 }
 
-#[derive(Debug, CandidType, Serialize, Deserialize)]
+/// A folder in a user's file tree.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Folder {
+    pub folder_id: u64,
+    pub owner: Principal,
+    pub name: String,
+    /// `None` for folders in the owner's root folder.
+    pub parent_id: Option<u64>,
+    pub created_at: u64,
+}
+
+/// An earlier version of a file, kept when a new version is uploaded.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileVersion {
+    pub version: u64,
+    /// The ID the version's chunks are stored under in `file_contents`.
+    pub storage_id: u64,
+    pub num_chunks: u64,
+    pub file_type: String,
+    /// Total size of the version's chunks, in bytes.
+    pub size: u64,
+    pub created_at: u64,
+    pub vetkey_metadata: crate::vetkeys::EncryptedFileData,
+}
+
+/// A multi-chunk version of a file that is still being uploaded.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingVersion {
+    /// The ID the new chunks are stored under in `file_contents`.
+    pub storage_id: u64,
+    pub num_chunks: u64,
+    pub file_type: String,
+    pub vetkey_metadata: crate::vetkeys::EncryptedFileData,
+}
+
+/// The version history of a file that has been updated at least once.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct FileHistory {
+    /// Version number of the file's current content. Files without a history are at version 1.
+    pub current_version: u64,
+    /// Earlier versions, oldest first.
+    pub previous: Vec<FileVersion>,
+}
+
+/// A file in its owner's trash, waiting to be restored or purged.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TrashedFile {
+    pub owner: Principal,
+    pub trashed_at: u64,
+}
+
+/// Storage taken by a user's files.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageUsage {
+    /// Bytes stored for the user's files, trashed files and previous versions.
+    /// Chunks shared between copies are counted once.
+    pub bytes: u64,
+    /// Files owned by the user, including those in the trash.
+    pub file_count: u64,
+}
+
+/// A user's data export, assembled by `export_account` and downloaded in chunks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Takeout {
+    pub created_at: u64,
+    /// The CBOR-encoded `TakeoutManifest`.
+    pub data: Vec<u8>,
+}
+
+/// An owner's request for someone else to upload a file into their vault.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileRequest {
+    pub alias: String,
+    pub allow_anonymous: bool,
+    /// Set once somebody starts uploading against the alias; only they may continue.
+    pub uploader: Option<Principal>,
+}
+
+#[derive(Debug, CandidType, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum UploadFileError {
     #[serde(rename = "not_requested")]
     NotRequested,
@@ -120,6 +347,14 @@ pub enum UploadFileError {
     AlreadyUploaded,
     #[serde(rename = "not_authenticated")]
     NotAuthenticated,
+    #[serde(rename = "invalid_input")]
+    InvalidInput,
+    /// Storing the upload would take the owner over their storage quota.
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded,
+    /// The caller's account, or the account the file is uploaded for, is suspended.
+    #[serde(rename = "account_suspended")]
+    AccountSuspended,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, PartialEq)]
@@ -133,6 +368,12 @@ pub enum FileSharingResponse {
 }
 
 
+/// Default time deleted files are kept in the trash: 30 days.
+pub const DEFAULT_TRASH_RETENTION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Default storage quota of users without a plan: 1 GiB.
+pub const DEFAULT_STORAGE_QUOTA: u64 = 1 << 30;
+
 #[derive(Serialize, Deserialize)]
 pub struct State {
     // Keeps track of how many files have been requested so far
@@ -153,6 +394,124 @@ pub struct State {
     pub user_profiles: BTreeMap<Principal, UserProfile>,
     pub username_to_principal: BTreeMap<String, Principal>, // For username uniqueness
     pub user_count: u64,
+    /// Principals allowed to use the administration endpoints. Controllers are added when
+    /// the canister is installed or upgraded, and whenever they call an admin endpoint.
+    pub admins: BTreeSet<Principal>,
+    /// Why each suspended account was suspended.
+    pub suspensions: BTreeMap<Principal, Suspension>,
+    /// Recent sign-ins of each principal, most recent first.
+    pub login_history: BTreeMap<Principal, VecDeque<u64>>,
+    /// Principals that act as another principal's account, mapped to that account.
+    pub linked_principals: BTreeMap<Principal, Principal>,
+    /// Unconfirmed requests to link a principal to an account, keyed by (account, principal),
+    /// with the time they were made.
+    pub link_requests: BTreeMap<(Principal, Principal), u64>,
+    /// Outstanding Sign-In with Ethereum challenges, one per account.
+    pub eth_challenges: BTreeMap<Principal, WalletChallenge>,
+    /// Linked Ethereum addresses, lowercase, mapped to the account they are linked to.
+    pub eth_addresses: BTreeMap<String, Principal>,
+    /// Outstanding Sui address challenges, one per account.
+    pub sui_challenges: BTreeMap<Principal, WalletChallenge>,
+    /// Linked Sui addresses mapped to the account they are linked to.
+    pub sui_addresses: BTreeMap<String, Principal>,
+    /// The Sui address linked to the account that registered each Walrus file, if it had one.
+    pub walrus_registrants: BTreeMap<u64, String>,
+    /// The latest data export of each user.
+    pub takeouts: BTreeMap<Principal, Takeout>,
+    /// Accounts marked for deletion, deleted once their grace period is over.
+    pub pending_deletions: BTreeMap<Principal, PendingDeletion>,
+    /// What was removed when each deleted account was deleted.
+    pub deletion_receipts: BTreeMap<Principal, DeletionReceipt>,
+
+    /// Files created by `copy_file` share the chunks of the file they were copied from.
+    /// Mapping between such a file's ID and the ID its chunks are stored under in `file_contents`.
+    pub file_storage: BTreeMap<u64, u64>,
+    /// Number of files referencing each shared chunk storage ID.
+    pub storage_refs: BTreeMap<u64, u64>,
+
+    /// Encrypted names and types of files uploaded with encrypted metadata.
+    pub encrypted_file_metadata: BTreeMap<u64, EncryptedFileMetadata>,
+
+    /// Tags attached to files by their owners.
+    pub file_tags: BTreeMap<u64, BTreeSet<String>>,
+    /// Custom key/value attributes attached to files by their owners.
+    pub file_attributes: BTreeMap<u64, BTreeMap<String, String>>,
+    /// Mapping between an owner and tag, and the owner's files carrying that tag.
+    pub tag_index: BTreeMap<(Principal, String), BTreeSet<u64>>,
+
+    /// Version histories of files that have been updated with `upload_new_version`.
+    pub file_versions: BTreeMap<u64, FileHistory>,
+    /// New versions whose chunks are still being uploaded. The file keeps serving its
+    /// current content until the last chunk arrives.
+    pub pending_versions: BTreeMap<u64, PendingVersion>,
+
+    /// Public share links, keyed by their random token.
+    pub share_links: BTreeMap<String, ShareLink>,
+    /// Tokens of the share links minted for each file.
+    pub file_share_links: BTreeMap<u64, Vec<String>>,
+
+    // Keeps track of how many groups have been created so far
+    // and is used to assign IDs to new groups.
+    group_count: u64,
+    pub groups: BTreeMap<u64, Group>,
+    /// Mapping between a user's principal and the groups they are a member of.
+    pub user_groups: BTreeMap<Principal, Vec<u64>>,
+    /// Mapping between a group ID and the files assigned to it.
+    pub group_files: BTreeMap<u64, Vec<u64>>,
+    /// Mapping between a file ID and the group it is assigned to, if any.
+    pub file_groups: BTreeMap<u64, u64>,
+    /// The group key the content of a file is encrypted under. Files without an entry are
+    /// encrypted under their owner's key.
+    pub file_group_keys: BTreeMap<u64, GroupKey>,
+    /// Pending invitations to join a group, by invited user and group ID.
+    pub group_invites: BTreeMap<Principal, BTreeMap<u64, GroupInvite>>,
+
+    // Keeps track of how many folders have been created so far
+    // and is used to assign IDs to new folders.
+    folder_count: u64,
+    pub folders: BTreeMap<u64, Folder>,
+    /// Mapping between a user's principal and the folders they own.
+    pub user_folders: BTreeMap<Principal, Vec<u64>>,
+    /// Mapping between a file ID and its folder. Files without an entry are in the owner's root.
+    pub file_folders: BTreeMap<u64, u64>,
+
+    /// Deleted files that can still be restored. They are no longer listed in `file_owners`.
+    pub trashed_files: BTreeMap<u64, TrashedFile>,
+    /// Mapping between a user's principal and the files in their trash.
+    pub user_trash: BTreeMap<Principal, Vec<u64>>,
+    /// How long (in nanoseconds) files stay in the trash before they are purged.
+    pub trash_retention: u64,
+
+    /// Storage used by each user, kept up to date as chunks are written and deleted.
+    pub storage_usage: BTreeMap<Principal, StorageUsage>,
+    /// Owner and total size of the chunks stored under each storage ID.
+    pub stored_bytes: BTreeMap<u64, (Principal, u64)>,
+    /// Bytes stored across all users.
+    pub total_storage_used: u64,
+
+    /// Storage quota (in bytes) of users without a plan.
+    pub default_quota: u64,
+    /// Storage quota (in bytes) of each plan.
+    pub plan_quotas: BTreeMap<String, u64>,
+    /// Mapping between a user's principal and their plan.
+    pub user_plans: BTreeMap<Principal, String>,
+    /// Bytes declared for uploads that have not been written yet, keyed by file ID.
+    /// They count towards the owner's quota until the upload completes.
+    pub upload_reservations: BTreeMap<u64, (Principal, u64)>,
+
+    /// Mapping between the alias of a pending file request and the requested file's ID.
+    pub file_alias_index: BTreeMap<String, u64>,
+    /// Open file requests, keyed by the requested file's ID.
+    pub file_requests: BTreeMap<u64, FileRequest>,
+
+    /// Burn-after-reading files that have not been consumed yet, with the chunks
+    /// each recipient has downloaded so far.
+    pub burn_after_reading: BTreeMap<u64, BTreeMap<Principal, BTreeSet<u64>>>,
+
+    /// Cryptographically secure RNG, (re)seeded from the management canister's `raw_rand`.
+    /// `None` until the first seed has been received.
+    #[serde(skip)]
+    rng: Option<ChaCha20Rng>,
 }
 
 impl State {
@@ -163,7 +522,150 @@ impl State {
         file_id
     }
 
-    fn new(_rand_seed: &[u8]) -> Self {
+    pub fn generate_group_id(&mut self) -> u64 {
+        let group_id = self.group_count;
+        self.group_count += 1;
+        group_id
+    }
+
+    pub fn generate_folder_id(&mut self) -> u64 {
+        let folder_id = self.folder_count;
+        self.folder_count += 1;
+        folder_id
+    }
+
+    pub fn is_file_owner(&self, principal: Principal, file_id: u64) -> bool {
+        self.file_owners
+            .get(&principal)
+            .is_some_and(|files| files.contains(&file_id))
+    }
+
+    /// The ID under which the chunks of a file are stored in `file_contents`.
+    pub fn storage_id(&self, file_id: u64) -> u64 {
+        self.file_storage.get(&file_id).copied().unwrap_or(file_id)
+    }
+
+    /// Makes `copy_id` share the chunks of `file_id`.
+    pub fn share_chunks(&mut self, file_id: u64, copy_id: u64) {
+        let storage_id = self.storage_id(file_id);
+        self.retain_storage(storage_id);
+        self.file_storage.insert(copy_id, storage_id);
+    }
+
+    /// Adds a reference to the chunks stored under `storage_id`.
+    pub fn retain_storage(&mut self, storage_id: u64) {
+        // The first extra reference turns private chunks into shared ones.
+        *self.storage_refs.entry(storage_id).or_insert(1) += 1;
+    }
+
+    /// Drops a file's reference to its chunks, deleting them once no other file uses them.
+    pub fn release_chunks(&mut self, file_id: u64, num_chunks: u64) {
+        let storage_id = self.file_storage.remove(&file_id).unwrap_or(file_id);
+        self.release_storage(storage_id, num_chunks);
+    }
+
+    /// Drops a reference to the chunks stored under `storage_id`, deleting them once unused.
+    pub fn release_storage(&mut self, storage_id: u64, num_chunks: u64) {
+        match self.storage_refs.get_mut(&storage_id) {
+            Some(refs) if *refs > 1 => {
+                *refs -= 1;
+                return;
+            }
+            Some(_) => {
+                self.storage_refs.remove(&storage_id);
+            }
+            None => {}
+        }
+        for chunk_id in 0..num_chunks {
+            self.file_contents.remove(&(storage_id, chunk_id));
+        }
+        if let Some((owner, bytes)) = self.stored_bytes.remove(&storage_id) {
+            self.remove_usage(owner, bytes, 0);
+        }
+    }
+
+    /// Stores a chunk and accounts for it in `owner`'s storage usage.
+    pub fn write_chunk(&mut self, owner: Principal, storage_id: u64, chunk_id: u64, contents: Vec<u8>) {
+        let added = contents.len() as u64;
+        let replaced = self
+            .file_contents
+            .insert((storage_id, chunk_id), contents)
+            .map_or(0, |old| old.len() as u64);
+        let stored = &mut self.stored_bytes.entry(storage_id).or_insert((owner, 0)).1;
+        *stored = *stored + added - replaced;
+        self.remove_usage(owner, replaced, 0);
+        self.add_usage(owner, added, 0);
+    }
+
+    /// Records `owner` as the owner of a newly created file.
+    pub fn add_owned_file(&mut self, owner: Principal, file_id: u64) {
+        self.file_owners.entry(owner).or_default().push(file_id);
+        self.add_usage(owner, 0, 1);
+    }
+
+    pub fn add_usage(&mut self, owner: Principal, bytes: u64, files: u64) {
+        let usage = self.storage_usage.entry(owner).or_default();
+        usage.bytes += bytes;
+        usage.file_count += files;
+        self.total_storage_used += bytes;
+        self.sync_profile_usage(owner);
+    }
+
+    pub fn remove_usage(&mut self, owner: Principal, bytes: u64, files: u64) {
+        let usage = self.storage_usage.entry(owner).or_default();
+        usage.bytes = usage.bytes.saturating_sub(bytes);
+        usage.file_count = usage.file_count.saturating_sub(files);
+        self.total_storage_used = self.total_storage_used.saturating_sub(bytes);
+        self.sync_profile_usage(owner);
+    }
+
+    /// Copies a user's storage usage into their profile, if they have one.
+    pub fn sync_profile_usage(&mut self, owner: Principal) {
+        let usage = self.storage_usage.get(&owner).copied().unwrap_or_default();
+        if let Some(profile) = self.user_profiles.get_mut(&owner) {
+            profile.storage_used = usage.bytes;
+            profile.file_count = usage.file_count;
+        }
+    }
+
+    pub fn is_admin(&self, principal: Principal) -> bool {
+        self.admins.contains(&principal)
+    }
+
+    /// The account a principal acts as: the principal it is linked to, or itself.
+    pub fn account_of(&self, principal: Principal) -> Principal {
+        self.linked_principals.get(&principal).copied().unwrap_or(principal)
+    }
+
+    /// Suspended users cannot upload, download or share files.
+    pub fn is_suspended(&self, principal: Principal) -> bool {
+        self.user_profiles
+            .get(&principal)
+            .is_some_and(|profile| !profile.is_active)
+    }
+
+    /// The role of `principal` in a group, if they are a member.
+    pub fn group_role(&self, group_id: u64, principal: Principal) -> Option<GroupRole> {
+        self.groups.get(&group_id)?.members.get(&principal).copied()
+    }
+
+    /// Owners can read their files; members of a group can read the files assigned to it.
+    /// Trashed files cannot be read by anyone until they are restored.
+    pub fn can_read_file(&self, principal: Principal, file_id: u64) -> bool {
+        !self.is_trashed(file_id)
+            && (self.is_file_owner(principal, file_id)
+                || self
+                    .file_groups
+                    .get(&file_id)
+                    .is_some_and(|&group_id| self.group_role(group_id, principal).is_some()))
+    }
+
+    pub fn is_trashed(&self, file_id: u64) -> bool {
+        self.trashed_files.contains_key(&file_id)
+    }
+
+    /// Creates an empty state. The RNG is seeded only if `rand_seed` is exactly 32 bytes long.
+    fn new(rand_seed: &[u8]) -> Self {
         Self {
             file_count: 0,
             file_data: BTreeMap::new(),
@@ -172,9 +674,85 @@ impl State {
             user_profiles: BTreeMap::new(),
             username_to_principal: BTreeMap::new(),
             user_count: 0,
+            admins: BTreeSet::new(),
+            suspensions: BTreeMap::new(),
+            login_history: BTreeMap::new(),
+            linked_principals: BTreeMap::new(),
+            link_requests: BTreeMap::new(),
+            eth_challenges: BTreeMap::new(),
+            eth_addresses: BTreeMap::new(),
+            sui_challenges: BTreeMap::new(),
+            sui_addresses: BTreeMap::new(),
+            walrus_registrants: BTreeMap::new(),
+            takeouts: BTreeMap::new(),
+            pending_deletions: BTreeMap::new(),
+            deletion_receipts: BTreeMap::new(),
+            file_storage: BTreeMap::new(),
+            storage_refs: BTreeMap::new(),
+            encrypted_file_metadata: BTreeMap::new(),
+            file_tags: BTreeMap::new(),
+            file_attributes: BTreeMap::new(),
+            tag_index: BTreeMap::new(),
+            file_versions: BTreeMap::new(),
+            pending_versions: BTreeMap::new(),
+            share_links: BTreeMap::new(),
+            file_share_links: BTreeMap::new(),
+            group_count: 0,
+            groups: BTreeMap::new(),
+            user_groups: BTreeMap::new(),
+            group_files: BTreeMap::new(),
+            file_groups: BTreeMap::new(),
+            file_group_keys: BTreeMap::new(),
+            group_invites: BTreeMap::new(),
+            folder_count: 0,
+            folders: BTreeMap::new(),
+            user_folders: BTreeMap::new(),
+            file_folders: BTreeMap::new(),
+            trashed_files: BTreeMap::new(),
+            user_trash: BTreeMap::new(),
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            storage_usage: BTreeMap::new(),
+            stored_bytes: BTreeMap::new(),
+            total_storage_used: 0,
+            default_quota: DEFAULT_STORAGE_QUOTA,
+            plan_quotas: BTreeMap::new(),
+            user_plans: BTreeMap::new(),
+            upload_reservations: BTreeMap::new(),
+            file_alias_index: BTreeMap::new(),
+            file_requests: BTreeMap::new(),
+            burn_after_reading: BTreeMap::new(),
+            rng: <[u8; 32]>::try_from(rand_seed).ok().map(ChaCha20Rng::from_seed),
         }
     }
 
+    /// Replaces the RNG with one seeded from fresh entropy.
+    pub fn reseed(&mut self, seed: [u8; 32]) {
+        self.rng = Some(ChaCha20Rng::from_seed(seed));
+    }
+
+    /// Returns `N` cryptographically secure random bytes.
+    ///
+    /// Panics (i.e. traps the call) if the RNG has not been seeded from `raw_rand` yet.
+    pub fn random_bytes<const N: usize>(&mut self) -> [u8; N] {
+        let rng = self
+            .rng
+            .as_mut()
+            .expect("randomness has not been seeded yet, try again later");
+        let mut bytes = [0u8; N];
+        rng.fill_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Random 256-bit token, hex encoded. Used for share tokens and aliases.
+    pub fn generate_token(&mut self) -> String {
+        hex::encode(self.random_bytes::<32>())
+    }
+
+    /// Random 128-bit nonce, hex encoded. Used for signed-message challenges.
+    pub fn generate_nonce(&mut self) -> String {
+        hex::encode(self.random_bytes::<16>())
+    }
+
     // pub(crate) fn num_chunks_uploaded(&self, file_id: u64) -> u64 {
     //     self.file_contents
     //         .range((Included((file_id, 0u64)), Excluded(((file_id + 1), 0u64))))
@@ -183,9 +761,18 @@ impl State {
 }
 
 // This is a standard Rust pattern for initializing the state.
+/// The default state is unseeded, like the canister's state before `raw_rand` answers.
 impl Default for State {
     fn default() -> Self {
-        State::new(vec![0; 32].as_slice())
+        State::new(&[])
+    }
+}
+
+#[cfg(test)]
+impl State {
+    /// An empty state with a fixed RNG seed, for tests that generate tokens or IDs.
+    pub(crate) fn seeded() -> Self {
+        State::new(&[0; 32])
     }
 }
 
@@ -227,21 +814,8 @@ pub fn get_time() -> u64 {
     12345
 }
 
-fn get_randomness_seed() -> Vec<u8> {
-    // this is an array of u8 of length 8.
-    let time_seed = ic_cdk::api::time().to_be_bytes();
-    // we need to extend this to an array of size 32 by adding to it an array of size 24 full of 0s.
-    let zeroes_arr: [u8; 24] = [0; 24];
-    [&time_seed[..], &zeroes_arr[..]].concat()
-}
-
-
 pub fn ceil_division(dividend: usize, divisor: usize) -> usize {
-    if dividend % divisor == 0 {
-        dividend / divisor
-    } else {
-        dividend / divisor + 1
-    }
+    dividend.div_ceil(divisor)
 }
 
 fn init_file_contents() -> StableBTreeMap<(FileId, ChunkId), Vec<u8>, Memory> {
@@ -264,7 +838,71 @@ pub struct UserProfile {
     pub last_login: u64,
     pub storage_used: u64, // in bytes
     pub file_count: u64,
+    /// False while the account is suspended, see `State::suspensions`.
     pub is_active: bool,
+    /// One of the user's files, shown as their picture.
+    pub avatar_file_id: Option<u64>,
+    /// EIP-55 checksummed Ethereum address the user proved control of with `link_eth_address`.
+    pub eth_address: Option<String>,
+    /// Sui address the user proved control of with `link_sui_address`.
+    pub sui_address: Option<String>,
+}
+
+/// Why and when an admin suspended an account.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Suspension {
+    pub reason: String,
+    pub suspended_at: u64,
+    pub suspended_by: Principal,
+}
+
+/// The part of a profile anyone can look up.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PublicProfile {
+    pub principal: Principal,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_file_id: Option<u64>,
+}
+
+impl From<&UserProfile> for PublicProfile {
+    fn from(profile: &UserProfile) -> Self {
+        PublicProfile {
+            principal: profile.principal_id,
+            username: profile.username.clone(),
+            display_name: profile.display_name.clone(),
+            avatar_file_id: profile.avatar_file_id,
+        }
+    }
+}
+
+/// A message a user has to sign with their wallet to prove they control `address`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WalletChallenge {
+    pub address: String,
+    pub message: String,
+    pub expires_at: u64,
+}
+
+/// An account marked for deletion.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingDeletion {
+    pub requested_at: u64,
+    /// When the account and everything it owns will be deleted.
+    pub delete_at: u64,
+}
+
+/// Record of a deleted account, kept so the user can confirm what was removed.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeletionReceipt {
+    pub principal: Principal,
+    pub username: Option<String>,
+    pub requested_at: u64,
+    pub deleted_at: u64,
+    pub files_deleted: u64,
+    pub bytes_freed: u64,
+    pub share_links_revoked: u64,
+    pub groups_left: u64,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -279,6 +917,8 @@ pub struct UpdateUserRequest {
     pub username: Option<String>,
     pub display_name: Option<String>,
     pub email: Option<String>,
+    /// Must be one of the caller's files.
+    pub avatar_file_id: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -294,4 +934,6 @@ pub enum UserResponse {
 pub enum UserListResponse {
     Ok(Vec<UserProfile>),
     NotAuthenticated,
+    /// Only admins can list users.
+    NotAuthorized,
 }
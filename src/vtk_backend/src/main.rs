@@ -1,166 +1,341 @@
 // use ic_cdk_macros::{post_upgrade, pre_upgrade, query, update};
-use ic_cdk_macros::{query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
+use candid::Principal;
 use vtk_backend::*;
 use vtk_backend::api::UploadFileAtomicRequest;
 use vtk_backend::api::DeleteFileResult;
-use candid::Principal;
 use vtk_backend::api::{RegisterFileRequest, RegisterFileResponse};
+use vtk_backend::api::{CreateShareLinkRequest, ShareLinkError};
+use vtk_backend::api::{GroupError, GroupInfo, GroupInviteInfo};
+use vtk_backend::api::{TrashEntry, TrashError};
+use vtk_backend::api::{QuotaError, QuotaInfo, StorageUsageReport};
+use vtk_backend::api::{AccountDeletionError, AccountDeletionStatus};
+use vtk_backend::api::{ActivityError, LoginHistory};
+use vtk_backend::api::{LinkError, LinkedPrincipals};
+use vtk_backend::api::{SiweChallengeRequest, WalletLinkError};
+use vtk_backend::api::{TakeoutError, TakeoutInfo};
+use vtk_backend::api::{ImportAccountRequest, ImportError, ImportReport};
+use vtk_backend::api::{AdminError, CanisterStats};
+use vtk_backend::api::{SearchUsersRequest, SearchUsersResponse, UserDirectoryError};
+use vtk_backend::api::{EncryptedMetadataError, TagError, UpdateFileMetadataRequest};
+use vtk_backend::api::{ListFilesError, ListFilesRequest, ListFilesResponse};
+use vtk_backend::api::{FileVersionInfo, PruneVersionsRequest, UploadNewVersionRequest, VersionError};
+use vtk_backend::api::{CopyFileRequest, CreateFolderRequest, FolderError, FolderInfo, FolderListing, ListFolderRequest, PathEntry};
+use vtk_backend::api::{AliasInfo, RequestFileRequest, RequestFileResponse, UploadFileToAliasRequest};
 use vtk_backend::{CreateUserRequest, UpdateUserRequest, UserResponse, UserListResponse};
 // VetKey methods are already defined in the vetkd module
 
+#[init]
+fn init() {
+    // The controller installing the canister is its first admin.
+    add_controller_admin(ic_cdk::caller());
+    vtk_backend::randomness::schedule_reseeding();
+    vtk_backend::api::schedule_trash_purge();
+    vtk_backend::api::schedule_account_deletions();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    add_controller_admin(ic_cdk::caller());
+    vtk_backend::randomness::schedule_reseeding();
+    vtk_backend::api::schedule_trash_purge();
+    vtk_backend::api::schedule_account_deletions();
+}
+
+/// The account the calling principal acts as, see `request_principal_link`.
+fn account_caller() -> Principal {
+    let caller = ic_cdk::caller();
+    with_state(|s| s.account_of(caller))
+}
+
 #[update]
-fn upload_file_atomic(request: UploadFileAtomicRequest) -> Result<u64, String> {
-    let caller = ic_cdk::caller();
-    with_state_mut(|s| {
-        // For now, we'll use a simplified version without vetKey encryption
-        // TODO: Implement proper async vetKey integration
-        if caller == Principal::anonymous() {
-            return Err("Not authenticated".to_string());
-        }
-
-        let file_id = s.generate_file_id();
-        
-        // Create a simple encrypted data structure for now
-        let encrypted_data = crate::vetkeys::EncryptedFileData {
-            encrypted_content: request.content.clone(),
-            file_owners: vec![caller],
-            encryption_metadata: std::collections::HashMap::new(),
-        };
-        
-        let content = if request.num_chunks == 1 {
-            crate::FileContent::Uploaded {
-                num_chunks: request.num_chunks,
-                file_type: request.file_type.clone(),
-                vetkey_metadata: encrypted_data,
-            }
-        } else {
-            crate::FileContent::PartiallyUploaded {
-                num_chunks: request.num_chunks,
-                file_type: request.file_type.clone(),
-                vetkey_metadata: encrypted_data,
-            }
-        };
-        
-        s.file_contents.insert((file_id, 0), request.content.clone());
-        s.file_data.insert(
-            file_id,
-            crate::File {
-                metadata: crate::FileMetadata {
-                    file_name: request.name,
-                    requester_principal: caller,
-                    requested_at: crate::get_time(),
-                    uploaded_at: Some(crate::get_time()),
-                    storage_provider: "icp".to_string(),
-                    blob_id: None,
-                    is_encrypted: true, // Mark as VetKey encrypted
-                },
-                content,
-            },
-        );
-
-        s.file_owners
-            .entry(caller)
-            .or_insert_with(Vec::new)
-            .push(file_id);
-
-        Ok(file_id)
-    })
+fn upload_file_atomic(request: UploadFileAtomicRequest) -> Result<u64, UploadFileError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::upload_file_atomic(caller, request, s))
 }
 
 #[update]
 fn upload_file_continue(request: UploadFileContinueRequest) -> Result<(), UploadFileError> {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state_mut(|s| vtk_backend::api::upload_file_continue(caller, request, s))
 }
 
 #[update]
-fn register_file(request: RegisterFileRequest) -> RegisterFileResponse {
-    let caller = ic_cdk::caller();
-    vtk_backend::api::register_file(caller, request)
+fn register_file(request: RegisterFileRequest) -> Result<RegisterFileResponse, UploadFileError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::register_file(caller, request, s))
+}
+
+#[update]
+fn request_file(request: RequestFileRequest) -> Result<RequestFileResponse, UploadFileError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::request_file(caller, request, s))
+}
+
+#[query]
+fn get_alias_info(alias: String) -> Result<AliasInfo, UploadFileError> {
+    with_state(|s| vtk_backend::api::get_alias_info(alias, s))
+}
+
+#[update]
+fn upload_file_to_alias(request: UploadFileToAliasRequest) -> Result<u64, UploadFileError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::upload_file_to_alias(caller, request, s))
 }
 
 #[query]
 fn download_file(file_id: u64, chunk_id: u64) -> Result<FileDownloadResponse, String> {
-    let caller = ic_cdk::caller();
-    with_state(|s| {
-        // For now, we'll use a simplified version without vetKey decryption
-        // TODO: Implement proper async vetKey integration
-        if caller == Principal::anonymous() {
-            return Ok(FileDownloadResponse::PermissionError);
-        }
-
-        match s.file_owners.get(&caller) {
-            Some(files) => {
-                if !files.contains(&file_id) {
-                    return Ok(FileDownloadResponse::PermissionError);
-                }
-            }
-            None => return Ok(FileDownloadResponse::PermissionError),
-        }
-
-        match s.file_data.get(&file_id) {
-            None => Ok(FileDownloadResponse::NotFoundFile),
-            Some(file) => match &file.content {
-                crate::FileContent::Uploaded { file_type, num_chunks, vetkey_metadata: _ } => {
-                    match s.file_contents.get(&(file_id, chunk_id)) {
-                        Some(contents) => Ok(FileDownloadResponse::FoundFile(crate::FileData {
-                            contents: contents.clone(),
-                            file_type: file_type.clone(),
-                            num_chunks: *num_chunks,
-                        })),
-                        None => Ok(FileDownloadResponse::NotFoundFile),
-                    }
-                }
-                _ => Ok(FileDownloadResponse::NotUploadedFile),
-            },
-        }
-    })
+    let caller = account_caller();
+    with_state(|s| Ok(vtk_backend::api::download_file(s, caller, file_id, chunk_id)))
+}
+
+#[update]
+fn create_share_link(request: CreateShareLinkRequest) -> Result<String, ShareLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::create_share_link(caller, request, s))
+}
+
+#[update]
+fn revoke_link(token: String) -> Result<(), ShareLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::revoke_link(caller, token, s))
+}
+
+// An update rather than a query: download counts, wrong passwords and burn-after-reading
+// downloads have to be recorded, and state changes made by queries are discarded.
+#[update]
+fn download_by_link(token: String, chunk_id: u64, password: Option<String>) -> Result<FileDownloadResponse, ShareLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::download_by_link(caller, token, chunk_id, password, s))
 }
 
 #[update]
 fn delete_file(file_id: u64) -> DeleteFileResult {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state_mut(|s| vtk_backend::api::delete_file(s, caller, file_id))
 }
 
 
 #[query]
-fn list_files() -> Vec<PublicFileMetadata> {
-    let caller = ic_cdk::caller();
-    with_state(|s| {
-        // If caller is anonymous, return empty list
-        if caller == Principal::anonymous() {
-            panic!("Not authenticated");
-        }
+fn list_files(request: Option<ListFilesRequest>) -> Result<ListFilesResponse, ListFilesError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_files(caller, request.unwrap_or_default(), s))
+}
+
+
+#[update]
+fn update_file_metadata(request: UpdateFileMetadataRequest) -> Result<(), TagError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::update_file_metadata(caller, request, s))
+}
+
+#[update]
+fn set_encrypted_metadata(file_id: u64, metadata: EncryptedFileMetadata) -> Result<(), EncryptedMetadataError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::set_encrypted_metadata(caller, file_id, metadata, s))
+}
 
-        // Get files owned by the caller
-        let empty_vec = Vec::new();
-        let owned_files = s.file_owners.get(&caller).unwrap_or(&empty_vec);
-        
-        owned_files.iter().filter_map(|&file_id| {
-            s.file_data.get(&file_id).map(|file| {
-                let file_status = match &file.content {
-                    FileContent::Pending { alias } => FileStatus::Pending {
-                        alias: alias.clone(),
-                        requested_at: file.metadata.requested_at,
-                    },
-                    FileContent::PartiallyUploaded { .. } => FileStatus::PartiallyUploaded,
-                    FileContent::Uploaded { .. } => FileStatus::Uploaded {
-                        uploaded_at: file.metadata.uploaded_at.unwrap_or(file.metadata.requested_at),
-                    },
-                };
+// Versioning endpoints
+#[update]
+fn upload_new_version(request: UploadNewVersionRequest) -> Result<u64, VersionError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::upload_new_version(caller, request, s))
+}
+
+#[query]
+fn list_versions(file_id: u64) -> Result<Vec<FileVersionInfo>, VersionError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_versions(caller, file_id, s))
+}
 
-                PublicFileMetadata {
-                    file_id,
-                    file_name: file.metadata.file_name.clone(),
-                    group_name: "".to_string(),          // Fill this if you use groups
-                    group_alias: None,                   // Or Some(...) if available
-                    file_status,
-                    shared_with: vec![],                 // Empty vector for now since we don't support sharing yet
-                }
-            })
-        }).collect()
-    })
+#[query]
+fn download_version(file_id: u64, version: u64, chunk_id: u64) -> FileDownloadResponse {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::download_version(caller, file_id, version, chunk_id, s))
+}
+
+#[update]
+fn restore_version(file_id: u64, version: u64) -> Result<u64, VersionError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::restore_version(caller, file_id, version, s))
+}
+
+#[update]
+fn prune_versions(request: PruneVersionsRequest) -> Result<u64, VersionError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::prune_versions(caller, request, s))
+}
+
+// Trash endpoints
+#[query]
+fn list_trash() -> Vec<TrashEntry> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_trash(caller, s))
+}
+
+#[update]
+fn restore_file(file_id: u64) -> Result<(), TrashError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::restore_file(caller, file_id, s))
+}
+
+#[update]
+fn empty_trash() -> Result<u64, TrashError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::empty_trash(caller, s))
+}
+
+#[update]
+fn set_trash_retention(retention_ns: u64) -> Result<(), TrashError> {
+    if !caller_is_admin(ic_cdk::caller()) {
+        return Err(TrashError::PermissionError);
+    }
+    with_state_mut(|s| vtk_backend::api::set_trash_retention(retention_ns, s));
+    Ok(())
+}
+
+#[query]
+fn get_quota() -> Result<QuotaInfo, QuotaError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::get_quota(caller, s))
+}
+
+#[update]
+fn set_default_quota(quota: u64) -> Result<(), QuotaError> {
+    if !caller_is_admin(ic_cdk::caller()) {
+        return Err(QuotaError::PermissionError);
+    }
+    with_state_mut(|s| vtk_backend::api::set_default_quota(quota, s));
+    Ok(())
+}
+
+#[update]
+fn set_plan_quota(plan: String, quota: Option<u64>) -> Result<(), QuotaError> {
+    if !caller_is_admin(ic_cdk::caller()) {
+        return Err(QuotaError::PermissionError);
+    }
+    with_state_mut(|s| vtk_backend::api::set_plan_quota(plan, quota, s));
+    Ok(())
+}
+
+#[update]
+fn set_user_plan(user: Principal, plan: Option<String>) -> Result<(), QuotaError> {
+    if !caller_is_admin(ic_cdk::caller()) {
+        return Err(QuotaError::PermissionError);
+    }
+    with_state_mut(|s| vtk_backend::api::set_user_plan(user, plan, s))
+}
+
+#[update]
+fn check_storage_usage(repair: bool) -> Result<StorageUsageReport, QuotaError> {
+    if !caller_is_admin(ic_cdk::caller()) {
+        return Err(QuotaError::PermissionError);
+    }
+    Ok(with_state_mut(|s| vtk_backend::api::check_storage_usage(repair, s)))
+}
+
+// Folder endpoints
+#[update]
+fn create_folder(request: CreateFolderRequest) -> Result<FolderInfo, FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::create_folder(caller, request, s))
+}
+
+#[update]
+fn rename_folder(folder_id: u64, name: String) -> Result<FolderInfo, FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::rename_folder(caller, folder_id, name, s))
+}
+
+#[update]
+fn delete_folder(folder_id: u64) -> Result<(), FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::delete_folder(caller, folder_id, s))
+}
+
+#[update]
+fn move_file(file_id: u64, folder_id: Option<u64>) -> Result<(), FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::move_file(caller, file_id, folder_id, s))
+}
+
+#[update]
+fn rename_file(file_id: u64, name: String) -> Result<(), FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::rename_file(caller, file_id, name, s))
+}
+
+#[update]
+fn copy_file(request: CopyFileRequest) -> Result<u64, FolderError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::copy_file(caller, request, s))
+}
+
+#[query]
+fn list_folder(request: ListFolderRequest) -> Result<FolderListing, FolderError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_folder(caller, request, s))
+}
+
+#[query]
+fn resolve_path(path: String) -> Result<PathEntry, FolderError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::resolve_path(caller, path, s))
+}
+
+// Group endpoints
+#[update]
+fn create_group(name: String) -> Result<GroupInfo, GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::create_group(caller, name, s))
+}
+
+#[update]
+fn invite_group_member(group_id: u64, username: String, role: GroupRole) -> Result<GroupInfo, GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::invite_group_member(caller, group_id, username, role, s))
+}
+
+#[update]
+fn set_group_member_role(group_id: u64, username: String, role: GroupRole) -> Result<GroupInfo, GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::set_group_member_role(caller, group_id, username, role, s))
+}
+
+#[update]
+fn accept_group_invite(group_id: u64) -> Result<GroupInfo, GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::accept_group_invite(caller, group_id, s))
+}
+
+#[update]
+fn decline_group_invite(group_id: u64) -> Result<(), GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::decline_group_invite(caller, group_id, s))
+}
+
+#[update]
+fn remove_group_member(group_id: u64, username: String) -> Result<(), GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::remove_group_member(caller, group_id, username, s))
+}
+
+#[update]
+fn assign_file_to_group(file_id: u64, group_id: Option<u64>) -> Result<(), GroupError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::assign_file_to_group(caller, file_id, group_id, s))
+}
+
+#[query]
+fn list_groups() -> Vec<GroupInfo> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_groups(caller, s))
+}
+
+#[query]
+fn list_group_invites() -> Vec<GroupInviteInfo> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::list_group_invites(caller, s))
 }
 
 
@@ -172,40 +347,251 @@ fn greet(name: String) -> String {
 // User management endpoints
 #[update]
 fn create_user_profile(request: CreateUserRequest) -> UserResponse {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state_mut(|s| vtk_backend::api::create_user_profile(caller, request, s))
 }
 
 #[query]
 fn get_user_profile() -> UserResponse {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state(|s| vtk_backend::api::get_user_profile(caller, s))
 }
 
 #[update]
 fn update_user_profile(request: UpdateUserRequest) -> UserResponse {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state_mut(|s| vtk_backend::api::update_user_profile(caller, request, s))
 }
 
 #[update]
 fn delete_user_profile() -> UserResponse {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state_mut(|s| vtk_backend::api::delete_user_profile(caller, s))
 }
 
+#[update]
+fn export_account() -> Result<TakeoutInfo, TakeoutError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::export_account(caller, s))
+}
+
+#[query]
+fn get_takeout_info() -> Result<TakeoutInfo, TakeoutError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::get_takeout_info(caller, s))
+}
+
+#[query]
+fn download_takeout(chunk_id: u64) -> Result<Vec<u8>, TakeoutError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::download_takeout(caller, chunk_id, s))
+}
+
+#[update]
+fn import_account(request: ImportAccountRequest) -> Result<ImportReport, ImportError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::import_account(caller, request, s))
+}
+
+#[update]
+fn request_account_deletion() -> Result<PendingDeletion, AccountDeletionError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::request_account_deletion(caller, s))
+}
+
+#[update]
+fn cancel_account_deletion() -> Result<(), AccountDeletionError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::cancel_account_deletion(caller, s))
+}
+
+#[query]
+fn get_account_deletion_status() -> Result<AccountDeletionStatus, AccountDeletionError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::get_account_deletion_status(caller, s))
+}
+
+/// Controllers are always admins.
+fn add_controller_admin(caller: Principal) {
+    if ic_cdk::api::is_controller(&caller) {
+        with_state_mut(|s| s.admins.insert(caller));
+    }
+}
+
+/// Whether the caller of an update call is an admin, recording controllers as admins first.
+fn caller_is_admin(caller: Principal) -> bool {
+    add_controller_admin(caller);
+    with_state(|s| s.is_admin(caller))
+}
+
+#[update]
+fn add_admin(admin: Principal) -> Result<(), AdminError> {
+    let caller = ic_cdk::caller();
+    add_controller_admin(caller);
+    with_state_mut(|s| vtk_backend::api::add_admin(caller, admin, s))
+}
+
+#[update]
+fn remove_admin(admin: Principal) -> Result<(), AdminError> {
+    let caller = ic_cdk::caller();
+    add_controller_admin(caller);
+    with_state_mut(|s| vtk_backend::api::remove_admin(caller, admin, s))
+}
+
+#[query]
+fn list_admins() -> Result<Vec<Principal>, AdminError> {
+    let caller = ic_cdk::caller();
+    let is_controller = ic_cdk::api::is_controller(&caller);
+    with_state(|s| vtk_backend::api::list_admins(caller, is_controller, s))
+}
+
+#[update]
+fn suspend_user(user: Principal, reason: String) -> Result<(), AdminError> {
+    let caller = ic_cdk::caller();
+    add_controller_admin(caller);
+    with_state_mut(|s| vtk_backend::api::suspend_user(caller, user, reason, s))
+}
+
+#[update]
+fn reactivate_user(user: Principal) -> Result<(), AdminError> {
+    let caller = ic_cdk::caller();
+    add_controller_admin(caller);
+    with_state_mut(|s| vtk_backend::api::reactivate_user(caller, user, s))
+}
+
+#[query]
+fn list_suspensions() -> Result<Vec<(Principal, Suspension)>, AdminError> {
+    let caller = ic_cdk::caller();
+    let is_controller = ic_cdk::api::is_controller(&caller);
+    with_state(|s| vtk_backend::api::list_suspensions(caller, is_controller, s))
+}
+
+#[query]
+fn get_canister_stats() -> Result<CanisterStats, AdminError> {
+    let caller = ic_cdk::caller();
+    let is_controller = ic_cdk::api::is_controller(&caller);
+    with_state(|s| vtk_backend::api::get_canister_stats(caller, is_controller, s))
+}
+
+#[update]
+fn admin_remove_file(file_id: u64) -> Result<(), AdminError> {
+    let caller = ic_cdk::caller();
+    add_controller_admin(caller);
+    with_state_mut(|s| vtk_backend::api::admin_remove_file(caller, file_id, s))
+}
+
 #[query]
 fn list_users() -> UserListResponse {
     let caller = ic_cdk::caller();
-    with_state(|s| vtk_backend::api::list_users(caller, s))
+    let is_controller = ic_cdk::api::is_controller(&caller);
+    with_state(|s| vtk_backend::api::list_users(caller, is_controller, s))
+}
+
+#[query]
+fn search_users(request: SearchUsersRequest) -> Result<SearchUsersResponse, UserDirectoryError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::search_users(caller, request, s))
+}
+
+#[query]
+fn resolve_username(username: String) -> Result<PublicProfile, UserDirectoryError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::resolve_username(caller, username, s))
 }
 
 #[query]
 fn get_user_stats() -> UserResponse {
-    let caller = ic_cdk::caller();
+    let caller = account_caller();
     with_state(|s| vtk_backend::api::get_user_stats(caller, s))
 }
 
+#[update]
+fn record_login() -> Result<u64, ActivityError> {
+    let caller = ic_cdk::caller();
+    with_state_mut(|s| vtk_backend::api::record_login(caller, s))
+}
+
+#[query]
+fn get_login_history() -> Result<LoginHistory, ActivityError> {
+    let caller = ic_cdk::caller();
+    with_state(|s| vtk_backend::api::get_login_history(caller, s))
+}
+
+// Linked principals
+#[update]
+fn request_principal_link(principal: Principal) -> Result<(), LinkError> {
+    let caller = ic_cdk::caller();
+    with_state_mut(|s| vtk_backend::api::request_principal_link(caller, principal, s))
+}
+
+#[update]
+fn confirm_principal_link(account: Principal) -> Result<(), LinkError> {
+    let caller = ic_cdk::caller();
+    with_state_mut(|s| vtk_backend::api::confirm_principal_link(caller, account, s))
+}
+
+#[update]
+fn unlink_principal(principal: Principal) -> Result<(), LinkError> {
+    let caller = ic_cdk::caller();
+    with_state_mut(|s| vtk_backend::api::unlink_principal(caller, principal, s))
+}
+
+#[query]
+fn list_linked_principals() -> Result<LinkedPrincipals, LinkError> {
+    let caller = ic_cdk::caller();
+    with_state(|s| vtk_backend::api::list_linked_principals(caller, s))
+}
+
+// Sign-In with Ethereum
+#[update]
+fn prepare_eth_link(request: SiweChallengeRequest) -> Result<WalletChallenge, WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::prepare_eth_link(caller, request, s))
+}
+
+#[update]
+fn link_eth_address(signature: String) -> Result<String, WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::link_eth_address(caller, signature, s))
+}
+
+#[update]
+fn unlink_eth_address() -> Result<(), WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::unlink_eth_address(caller, s))
+}
+
+#[query]
+fn resolve_eth_address(address: String) -> Result<PublicProfile, WalletLinkError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::resolve_eth_address(caller, address, s))
+}
+
+// Sui address linking
+#[update]
+fn prepare_sui_link(address: String) -> Result<WalletChallenge, WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::prepare_sui_link(caller, address, s))
+}
+
+#[update]
+fn link_sui_address(signature: String) -> Result<String, WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::link_sui_address(caller, signature, s))
+}
+
+#[update]
+fn unlink_sui_address() -> Result<(), WalletLinkError> {
+    let caller = account_caller();
+    with_state_mut(|s| vtk_backend::api::unlink_sui_address(caller, s))
+}
+
+#[query]
+fn resolve_sui_address(address: String) -> Result<PublicProfile, WalletLinkError> {
+    let caller = account_caller();
+    with_state(|s| vtk_backend::api::resolve_sui_address(caller, address, s))
+}
+
 // === VetKey Methods ===
 // Note: vetkd_public_key is already defined in the vetkd module
 
@@ -214,4 +600,9 @@ async fn vetkd_encrypted_key(encryption_public_key: Vec<u8>, file_id: Option<u64
     vtk_backend::vetkd::vetkd_encrypted_key(encryption_public_key, file_id).await
 }
 
+#[ic_cdk::update]
+async fn vetkd_encrypted_group_key(encryption_public_key: Vec<u8>, group_id: u64) -> Result<Vec<u8>, String> {
+    vtk_backend::vetkd::vetkd_encrypted_group_key(encryption_public_key, group_id).await
+}
+
 fn main() {}
\ No newline at end of file
@@ -0,0 +1,71 @@
+use crate::with_state_mut;
+use ic_cdk::api::management_canister::main::raw_rand;
+use std::time::Duration;
+
+/// How often the state RNG is reseeded with fresh entropy from `raw_rand`.
+pub const RESEED_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long to wait before asking `raw_rand` again after it failed.
+pub const RESEED_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Fetches 32 bytes from the management canister's `raw_rand` and reseeds the state RNG.
+///
+/// On failure the call is retried after `RESEED_RETRY_DELAY`: until the first seed arrives,
+/// every endpoint that needs randomness traps.
+pub async fn reseed_rng() {
+    let seed = match raw_rand().await {
+        Ok((bytes,)) => <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| format!("raw_rand returned {} bytes, expected 32", bytes.len())),
+        Err((code, msg)) => Err(format!("raw_rand failed: {:?} {}", code, msg)),
+    };
+    match seed {
+        Ok(seed) => with_state_mut(|s| s.reseed(seed)),
+        Err(err) => {
+            ic_cdk::println!("{}", err);
+            ic_cdk_timers::set_timer(RESEED_RETRY_DELAY, || ic_cdk::spawn(reseed_rng()));
+        }
+    }
+}
+
+/// Seeds the RNG as soon as possible and keeps reseeding it periodically.
+///
+/// Must be called from `init` and `post_upgrade`: inter-canister calls are not
+/// allowed there, so the first seed is fetched from a zero-delay timer.
+pub fn schedule_reseeding() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(reseed_rng()));
+    ic_cdk_timers::set_timer_interval(RESEED_INTERVAL, || ic_cdk::spawn(reseed_rng()));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::State;
+
+    #[test]
+    fn same_seed_gives_same_tokens() {
+        let mut a = State::new(&[7; 32]);
+        let mut b = State::new(&[7; 32]);
+        assert_eq!(a.generate_token(), b.generate_token());
+        assert_ne!(a.generate_token(), a.generate_token());
+    }
+
+    #[test]
+    fn reseeding_changes_the_stream() {
+        let mut a = State::new(&[7; 32]);
+        let mut b = State::new(&[7; 32]);
+        b.reseed([8; 32]);
+        assert_ne!(a.random_bytes::<32>(), b.random_bytes::<32>());
+    }
+
+    #[test]
+    fn token_and_nonce_lengths() {
+        let mut state = State::seeded();
+        assert_eq!(state.generate_token().len(), 64);
+        assert_eq!(state.generate_nonce().len(), 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "randomness has not been seeded yet")]
+    fn unseeded_state_refuses_to_produce_randomness() {
+        let mut state = State::default();
+        state.generate_token();
+    }
+}
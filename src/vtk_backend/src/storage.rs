@@ -0,0 +1,201 @@
+use crate::{File, FileContent, FileData, FileDownloadResponse, State, StorageProvider};
+use candid::Principal;
+
+/// Length of a Walrus blob ID: 32 bytes, URL-safe base64 without padding.
+const WALRUS_BLOB_ID_LEN: usize = 43;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StorageError {
+    /// The content is stored outside the canister, so chunks cannot be written to it.
+    ExternalContent,
+}
+
+/// Where the content of a file lives, and how to reach it.
+pub trait StorageBackend {
+    /// Stores one chunk of the content kept under `storage_id`, charged to `owner`.
+    fn put(
+        &self,
+        state: &mut State,
+        owner: Principal,
+        storage_id: u64,
+        chunk_id: u64,
+        contents: Vec<u8>,
+    ) -> Result<(), StorageError>;
+
+    /// Returns one chunk of a file, or where clients can fetch its content from.
+    fn get(&self, state: &State, file: &File, file_id: u64, chunk_id: u64) -> FileDownloadResponse;
+
+    /// Drops a file's reference to its content, deleting it once no other file uses it.
+    fn delete(&self, state: &mut State, file_id: u64, num_chunks: u64);
+
+    /// Checks that the content of an uploaded file is where its metadata says it is.
+    fn verify(&self, state: &State, file: &File, file_id: u64) -> bool;
+}
+
+/// Chunks stored in the canister's stable memory.
+pub struct IcpStorage;
+
+/// Content stored on Walrus. The canister only keeps the blob ID; clients fetch the blob from
+/// an aggregator and the blob expires there at the end of its storage period.
+pub struct WalrusStorage;
+
+impl StorageProvider {
+    pub fn backend(self) -> &'static dyn StorageBackend {
+        match self {
+            StorageProvider::Icp => &IcpStorage,
+            StorageProvider::Walrus => &WalrusStorage,
+        }
+    }
+}
+
+impl StorageBackend for IcpStorage {
+    fn put(
+        &self,
+        state: &mut State,
+        owner: Principal,
+        storage_id: u64,
+        chunk_id: u64,
+        contents: Vec<u8>,
+    ) -> Result<(), StorageError> {
+        state.write_chunk(owner, storage_id, chunk_id, contents);
+        Ok(())
+    }
+
+    fn get(&self, state: &State, file: &File, file_id: u64, chunk_id: u64) -> FileDownloadResponse {
+        match &file.content {
+            FileContent::Uploaded { file_type, num_chunks, .. } => {
+                match state.file_contents.get(&(state.storage_id(file_id), chunk_id)) {
+                    Some(contents) => FileDownloadResponse::FoundFile(FileData {
+                        contents,
+                        file_type: file_type.clone(),
+                        num_chunks: *num_chunks,
+                    }),
+                    None => FileDownloadResponse::NotFoundFile,
+                }
+            }
+            FileContent::Consumed { .. } => FileDownloadResponse::NotFoundFile,
+            _ => FileDownloadResponse::NotUploadedFile,
+        }
+    }
+
+    fn delete(&self, state: &mut State, file_id: u64, num_chunks: u64) {
+        state.release_chunks(file_id, num_chunks);
+    }
+
+    fn verify(&self, state: &State, file: &File, file_id: u64) -> bool {
+        let FileContent::Uploaded { num_chunks, .. } = file.content else {
+            return true;
+        };
+        let storage_id = state.storage_id(file_id);
+        (0..num_chunks).all(|chunk_id| state.file_contents.contains_key(&(storage_id, chunk_id)))
+    }
+}
+
+impl StorageBackend for WalrusStorage {
+    fn put(&self, _: &mut State, _: Principal, _: u64, _: u64, _: Vec<u8>) -> Result<(), StorageError> {
+        Err(StorageError::ExternalContent)
+    }
+
+    fn get(&self, _: &State, file: &File, _: u64, _: u64) -> FileDownloadResponse {
+        match (&file.content, &file.metadata.blob_id) {
+            (FileContent::Uploaded { .. }, Some(blob_id)) => FileDownloadResponse::WalrusBlob(blob_id.clone()),
+            (FileContent::Consumed { .. }, _) => FileDownloadResponse::NotFoundFile,
+            _ => FileDownloadResponse::NotUploadedFile,
+        }
+    }
+
+    fn delete(&self, state: &mut State, file_id: u64, _: u64) {
+        // Nothing is stored in the canister, only the reference is dropped.
+        state.release_chunks(file_id, 0);
+    }
+
+    fn verify(&self, _: &State, file: &File, _: u64) -> bool {
+        // The canister cannot reach Walrus, so neither the blob nor who owns it is checked.
+        file.metadata.blob_id.as_deref().is_some_and(is_valid_blob_id)
+    }
+}
+
+pub fn is_valid_blob_id(blob_id: &str) -> bool {
+    blob_id.len() == WALRUS_BLOB_ID_LEN
+        && blob_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        check_storage_usage, delete_file, download_file, empty_trash, register_file, upload_file_atomic,
+        RegisterFileRequest, UploadFileAtomicRequest,
+    };
+    use crate::UploadFileError;
+
+    const BLOB_ID: &str = "M4hsZGQ1oCktdzegB6HnI6Mi28S2nqOPHxK-W7_4BUk";
+
+    fn owner() -> Principal {
+        Principal::from_text("ivwno-rqaae-bagba-faydq-qci").unwrap()
+    }
+
+    fn register(state: &mut State, provider: StorageProvider, blob_id: Option<&str>) -> Result<u64, UploadFileError> {
+        let request = RegisterFileRequest {
+            file_name: "video.mp4".to_string(),
+            storage_provider: provider,
+            blob_id: blob_id.map(str::to_string),
+            requested_at: 0,
+            uploaded_at: None,
+            size: Some(1 << 20),
+        };
+        register_file(owner(), request, state).map(|response| response.file_id)
+    }
+
+    #[test]
+    fn walrus_files_are_served_from_walrus() {
+        let mut state = State::seeded();
+        let file_id = register(&mut state, StorageProvider::Walrus, Some(BLOB_ID)).unwrap();
+        assert_eq!(download_file(&state, owner(), file_id, 0), FileDownloadResponse::WalrusBlob(BLOB_ID.to_string()));
+        // The blob takes no space in the canister.
+        assert_eq!(state.total_storage_used, 0);
+        assert!(state.upload_reservations.is_empty());
+
+        let file = &state.file_data[&file_id];
+        assert!(StorageProvider::Walrus.backend().verify(&state, file, file_id));
+        assert_eq!(
+            StorageProvider::Walrus.backend().put(&mut state, owner(), file_id, 0, vec![1]),
+            Err(StorageError::ExternalContent)
+        );
+        assert!(check_storage_usage(false, &mut state).unverified_files.is_empty());
+
+        delete_file(&mut state, owner(), file_id);
+        empty_trash(owner(), &mut state).unwrap();
+        assert_eq!(download_file(&state, owner(), file_id, 0), FileDownloadResponse::NotFoundFile);
+    }
+
+    #[test]
+    fn blob_ids_are_validated() {
+        let mut state = State::seeded();
+        assert_eq!(register(&mut state, StorageProvider::Walrus, None), Err(UploadFileError::InvalidInput));
+        assert_eq!(register(&mut state, StorageProvider::Walrus, Some("blob")), Err(UploadFileError::InvalidInput));
+        assert_eq!(register(&mut state, StorageProvider::Icp, Some(BLOB_ID)), Err(UploadFileError::InvalidInput));
+        assert!(register(&mut state, StorageProvider::Icp, None).is_ok());
+    }
+
+    #[test]
+    fn icp_files_are_verified_against_their_chunks() {
+        let mut state = State::seeded();
+        let request = UploadFileAtomicRequest {
+            name: "a.txt".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "txt".to_string(),
+            num_chunks: 1,
+            burn_after_reading: None,
+            encrypted_metadata: None,
+            total_size: None,
+            group_id: None,
+        };
+        let file_id = upload_file_atomic(owner(), request, &mut state).unwrap();
+        let file = state.file_data[&file_id].clone();
+        assert!(IcpStorage.verify(&state, &file, file_id));
+        state.file_contents.remove(&(file_id, 0));
+        assert!(!IcpStorage.verify(&state, &file, file_id));
+        assert_eq!(check_storage_usage(false, &mut state).unverified_files, vec![file_id]);
+    }
+}
@@ -3,4 +3,4 @@ pub mod vetkd_encrypted_key;
 
 // Re-export controller methods
 pub use vetkd_public_key::vetkd_public_key;
-pub use vetkd_encrypted_key::vetkd_encrypted_key; 
\ No newline at end of file
+pub use vetkd_encrypted_key::{vetkd_encrypted_group_key, vetkd_encrypted_key}; 
\ No newline at end of file
@@ -1,6 +1,7 @@
 use crate::declarations::vetkd_system_api::{
     VETKD_SYSTEM_API, VetkdCurve, VetkdDeriveEncryptedKeyArgs, VetkdDeriveEncryptedKeyArgsKeyId,
 };
+use crate::api::{group_upload_derivation_id, vetkey_derivation_id};
 use crate::with_state;
 use serde_bytes::ByteBuf;
 
@@ -9,23 +10,26 @@ pub async fn vetkd_encrypted_key(
     encryption_public_key: Vec<u8>,
     file_id: Option<u64>,
 ) -> Result<Vec<u8>, String> {
-    // Determine derivation ID based on file ownership
+    // Linked principals derive the keys of the account they act as.
+    let caller = with_state(|state| state.account_of(ic_cdk::api::caller()));
+    // Files uploaded into a group are encrypted under a version of the group's key
     let derivation_id = if let Some(id) = file_id {
-        // Use file owner's principal for shared files
-        let principal = with_state(|state| {
-            state
-                .file_data
-                .get(&id)
-                .map(|file| file.metadata.requester_principal.as_slice().to_vec())
-                .ok_or_else(|| "File not found".to_string())
-        })?;
-        principal
+        with_state(|state| vetkey_derivation_id(state, caller, id))?
     } else {
         // Use caller's principal for own files
-        let caller = ic_cdk::api::caller().as_slice().to_vec();
-        caller
+        caller.as_slice().to_vec()
     };
+    derive_encrypted_key(encryption_public_key, derivation_id).await
+}
 
+// The current key of a group, for editors encrypting a file they upload into it
+pub async fn vetkd_encrypted_group_key(encryption_public_key: Vec<u8>, group_id: u64) -> Result<Vec<u8>, String> {
+    let caller = with_state(|state| state.account_of(ic_cdk::api::caller()));
+    let derivation_id = with_state(|state| group_upload_derivation_id(state, caller, group_id))?;
+    derive_encrypted_key(encryption_public_key, derivation_id).await
+}
+
+async fn derive_encrypted_key(encryption_public_key: Vec<u8>, derivation_id: Vec<u8>) -> Result<Vec<u8>, String> {
     let args = VetkdDeriveEncryptedKeyArgs {
         key_id: VetkdDeriveEncryptedKeyArgsKeyId {
             name: "insecure_test_key_1".to_string(),
@@ -2,4 +2,4 @@ pub mod controller;
 
 // Re-export VetKey methods for easy access
 pub use controller::vetkd_public_key;
-pub use controller::vetkd_encrypted_key; 
\ No newline at end of file
+pub use controller::{vetkd_encrypted_group_key, vetkd_encrypted_key}; 
\ No newline at end of file
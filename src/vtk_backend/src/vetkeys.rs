@@ -164,7 +164,6 @@ impl VetKeyService {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use candid::Principal;
 
     #[test]
     fn test_xor_encryption() {